@@ -0,0 +1,383 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Auditing the descriptors that a process takes into capability mode.
+//!
+//! Capability mode only confines a process to its descriptors, so a
+//! descriptor that nobody remembered to limit, like an inherited log file, is
+//! a hole in the sandbox.  [`open_descriptors`] lists every descriptor that
+//! the process holds, what it refers to, and its limits.
+//!
+//! # Example
+//! ```
+//! use capsicum::audit;
+//!
+//! for descriptor in audit::open_descriptors().unwrap() {
+//!     if descriptor.is_unlimited() {
+//!         println!(
+//!             "fd {} ({}) has all rights: {:?}",
+//!             descriptor.fd, descriptor.kind, descriptor.path
+//!         );
+//!     }
+//! }
+//! ```
+
+use std::{
+    fmt,
+    fs,
+    io,
+    mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
+    os::{
+        fd::{AsRawFd, BorrowedFd, RawFd},
+        raw::c_int,
+        unix::fs::MetadataExt,
+    },
+    path::PathBuf,
+};
+
+use crate::FdLimits;
+
+/// What a descriptor refers to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum DescriptorType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Directory,
+    /// A socket.
+    Socket,
+    /// An anonymous pipe.
+    Pipe,
+    /// A named pipe.
+    Fifo,
+    /// A character or block device, including terminals.
+    Device,
+    /// A kqueue.  On Linux, an epoll instance.
+    Kqueue,
+    /// A process descriptor.  On Linux, a pidfd.
+    ProcessDescriptor,
+    /// Anything else, like shared memory or an eventfd.
+    Other,
+}
+
+impl fmt::Display for DescriptorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DescriptorType::File => "file",
+            DescriptorType::Directory => "directory",
+            DescriptorType::Socket => "socket",
+            DescriptorType::Pipe => "pipe",
+            DescriptorType::Fifo => "fifo",
+            DescriptorType::Device => "device",
+            DescriptorType::Kqueue => "kqueue",
+            DescriptorType::ProcessDescriptor => "process descriptor",
+            DescriptorType::Other => "other",
+        })
+    }
+}
+
+/// An open descriptor, as found by [`open_descriptors`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Descriptor {
+    /// The descriptor number.
+    pub fd: RawFd,
+    /// What the descriptor refers to.
+    pub kind: DescriptorType,
+    /// The path of the file, directory, fifo or device, if known.  If it has
+    /// been removed since it was opened, the path it had.
+    pub path: Option<PathBuf>,
+    /// The local address of a socket, like `127.0.0.1:80`, or the path of a
+    /// Unix-domain socket, if it has one.
+    pub address: Option<String>,
+    /// The file status flags, like `O_RDWR | O_APPEND`, if known.
+    pub flags: Option<c_int>,
+    /// The descriptor's limits, if they could be retrieved.
+    pub limits: Option<FdLimits>,
+}
+
+impl Descriptor {
+    /// Does the descriptor still hold every capability right?
+    pub fn is_unlimited(&self) -> bool {
+        self.limits
+            .as_ref()
+            .is_some_and(|limits| limits.rights.is_unlimited())
+    }
+}
+
+/// What the OS's list of descriptors says about one of them.
+#[derive(Default)]
+struct Listed {
+    fd: RawFd,
+    kind: Option<DescriptorType>,
+    path: Option<PathBuf>,
+    flags: Option<c_int>,
+}
+
+/// List every descriptor that the process holds.
+///
+/// The list comes from `/proc/self/fd` and `/proc/self/fdinfo` on Linux, and
+/// from the `kern.proc.filedesc` sysctl on FreeBSD.  Where those are out of
+/// reach, like in capability mode, every descriptor number up to the
+/// process's limit is probed instead, and paths are unknown.
+///
+/// # Errors
+///
+/// Only if the descriptors can neither be listed nor probed.
+pub fn open_descriptors() -> io::Result<Vec<Descriptor>> {
+    let listed = match list() {
+        Ok(listed) => listed,
+        Err(_) => probe()?,
+    };
+    Ok(listed
+        .into_iter()
+        // Drop any that have since been closed, like the one that listed them.
+        .filter(|l| unsafe { libc::fcntl(l.fd, libc::F_GETFD) } >= 0)
+        .map(|l| {
+            let fd = unsafe { BorrowedFd::borrow_raw(l.fd) };
+            // Without CAP_FSTAT, the type can still be told from the path.
+            let kind = l
+                .kind
+                .or_else(|| stat_type(fd))
+                .or_else(|| {
+                    let mode = fs::metadata(l.path.as_ref()?).ok()?.mode();
+                    Some(mode_type(mode as libc::mode_t))
+                })
+                .unwrap_or(DescriptorType::Other);
+            Descriptor {
+                fd: l.fd,
+                kind,
+                path: l.path,
+                address: (kind == DescriptorType::Socket)
+                    .then(|| address(fd))
+                    .flatten(),
+                flags: l.flags.or_else(|| flags(fd)),
+                limits: FdLimits::from_file(&fd).ok(),
+            }
+        })
+        .collect())
+}
+
+/// What `fd` refers to, as far as can be told without listing it.
+#[cfg(feature = "tracing")]
+pub(crate) fn descriptor_type(fd: BorrowedFd<'_>) -> DescriptorType {
+    #[cfg(target_os = "linux")]
+    if let Some(kind) = link(fd.as_raw_fd()).and_then(|link| link_type(&link)) {
+        return kind;
+    }
+    stat_type(fd).unwrap_or(DescriptorType::Other)
+}
+
+fn stat_type(fd: BorrowedFd<'_>) -> Option<DescriptorType> {
+    let mut sb = mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd.as_raw_fd(), sb.as_mut_ptr()) } < 0 {
+        return None;
+    }
+    Some(mode_type(unsafe { sb.assume_init() }.st_mode))
+}
+
+fn mode_type(mode: libc::mode_t) -> DescriptorType {
+    match mode & libc::S_IFMT {
+        libc::S_IFREG => DescriptorType::File,
+        libc::S_IFDIR => DescriptorType::Directory,
+        libc::S_IFSOCK => DescriptorType::Socket,
+        libc::S_IFIFO => DescriptorType::Fifo,
+        libc::S_IFCHR | libc::S_IFBLK => DescriptorType::Device,
+        _ => DescriptorType::Other,
+    }
+}
+
+fn flags(fd: BorrowedFd<'_>) -> Option<c_int> {
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    (flags >= 0).then_some(flags)
+}
+
+/// The local address of a socket.
+fn address(fd: BorrowedFd<'_>) -> Option<String> {
+    let mut ss = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+    let mut len = mem::size_of_val(&ss) as libc::socklen_t;
+    let sa = &mut ss as *mut libc::sockaddr_storage;
+    if unsafe { libc::getsockname(fd.as_raw_fd(), sa.cast(), &mut len) } < 0 {
+        return None;
+    }
+    match c_int::from(ss.ss_family) {
+        libc::AF_INET => {
+            let sin = unsafe { &*sa.cast::<libc::sockaddr_in>() };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).to_string())
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*sa.cast::<libc::sockaddr_in6>() };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            let port = u16::from_be(sin6.sin6_port);
+            let addr = SocketAddrV6::new(ip, port, sin6.sin6_flowinfo, sin6.sin6_scope_id);
+            Some(addr.to_string())
+        }
+        libc::AF_UNIX => {
+            let sun = unsafe { &*sa.cast::<libc::sockaddr_un>() };
+            let offset = mem::offset_of!(libc::sockaddr_un, sun_path);
+            let path = sun.sun_path[..(len as usize).saturating_sub(offset)]
+                .iter()
+                .take_while(|c| **c != 0)
+                .map(|c| *c as u8)
+                .collect::<Vec<_>>();
+            // Unnamed sockets, and Linux's abstract ones, have no path.
+            (!path.is_empty()).then(|| String::from_utf8_lossy(&path).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Every descriptor number that the process may use, up to a point.
+fn probe() -> io::Result<Vec<Listed>> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let max = rlim.rlim_cur.min(65536) as RawFd;
+    Ok((0..max)
+        .map(|fd| Listed {
+            fd,
+            ..Default::default()
+        })
+        .collect())
+}
+
+/// The target of a descriptor's `/proc/self/fd` link.
+///
+/// If the file has been removed, the kernel appends ` (deleted)` to its
+/// path.  That's stripped, unless the file still has a link, so that its
+/// name really ends like that.
+#[cfg(target_os = "linux")]
+fn link(fd: RawFd) -> Option<PathBuf> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let link = fs::read_link(format!("/proc/self/fd/{fd}")).ok()?;
+    let Some(path) = link.as_os_str().as_bytes().strip_suffix(b" (deleted)") else {
+        return Some(link);
+    };
+    let mut sb = mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd, sb.as_mut_ptr()) } < 0 || unsafe { sb.assume_init() }.st_nlink > 0 {
+        return Some(link);
+    }
+    Some(OsStr::from_bytes(path).into())
+}
+
+/// What a `/proc/self/fd` link says about the type of its descriptor.
+#[cfg(target_os = "linux")]
+fn link_type(link: &std::path::Path) -> Option<DescriptorType> {
+    let link = link.to_str()?;
+    if link.starts_with("pipe:") {
+        Some(DescriptorType::Pipe)
+    } else if link.starts_with("socket:") {
+        Some(DescriptorType::Socket)
+    } else if link == "anon_inode:[pidfd]" || link.starts_with("pidfd:") {
+        Some(DescriptorType::ProcessDescriptor)
+    } else if link == "anon_inode:[eventpoll]" {
+        Some(DescriptorType::Kqueue)
+    } else if link.starts_with("anon_inode:") {
+        Some(DescriptorType::Other)
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn list() -> io::Result<Vec<Listed>> {
+    let mut fds = fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect::<Vec<RawFd>>();
+    fds.sort_unstable();
+    Ok(fds
+        .into_iter()
+        .map(|fd| {
+            let link = link(fd);
+            let kind = link.as_deref().and_then(link_type);
+            // fdinfo's flags are in octal.
+            let flags = fs::read_to_string(format!("/proc/self/fdinfo/{fd}"))
+                .ok()
+                .and_then(|info| {
+                    let flags = info.lines().find_map(|l| l.strip_prefix("flags:"))?;
+                    c_int::from_str_radix(flags.trim(), 8).ok()
+                });
+            Listed {
+                fd,
+                kind,
+                path: link
+                    .filter(|_| kind.is_none())
+                    .filter(|link| link.is_absolute()),
+                flags,
+            }
+        })
+        .collect())
+}
+
+#[cfg(target_os = "freebsd")]
+fn list() -> io::Result<Vec<Listed>> {
+    use std::{ffi::CStr, os::unix::ffi::OsStrExt, ptr};
+
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_FILEDESC,
+        unsafe { libc::getpid() },
+    ];
+    let sysctl = |buf: *mut libc::c_void, len: &mut usize| match unsafe {
+        libc::sysctl(mib.as_ptr(), 4, buf, len, ptr::null(), 0)
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    };
+    let mut len = 0;
+    sysctl(ptr::null_mut(), &mut len)?;
+    // Leave room for descriptors opened in the meantime.
+    len = len * 4 / 3;
+    let mut buf = vec![0u8; len];
+    sysctl(buf.as_mut_ptr().cast(), &mut len)?;
+    buf.truncate(len);
+
+    // The records are kinfo_files, each truncated to the length of its path.
+    let mut listed = Vec::new();
+    let mut rest = &buf[..];
+    while rest.len() >= mem::size_of::<c_int>() {
+        let size = c_int::from_ne_bytes(rest[..4].try_into().unwrap());
+        let Ok(size @ 1..) = usize::try_from(size) else {
+            break;
+        };
+        let mut kif = unsafe { mem::zeroed::<libc::kinfo_file>() };
+        let n = size.min(rest.len()).min(mem::size_of_val(&kif));
+        unsafe {
+            ptr::copy_nonoverlapping(rest.as_ptr(), (&mut kif as *mut libc::kinfo_file).cast(), n)
+        };
+        rest = &rest[size.min(rest.len())..];
+        // Negative descriptor numbers are for the cwd, root, and so on.
+        if kif.kf_fd < 0 {
+            continue;
+        }
+        let kind = match kif.kf_type {
+            libc::KF_TYPE_SOCKET => Some(DescriptorType::Socket),
+            libc::KF_TYPE_PIPE => Some(DescriptorType::Pipe),
+            libc::KF_TYPE_FIFO => Some(DescriptorType::Fifo),
+            libc::KF_TYPE_KQUEUE => Some(DescriptorType::Kqueue),
+            libc::KF_TYPE_PROCDESC => Some(DescriptorType::ProcessDescriptor),
+            libc::KF_TYPE_PTS | libc::KF_TYPE_DEV => Some(DescriptorType::Device),
+            libc::KF_TYPE_VNODE => None,
+            _ => Some(DescriptorType::Other),
+        };
+        let path = unsafe { CStr::from_ptr(kif.kf_path.as_ptr()) }.to_bytes();
+        listed.push(Listed {
+            fd: kif.kf_fd,
+            kind,
+            path: (!path.is_empty()).then(|| std::ffi::OsStr::from_bytes(path).into()),
+            flags: None,
+        });
+    }
+    listed.sort_unstable_by_key(|l| l.fd);
+    Ok(listed)
+}
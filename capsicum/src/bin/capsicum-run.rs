@@ -0,0 +1,166 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Run a program under a declared sandbox policy.
+//!
+//! ```text
+//! capsicum-run [--first-fd N] POLICY PROGRAM [ARG...]
+//! ```
+//!
+//! `capsicum-run` loads the [policy](capsicum::policy) file `POLICY`, opens
+//! `PROGRAM`, which is looked up in `PATH` if it contains no slash, and
+//! executes it as a [`SandboxedCommand`](capsicum::process::SandboxedCommand)
+//! would.  The program inherits the policy's preopens and the standard
+//! streams, each with its limits, and enters capability mode before it
+//! starts, unless the policy says not to.
+//!
+//! The preopens are inherited at consecutive descriptor numbers, from 3 or
+//! from `N`, in the order they're declared.  Their names and numbers are
+//! also listed in the `CAPSICUM_FDS` environment variable, like
+//! `CAPSICUM_FDS=data=3,log=4`.
+//!
+//! In capability mode, `PROGRAM` can't open anything by path, not even its
+//! shared libraries.  On Linux it must be statically linked.  On FreeBSD, a
+//! dynamically linked program is run by its runtime linker, which is given
+//! `/lib` and `/usr/lib` to find its libraries in.
+//!
+//! On Linux, limits are only enforced in capability mode, and they're lost
+//! when `PROGRAM` is executed.  So a policy that doesn't enter capability
+//! mode is refused there.  A policy with Casper services is refused
+//! everywhere, since their channels can't be passed on to `PROGRAM`.
+//!
+//! Like `env(1)`, `capsicum-run` exits with status 125 if it fails itself,
+//! 126 if `PROGRAM` can't be executed, and 127 if it can't be found.
+
+use std::{
+    env,
+    ffi::{CString, OsStr, OsString},
+    io,
+    os::{fd::RawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use capsicum::{policy::Policy, process::Executable};
+
+/// The environment variable that lists the inherited preopens.
+const FDS_VAR: &str = "CAPSICUM_FDS";
+
+const USAGE: &str = "usage: capsicum-run [--first-fd N] POLICY PROGRAM [ARG...]";
+
+/// A failure, and the status to exit with.
+struct Failure(u8, String);
+
+impl Failure {
+    fn usage() -> Failure {
+        Failure(125, USAGE.to_owned())
+    }
+}
+
+fn main() -> ExitCode {
+    match run(env::args_os().skip(1).collect()) {
+        Ok(never) => match never {},
+        Err(Failure(status, msg)) => {
+            eprintln!("capsicum-run: {msg}");
+            ExitCode::from(status)
+        }
+    }
+}
+
+fn run(mut args: Vec<OsString>) -> Result<std::convert::Infallible, Failure> {
+    let mut first: RawFd = 3;
+    if args.first().is_some_and(|arg| arg == "--first-fd") {
+        first = args
+            .get(1)
+            .and_then(|n| n.to_str()?.parse().ok())
+            .filter(|&n| n > 2)
+            .ok_or_else(Failure::usage)?;
+        args.drain(..2);
+    }
+    if args.len() < 2 {
+        return Err(Failure::usage());
+    }
+    let argv = args.split_off(1);
+    let policy_path = PathBuf::from(args.remove(0));
+
+    let policy = Policy::load(&policy_path).map_err(|e| Failure(125, e.to_string()))?;
+    if cfg!(target_os = "linux") && !policy.enters() {
+        return Err(Failure(
+            125,
+            format!(
+                "{}: on Linux, limits are only enforced in capability mode, which the policy \
+                 doesn't enter",
+                policy_path.display()
+            ),
+        ));
+    }
+    if let Some(service) = policy.casper_services().next() {
+        return Err(Failure(
+            125,
+            format!(
+                "{}: Casper services, like {service}, can't be passed on to the program",
+                policy_path.display()
+            ),
+        ));
+    }
+    let program = &argv[0];
+    let path = find(program)
+        .ok_or_else(|| Failure(127, format!("{}: not found", Path::new(program).display())))?;
+    let cant_exec = |e: io::Error| Failure(126, format!("{}: {e}", path.display()));
+    let exe = Executable::open(&path).map_err(cant_exec)?;
+    #[cfg(target_os = "freebsd")]
+    let exe = library_dirs(exe).map_err(|e| Failure(125, e.to_string()))?;
+
+    let fds = policy
+        .preopen_names()
+        .zip(first..)
+        .map(|(name, fd)| format!("{name}={fd}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let command = policy
+        .command(exe, first)
+        .map_err(|e| Failure(125, format!("{}: {e}", policy_path.display())))?;
+    Err(cant_exec(command.args(&argv[1..]).env(FDS_VAR, fds).exec()))
+}
+
+/// Find `program` in `PATH`, unless it names a path.  Like `execvp(3)`, the
+/// search skips files that aren't executable.
+fn find(program: &OsStr) -> Option<PathBuf> {
+    if program.as_bytes().contains(&b'/') {
+        return Some(PathBuf::from(program));
+    }
+    let path = env::var_os("PATH").unwrap_or_else(|| "/bin:/usr/bin".into());
+    env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| executable(candidate))
+}
+
+fn executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    metadata.is_file() && unsafe { libc::access(path.as_ptr(), libc::X_OK) } == 0
+}
+
+/// Give the runtime linker the system's library directories.
+#[cfg(target_os = "freebsd")]
+fn library_dirs(mut exe: Executable) -> io::Result<Executable> {
+    use capsicum::{CapRights, FileRights, Right};
+
+    let mut rights = FileRights::new();
+    rights
+        .allow(Right::Lookup)
+        .allow(Right::Read)
+        .allow(Right::Fstat)
+        .allow(Right::MmapRX);
+    for dir in ["/lib", "/usr/lib"] {
+        let dir = std::fs::File::open(dir)?;
+        rights.limit(&dir)?;
+        exe = exe.library_dir(dir);
+    }
+    Ok(exe)
+}
@@ -14,7 +14,12 @@
 //!
 //! # See Also
 //! * [libcasper(3)](https://www.freebsd.org/cgi/man.cgi?query=libcasper)
-use std::{ffi::CStr, io, ptr};
+use std::{
+    ffi::{CStr, CString},
+    io,
+    ptr,
+    sync::Mutex,
+};
 
 // Reexport these symbols, consumer crates don't need to directly depend on the
 // libnv and libnv-sys crates.
@@ -23,12 +28,27 @@ pub use libnv::{
     NvError,
 };
 
+/// The names of the services opened so far, in order.
+static OPENED: Mutex<Vec<CString>> = Mutex::new(Vec::new());
+
+/// The names of the Casper services that this process has opened.
+#[cfg(feature = "manifest")]
+pub(crate) fn opened_services() -> Vec<String> {
+    let opened = OPENED.lock().unwrap_or_else(|e| e.into_inner());
+    opened
+        .iter()
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect()
+}
+
 /// Low-level stuff that we must reexport because it gets used in the macros.
 #[doc(hidden)]
 pub mod sys {
     pub use casper_sys::{cap_limit_get, cap_limit_set, cap_xfer_nvlist, service_register};
     pub use ctor::ctor;
     pub use libnv_sys::nvlist_t;
+
+    pub use crate::trace::casper_span;
 }
 
 /// ORable flags for use with [`service!`].
@@ -118,8 +138,27 @@ impl Casper {
     // thread-safe.
     #[doc(hidden)]
     pub fn service_open(&mut self, name: &CStr) -> io::Result<CapChannel> {
+        let _span = sys::casper_span("service_open", name);
         let chan = unsafe { casper_sys::cap_service_open(self.0.as_ptr(), name.as_ptr()) };
-        CapChannel::from_raw_ptr(chan).ok_or(io::Error::last_os_error())
+        let chan = CapChannel::from_raw_ptr(chan).ok_or(io::Error::last_os_error())?;
+        let mut opened = OPENED.lock().unwrap_or_else(|e| e.into_inner());
+        if !opened.iter().any(|n| n.as_c_str() == name) {
+            opened.push(name.to_owned());
+        }
+        Ok(chan)
+    }
+
+    /// Forbid opening any service not named in `names`.
+    #[cfg(feature = "policy")]
+    pub(crate) fn limit_services(&mut self, names: &[CString]) -> io::Result<()> {
+        let names = names.iter().map(|name| name.as_ptr()).collect::<Vec<_>>();
+        let r =
+            unsafe { casper_sys::cap_service_limit(self.0.as_ptr(), names.as_ptr(), names.len()) };
+        if r < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
     }
 
     /// Clone the handle to the Casper process.
@@ -209,6 +248,7 @@ mod macros {
 
                 /// Transfer a command to the service and await a response.
                 fn xfer_nvlist(&mut self, invl: $crate::casper::NvList) -> ::std::io::Result<$crate::casper::NvList> {
+                    let _span = $crate::casper::sys::casper_span("xfer_nvlist", $cname);
                     let r = unsafe {
                         $crate::casper::sys::cap_xfer_nvlist(self.0.as_ptr(), invl.into())
                     };
@@ -10,5 +10,8 @@ pub trait CapRights: Sized {
     ///
     /// When a file descriptor is first created, it is assigned all possible capability rights.
     /// Those rights may be reduced (but never expanded), by this method.
+    ///
+    /// On Linux, in capability mode, each call that changes a descriptor's limits installs
+    /// another seccomp filter.  After 32 of those, it fails with `ENOMEM`.
     fn limit<F: AsFd>(&self, f: &F) -> io::Result<()>;
 }
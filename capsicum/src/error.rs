@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    error::Error,
+    fmt,
+    io,
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+};
+
+#[cfg(target_os = "linux")]
+use crate::process::sandboxed;
+use crate::{operations::OPERATIONS, FileRights};
+
+/// The rights that the operation `op` needs from its descriptor, if known.
+///
+/// An operation on several descriptors, like `renameat`, is taken to be on
+/// the first.
+fn required(op: &str) -> Option<FileRights> {
+    let ops = OPERATIONS.iter().filter(|o| o.name == op);
+    let arg = ops.clone().map(|o| o.arg).min()?;
+    let mut required = FileRights::new();
+    for o in ops.filter(|o| o.arg == arg && !o.flags) {
+        required.allow(o.right);
+    }
+    Some(required)
+}
+
+/// Why an operation was denied, as determined by [`CapError::new`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum CapErrorKind {
+    /// Capability mode forbids the operation, because it uses a global
+    /// namespace, like the file system or the process ID space.  This is
+    /// `ECAPMODE` on FreeBSD.
+    CapabilityMode,
+    /// The descriptor's limits forbid the operation.  This is `ENOTCAPABLE`
+    /// on FreeBSD.
+    NotCapable,
+    /// The operation failed for some other reason.
+    Other,
+}
+
+/// An error from an operation that capability mode, or a descriptor's
+/// rights, may have denied, with a message that says which.
+///
+/// A bare `ENOTCAPABLE` says that some right was missing, but not which.
+/// `CapError` looks up the rights that the operation needs, and the rights
+/// that the descriptor holds, and names the difference.
+///
+/// It must be created while the descriptor is still open.
+///
+/// # Example
+/// ```
+/// # use std::{io::Write, os::fd::{AsFd, AsRawFd}};
+/// # use capsicum::{CapError, CapErrorKind, CapRights, FileRights, Right};
+/// let mut file = tempfile::tempfile().unwrap();
+/// FileRights::new()
+///     .allow(Right::Read)
+///     .allow(Right::Seek)
+///     .limit(&file)
+///     .unwrap();
+/// capsicum::enter().unwrap();
+///
+/// let e = file.write(b"hello").unwrap_err();
+/// let e = CapError::new(e, "write", Some(file.as_fd()));
+/// assert_eq!(e.kind(), CapErrorKind::NotCapable);
+/// assert_eq!(
+///     e.to_string(),
+///     format!(
+///         "write on fd {} requires CAP_WRITE; fd has CAP_READ|CAP_SEEK",
+///         file.as_raw_fd()
+///     )
+/// );
+/// ```
+#[derive(Debug)]
+pub struct CapError {
+    error: io::Error,
+    op: String,
+    fd: Option<RawFd>,
+    kind: CapErrorKind,
+    required: Option<FileRights>,
+    held: Option<FileRights>,
+}
+
+impl CapError {
+    /// Explain `error`, which operation `op` on `fd` failed with.
+    ///
+    /// `op` should be the name of the system call, like `"write"` or
+    /// `"openat"`, so that the rights that it needs can be looked up.  Any
+    /// other description works too, but the message won't name the missing
+    /// rights.  `fd` is the descriptor that the operation used, if any.
+    pub fn new(error: io::Error, op: &str, fd: Option<BorrowedFd<'_>>) -> Self {
+        let required = fd.and(required(op));
+        let held = fd.and_then(|fd| FileRights::from_file(&fd).ok());
+        let missing = matches!((required, held), (Some(r), Some(h)) if !h.contains(&r));
+        let kind = match error.raw_os_error() {
+            // Linux reports both kinds of denial as EPERM, and only once the
+            // process is sandboxed.
+            #[cfg(target_os = "linux")]
+            Some(libc::EPERM) if sandboxed() => {
+                let limited = held.is_some_and(|h| !h.is_unlimited());
+                if missing || (limited && (required.is_none() || matches!(op, "fcntl" | "ioctl"))) {
+                    CapErrorKind::NotCapable
+                } else {
+                    CapErrorKind::CapabilityMode
+                }
+            }
+            #[cfg(target_os = "freebsd")]
+            Some(libc::ECAPMODE) => CapErrorKind::CapabilityMode,
+            #[cfg(target_os = "freebsd")]
+            Some(libc::ENOTCAPABLE) => CapErrorKind::NotCapable,
+            _ => CapErrorKind::Other,
+        };
+        CapError {
+            error,
+            op: op.to_owned(),
+            fd: fd.map(|fd| fd.as_raw_fd()),
+            kind,
+            required,
+            held,
+        }
+    }
+
+    /// Why the operation was denied.
+    pub fn kind(&self) -> CapErrorKind {
+        self.kind
+    }
+
+    /// The operation that failed.
+    pub fn operation(&self) -> &str {
+        &self.op
+    }
+
+    /// The descriptor that the operation used, if any.
+    pub fn fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+
+    /// The rights that the operation needs from its descriptor, if known.
+    pub fn required(&self) -> Option<FileRights> {
+        self.required
+    }
+
+    /// The rights that the descriptor held when the error was created, if
+    /// known.
+    pub fn held(&self) -> Option<FileRights> {
+        self.held
+    }
+
+    /// The underlying error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// Consume the `CapError`, returning the underlying error.
+    pub fn into_inner(self) -> io::Error {
+        self.error
+    }
+}
+
+/// Join the names of some rights, like `CAP_READ|CAP_SEEK`.
+fn names(rights: &FileRights) -> String {
+    match rights.names().join("|") {
+        names if names.is_empty() => "no rights".to_owned(),
+        names => names,
+    }
+}
+
+impl fmt::Display for CapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        if let Some(fd) = self.fd {
+            write!(f, " on fd {fd}")?;
+        }
+        match self.kind {
+            CapErrorKind::CapabilityMode => write!(
+                f,
+                " is not allowed in capability mode, which forbids access to global namespaces"
+            ),
+            CapErrorKind::NotCapable => {
+                match self.required {
+                    Some(r) if self.held.is_none_or(|h| !h.contains(&r)) => {
+                        write!(f, " requires {}", names(&r))?
+                    }
+                    _ => write!(f, " is not allowed by the descriptor's limits")?,
+                }
+                match self.held {
+                    Some(h) => write!(f, "; fd has {}", names(&h)),
+                    None => Ok(()),
+                }
+            }
+            CapErrorKind::Other => write!(f, ": {}", self.error),
+        }
+    }
+}
+
+impl Error for CapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<CapError> for io::Error {
+    fn from(e: CapError) -> io::Error {
+        io::Error::new(e.error.kind(), e)
+    }
+}
@@ -7,7 +7,7 @@ use std::{
     os::{fd::AsFd, unix::io::AsRawFd},
 };
 
-use crate::common::CapRights;
+use crate::{common::CapRights, sys, trace};
 
 // TODO: use values from libc
 /// Fcntl commands that may be limited on file descriptors.
@@ -19,14 +19,14 @@ use crate::common::CapRights;
 #[derive(Debug)]
 pub enum Fcntl {
     /// Get descriptor status flags.
-    GetFL = libc::CAP_FCNTL_GETFL,
+    GetFL = sys::CAP_FCNTL_GETFL,
     /// Set descriptor status flags.
-    SetFL = libc::CAP_FCNTL_SETFL,
+    SetFL = sys::CAP_FCNTL_SETFL,
     /// Get the process ID or process group currently receiving SIGIO and SIGURG
     /// signals.
-    GetOwn = libc::CAP_FCNTL_GETOWN,
+    GetOwn = sys::CAP_FCNTL_GETOWN,
     /// Set the process or process group to receive SIGIO and SIGURG signal.
-    SetOwn = libc::CAP_FCNTL_SETOWN,
+    SetOwn = sys::CAP_FCNTL_SETOWN,
 }
 
 /// Used to construct a new set of allowed fcntl commands.
@@ -101,12 +101,58 @@ impl FcntlsBuilder {
 /// fcntl(file.as_raw_fd(), FcntlArg::F_GETFL).unwrap();
 ///
 /// let r = fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_CLOEXEC));
+/// # #[cfg(target_os = "freebsd")]
 /// assert_eq!(r, Err(Errno::ENOTCAPABLE));
+/// # #[cfg(target_os = "linux")]
+/// # assert_eq!(r, Err(Errno::EPERM));
 /// ```
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
-pub struct FcntlRights(u32);
+pub struct FcntlRights(pub(crate) u32);
+
+/// The names of the fcntl commands that may be limited, as used by
+/// [`cap_fcntls_limit(2)`](https://www.freebsd.org/cgi/man.cgi?query=cap_fcntls_limit).
+const NAMES: &[(u32, &str)] = &[
+    (sys::CAP_FCNTL_GETFL, "CAP_FCNTL_GETFL"),
+    (sys::CAP_FCNTL_SETFL, "CAP_FCNTL_SETFL"),
+    (sys::CAP_FCNTL_GETOWN, "CAP_FCNTL_GETOWN"),
+    (sys::CAP_FCNTL_SETOWN, "CAP_FCNTL_SETOWN"),
+];
 
 impl FcntlRights {
+    /// Every fcntl command that can be limited.
+    pub(crate) const ALL: FcntlRights = FcntlRights(
+        sys::CAP_FCNTL_GETFL | sys::CAP_FCNTL_SETFL | sys::CAP_FCNTL_GETOWN | sys::CAP_FCNTL_SETOWN,
+    );
+
+    /// Checks if `self` contains all of the commands present in `other`.
+    pub(crate) fn contains(&self, other: &FcntlRights) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The names of the commands allowed here, like `CAP_FCNTL_GETFL`.
+    pub(crate) fn names(&self) -> Vec<&'static str> {
+        NAMES
+            .iter()
+            .filter(|(cmd, _)| self.0 & cmd != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// The inverse of [`FcntlRights::names`].  Fails with the first name
+    /// that isn't known.
+    #[cfg(any(feature = "manifest", feature = "policy"))]
+    pub(crate) fn from_names<'a, I>(names: I) -> Result<FcntlRights, &'a str>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut rights = FcntlRights::new();
+        for name in names {
+            let (cmd, _) = NAMES.iter().find(|(_, n)| *n == name).ok_or(name)?;
+            rights.0 |= cmd;
+        }
+        Ok(rights)
+    }
+
     /// Initialize a new `FcntlsRights` which will deny all rights.
     pub fn new() -> FcntlRights {
         FcntlRights::default()
@@ -164,7 +210,7 @@ impl FcntlRights {
         unsafe {
             let mut empty_fcntls = 0;
             let fd = f.as_fd().as_raw_fd();
-            let res = libc::cap_fcntls_get(fd, &mut empty_fcntls as *mut u32);
+            let res = sys::cap_fcntls_get(fd, &mut empty_fcntls as *mut u32);
             if res < 0 {
                 Err(io::Error::last_os_error())
             } else {
@@ -176,12 +222,13 @@ impl FcntlRights {
 
 impl CapRights for FcntlRights {
     fn limit<F: AsFd>(&self, fd: &F) -> io::Result<()> {
-        unsafe {
-            if libc::cap_fcntls_limit(fd.as_fd().as_raw_fd(), self.0) < 0 {
+        let result = unsafe {
+            if sys::cap_fcntls_limit(fd.as_fd().as_raw_fd(), self.0) < 0 {
                 Err(io::Error::last_os_error())
             } else {
                 Ok(())
             }
-        }
+        };
+        trace::limit(fd, "fcntls", || self.names().join("|"), result)
     }
 }
@@ -0,0 +1,389 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io,
+    mem,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+};
+
+use libc::c_int;
+
+use crate::{sys, try_clone_with_rights, FcntlRights, FdLimits, FileRights, IoctlRights};
+
+/// The most descriptors that Linux accepts in one message.
+const SCM_MAX_FD: usize = 253;
+
+/// The largest message, descriptors' labels included, that will be sent or
+/// received.
+const MAX_MESSAGE: usize = 1 << 24;
+
+/// The largest message that will be received from a datagram socket.
+const MAX_DATAGRAM: usize = 1 << 16;
+
+/// Received descriptors, each with the limits that it holds.
+pub(crate) type LabeledFds = Vec<(OwnedFd, FdLimits)>;
+
+/// Send descriptors over a Unix-domain socket, each labeled with its limits.
+///
+/// Each descriptor is duplicated, and the duplicate is limited to its
+/// `FdLimits` before it's sent, so the sender's descriptors are unaffected.
+/// On Linux, where limits don't travel with a descriptor, the descriptor
+/// itself is sent instead.  The labels travel in the same message, for
+/// [`recv_fds`] to check and, on Linux, to apply.  On a
+/// stream socket, the message must not be interleaved with other writes.
+///
+/// On Linux, a process whose limits are enforced can't send descriptors at
+/// all, since the receiver's copies would escape those limits.
+///
+/// # Errors
+///
+/// * `EINVAL` - Too many descriptors were given.
+/// * `EMSGSIZE` - The labels are too large, because of their ioctl lists.
+/// * `ENOTCAPABLE` - Some descriptor lacks some right that its limits grant.
+///   Or, on Linux, the process is in capability mode and has limited some
+///   descriptor.
+///
+/// # Example
+/// ```
+/// # use std::{fs::File, os::{fd::AsFd, unix::{fs::FileExt, net::UnixStream}}};
+/// use capsicum::{FdLimits, FileRights, Right};
+///
+/// # let mut file = tempfile::tempfile().unwrap();
+/// # std::io::Write::write_all(&mut file, b"Hello, World!").unwrap();
+/// let (broker, worker) = UnixStream::pair().unwrap();
+/// let ro = FdLimits::new(*FileRights::new().allow(Right::Read).allow(Right::Pread));
+/// capsicum::send_fds(&broker, &[(file.as_fd(), &ro)]).unwrap();
+///
+/// let (fd, limits) = capsicum::recv_fds(&worker).unwrap().pop().unwrap();
+/// assert_eq!(limits, ro);
+/// let mut buf = [0; 5];
+/// File::from(fd).read_exact_at(&mut buf, 0).unwrap();
+/// assert_eq!(&buf, b"Hello");
+/// ```
+pub fn send_fds<S: AsFd>(socket: &S, fds: &[(BorrowedFd<'_>, &FdLimits)]) -> io::Result<()> {
+    send(socket, fds, &[])
+}
+
+/// Like [`send_fds`], but with an arbitrary payload after the labels.
+pub(crate) fn send<S: AsFd>(
+    socket: &S,
+    fds: &[(BorrowedFd<'_>, &FdLimits)],
+    payload: &[u8],
+) -> io::Result<()> {
+    if fds.len() > SCM_MAX_FD {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    // The message's length, filled in last, then the labels.
+    let mut message = vec![0; 4];
+    message.extend((fds.len() as u32).to_ne_bytes());
+    let mut limited = Vec::with_capacity(fds.len());
+    let mut raw = Vec::with_capacity(fds.len());
+    for (fd, limits) in fds {
+        if cfg!(target_os = "linux") {
+            // Linux's emulated limits don't travel with the descriptor, so
+            // there's no use limiting a duplicate.  The receiver applies the
+            // label instead.
+            if !FdLimits::from_file(fd)?.contains(limits) {
+                return Err(io::Error::from_raw_os_error(sys::ENOTCAPABLE));
+            }
+            raw.push(fd.as_raw_fd());
+        } else {
+            let dup = try_clone_with_rights(fd, limits)?;
+            raw.push(dup.as_raw_fd());
+            limited.push(dup);
+        }
+        encode(&mut message, limits);
+    }
+    message.extend(payload);
+    if message.len() > MAX_MESSAGE {
+        return Err(io::Error::from_raw_os_error(libc::EMSGSIZE));
+    }
+    let len = message.len() as u32;
+    message[..4].copy_from_slice(&len.to_ne_bytes());
+
+    let fd = socket.as_fd().as_raw_fd();
+    // Without descriptors, a plain send will do.  On Linux, it's allowed even
+    // where sendmsg isn't.
+    let mut sent = 0;
+    if !raw.is_empty() {
+        let mut control = control_buffer(raw.len());
+        let mut iov = libc::iovec {
+            iov_base: message.as_mut_ptr().cast(),
+            iov_len: message.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = (control.len() * mem::size_of::<u64>()) as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(&raw[..]) as u32) as _;
+            ptr::copy_nonoverlapping(raw.as_ptr(), libc::CMSG_DATA(cmsg).cast(), raw.len());
+        }
+        sent = retry(|| unsafe { libc::sendmsg(fd, &msg, libc::MSG_NOSIGNAL) })?;
+    }
+    // A stream socket may take the message piecemeal.  Any descriptors went
+    // with the first piece.
+    while sent < message.len() {
+        let rest = &message[sent..];
+        sent += retry(|| unsafe {
+            libc::send(fd, rest.as_ptr().cast(), rest.len(), libc::MSG_NOSIGNAL)
+        })?;
+    }
+    Ok(())
+}
+
+/// Receive descriptors sent by [`send_fds`], and check their labels.
+///
+/// Each descriptor is returned with the limits that it actually holds, after
+/// checking that they're no more than its label claims.  If any descriptor
+/// holds more, they're all closed, and the message is rejected.  So a
+/// sandboxed process can trust the labels even if the sender is buggy.
+///
+/// On Linux, emulated limits don't travel with a descriptor, so there's
+/// nothing to check.  Instead, the receiver imposes each label on its
+/// descriptor, whatever the sender held.  The labels still hold, but only
+/// once the receiver enters capability mode, like any other limits.  In
+/// capability mode, each
+/// received descriptor is numbered from a range that it shares with other new
+/// descriptors with the same limits, like with
+/// [`try_clone_with_rights`](crate::try_clone_with_rights).
+///
+/// # Errors
+///
+/// * `EBADMSG` - The message is malformed, or the number of descriptors
+///               doesn't match the number of labels.
+/// * `EMFILE` - On Linux, in capability mode, the range for some label is
+///   full.
+/// * `EMSGSIZE` - The message is too large, and was truncated.
+/// * `ENOMEM` - On Linux, in capability mode, there's no room for another
+///   range.
+/// * `ENOTCAPABLE` - On FreeBSD, some descriptor holds more rights than its
+///   label claims.
+/// * [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) - The peer closed the
+///   socket.
+pub fn recv_fds<S: AsFd>(socket: &S) -> io::Result<Vec<(OwnedFd, FdLimits)>> {
+    let (fds, payload) = recv(socket)?;
+    if !payload.is_empty() {
+        return Err(bad_message());
+    }
+    Ok(fds)
+}
+
+/// Like [`recv_fds`], but also return the payload that followed the labels.
+pub(crate) fn recv<S: AsFd>(socket: &S) -> io::Result<(LabeledFds, Vec<u8>)> {
+    let fd = socket.as_fd().as_raw_fd();
+    // On a stream socket, receive only the length at first, so as not to
+    // receive any of the next message too.
+    let mut ty: c_int = 0;
+    let mut len = mem::size_of::<c_int>() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            (&mut ty as *mut c_int).cast(),
+            &mut len,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stream = ty == libc::SOCK_STREAM;
+    let first = if stream { 4 } else { MAX_DATAGRAM };
+    let mut message = vec![0u8; first];
+
+    let mut control = control_buffer(SCM_MAX_FD);
+    let mut iov = libc::iovec {
+        iov_base: message.as_mut_ptr().cast(),
+        iov_len: first,
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = (control.len() * mem::size_of::<u64>()) as _;
+    let mut received = retry(|| unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_CMSG_CLOEXEC) })?;
+
+    // Take ownership of the descriptors first, so they're closed on error.
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    if msg.msg_flags & (libc::MSG_TRUNC | libc::MSG_CTRUNC) != 0 {
+        return Err(io::Error::from_raw_os_error(libc::EMSGSIZE));
+    }
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    // A datagram arrives whole, or not at all.
+    while stream && (received < 4 || received < message_len(&message)) {
+        let end = if received < 4 {
+            4
+        } else {
+            message_len(&message)
+        };
+        if end > MAX_MESSAGE {
+            return Err(bad_message());
+        }
+        message.resize(message.len().max(end), 0);
+        let rest = &mut message[received..end];
+        match retry(|| unsafe { libc::recv(fd, rest.as_mut_ptr().cast(), rest.len(), 0) })? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => received += n,
+        }
+    }
+    if received < 4 || received != message_len(&message) {
+        return Err(bad_message());
+    }
+    message.truncate(received);
+
+    let mut labels = Decoder(&message[4..]);
+    if labels.u32()? as usize != fds.len() {
+        return Err(bad_message());
+    }
+    let mut checked = Vec::with_capacity(fds.len());
+    for fd in fds {
+        let label = labels.limits()?;
+        // Linux's emulated limits don't travel with the descriptor, so the
+        // label is imposed rather than checked.
+        #[cfg(target_os = "linux")]
+        let fd = crate::limits::limit_new(fd, None, &label)?;
+        let actual = FdLimits::from_file(&fd)?;
+        #[cfg(not(target_os = "linux"))]
+        if !label.contains(&actual) {
+            return Err(io::Error::from_raw_os_error(sys::ENOTCAPABLE));
+        }
+        checked.push((fd, actual));
+    }
+    let payload = labels.0.to_vec();
+    Ok((checked, payload))
+}
+
+/// An aligned buffer for a control message with `n` descriptors.
+fn control_buffer(n: usize) -> Vec<u64> {
+    let space = unsafe { libc::CMSG_SPACE((n * mem::size_of::<RawFd>()) as u32) } as usize;
+    vec![0; space.div_ceil(mem::size_of::<u64>())]
+}
+
+fn message_len(message: &[u8]) -> usize {
+    u32::from_ne_bytes(message[..4].try_into().unwrap()) as usize
+}
+
+fn bad_message() -> io::Error {
+    io::Error::from_raw_os_error(libc::EBADMSG)
+}
+
+/// Retry a syscall that was interrupted by a signal.
+fn retry<F: FnMut() -> isize>(mut f: F) -> io::Result<usize> {
+    loop {
+        let r = f();
+        if r >= 0 {
+            return Ok(r as usize);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Append a descriptor's label to a message.
+fn encode(message: &mut Vec<u8>, limits: &FdLimits) {
+    for word in limits.rights.to_raw() {
+        message.extend(word.to_ne_bytes());
+    }
+    match &limits.fcntls {
+        None => message.push(0),
+        Some(fcntls) => {
+            message.push(1);
+            message.extend(fcntls.0.to_ne_bytes());
+        }
+    }
+    match &limits.ioctls {
+        IoctlRights::Unlimited => message.push(0),
+        IoctlRights::Limited(cmds) => {
+            message.push(1);
+            message.extend((cmds.len() as u32).to_ne_bytes());
+            for cmd in cmds {
+                message.extend(cmd.to_ne_bytes());
+            }
+        }
+    }
+}
+
+/// Reads labels from a message.
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        if self.0.len() < N {
+            return Err(bad_message());
+        }
+        let (head, tail) = self.0.split_at(N);
+        self.0 = tail;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.take().map(u32::from_ne_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.take().map(u64::from_ne_bytes)
+    }
+
+    fn limits(&mut self) -> io::Result<FdLimits> {
+        let rights = FileRights::from_raw([self.u64()?, self.u64()?]).ok_or_else(bad_message)?;
+        let fcntls = match self.u8()? {
+            0 => None,
+            1 => {
+                let fcntls = self.u32()?;
+                if fcntls & !FcntlRights::ALL.0 != 0 {
+                    return Err(bad_message());
+                }
+                Some(FcntlRights(fcntls))
+            }
+            _ => return Err(bad_message()),
+        };
+        let ioctls = match self.u8()? {
+            0 => IoctlRights::Unlimited,
+            1 => {
+                let len = self.u32()? as usize;
+                if len > self.0.len() / mem::size_of::<sys::u_long>() {
+                    return Err(bad_message());
+                }
+                let cmds = (0..len)
+                    .map(|_| self.take().map(sys::u_long::from_ne_bytes))
+                    .collect::<io::Result<_>>()?;
+                IoctlRights::Limited(cmds)
+            }
+            _ => return Err(bad_message()),
+        };
+        Ok(FdLimits {
+            rights,
+            fcntls,
+            ioctls,
+        })
+    }
+}
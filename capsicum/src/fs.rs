@@ -0,0 +1,552 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Opening files by path, in capability mode.
+//!
+//! In capability mode, files can only be opened relative to directory
+//! descriptors that were opened beforehand.  But plenty of code only knows
+//! absolute paths.  A [`Preopens`] registry maps path prefixes to those
+//! directories, so that [`open`] and [`create`] can translate an absolute
+//! path into a lookup relative to the directory with the longest matching
+//! prefix.
+//!
+//! A [`SandboxTempDir`] provides temporary files and directories, which
+//! `tempfile` can't create in capability mode.
+//!
+//! # Example
+//! ```
+//! use std::io::Read;
+//!
+//! use capsicum::{fs::Preopens, FileRights, Right};
+//!
+//! let rights = *FileRights::new()
+//!     .allow(Right::Lookup)
+//!     .allow(Right::Read)
+//!     .allow(Right::Fstat);
+//! Preopens::new().open_dir("/etc", rights).unwrap().install();
+//! capsicum::enter().unwrap();
+//!
+//! let mut passwd = String::new();
+//! capsicum::fs::open("/etc/passwd")
+//!     .unwrap()
+//!     .read_to_string(&mut passwd)
+//!     .unwrap();
+//! capsicum::fs::open("/var/log/messages").unwrap_err();
+//! ```
+
+use std::{
+    env,
+    ffi::{CStr, CString, OsStr},
+    fmt,
+    fs::File,
+    hash::{BuildHasher, Hasher, RandomState},
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use crate::{common::CapRights, sys, FileRights, Right};
+
+/// The registry used by [`open`] and [`create`], once installed.
+static INSTALLED: RwLock<Option<Arc<Preopens>>> = RwLock::new(None);
+
+/// A registry of directories, opened before entering capability mode, and
+/// the path prefixes that they stand for.
+#[derive(Default)]
+pub struct Preopens {
+    dirs: Vec<(PathBuf, OwnedFd)>,
+}
+
+impl Preopens {
+    /// Create a new `Preopens` with no directories.
+    pub fn new() -> Preopens {
+        Preopens::default()
+    }
+
+    /// Register an open directory as standing for the absolute path `prefix`.
+    ///
+    /// The directory should already be limited to the rights that files
+    /// opened through it will need.  If `prefix` was already registered, this
+    /// replaces it.
+    pub fn dir<P, F>(mut self, prefix: P, dir: F) -> Self
+    where
+        P: AsRef<Path>,
+        F: Into<OwnedFd>,
+    {
+        let prefix = normalize(prefix.as_ref());
+        self.dirs.retain(|(p, _)| *p != prefix);
+        self.dirs.push((prefix, dir.into()));
+        self
+    }
+
+    /// Open the directory at `path`, limit it to `rights`, and register it as
+    /// standing for `path`.
+    ///
+    /// `rights` should include [`Right::Lookup`](crate::Right::Lookup), and
+    /// whatever rights files opened through the directory will need; they
+    /// inherit no more than the directory's.
+    pub fn open_dir<P: AsRef<Path>>(self, path: P, rights: FileRights) -> io::Result<Self> {
+        let path = path.as_ref();
+        let cpath = cstring(path)?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let dir = OwnedFd::from(file(unsafe { libc::open(cpath.as_ptr(), flags) })?);
+        rights.limit(&dir)?;
+        Ok(self.dir(path, dir))
+    }
+
+    /// Find the directory whose prefix is the longest that matches `path`,
+    /// and the rest of `path`, relative to it.
+    ///
+    /// `path` is normalized lexically first, so `..` can't climb out of a
+    /// prefix's directory.  Prefixes only match whole path components.
+    ///
+    /// # Errors
+    ///
+    /// * `ENOTCAPABLE` - No prefix matches.
+    /// * [`InvalidInput`](io::ErrorKind::InvalidInput) - `path` is relative.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::{fs::File, path::Path};
+    /// # use capsicum::fs::Preopens;
+    /// let preopens = Preopens::new()
+    ///     .dir("/", File::open("/").unwrap())
+    ///     .dir("/etc", File::open("/etc").unwrap());
+    /// let (_etc, rest) = preopens.resolve("/etc/ssl/cert.pem").unwrap();
+    /// assert_eq!(rest, Path::new("ssl/cert.pem"));
+    /// let (_root, rest) = preopens.resolve("/etcetera").unwrap();
+    /// assert_eq!(rest, Path::new("etcetera"));
+    /// ```
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> io::Result<(BorrowedFd<'_>, PathBuf)> {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not an absolute path", path.display()),
+            ));
+        }
+        let path = normalize(path);
+        self.dirs
+            .iter()
+            .filter_map(|(prefix, dir)| Some((prefix, dir, path.strip_prefix(prefix).ok()?)))
+            .max_by_key(|(prefix, ..)| prefix.components().count())
+            .map(|(_, dir, rest)| {
+                let rest = if rest.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    rest
+                };
+                (dir.as_fd(), rest.to_owned())
+            })
+            .ok_or_else(|| io::Error::from_raw_os_error(sys::ENOTCAPABLE))
+    }
+
+    /// Open `path`, relative to its directory, with `open(2)`'s `flags` and
+    /// `mode`.
+    fn openat(&self, path: &Path, flags: libc::c_int, mode: libc::mode_t) -> io::Result<File> {
+        let (dir, rest) = self.resolve(path)?;
+        let rest = cstring(&rest)?;
+        let flags = flags | libc::O_CLOEXEC;
+        let mode = libc::c_uint::from(mode);
+        file(unsafe { libc::openat(dir.as_raw_fd(), rest.as_ptr(), flags, mode) })
+    }
+
+    /// Make this the registry that [`open`] and [`create`] use, replacing any
+    /// that was installed before.
+    pub fn install(self) {
+        *INSTALLED.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(self));
+    }
+
+    /// The registered prefixes and their directories.
+    pub(crate) fn dirs(&self) -> impl Iterator<Item = (&Path, BorrowedFd<'_>)> {
+        self.dirs
+            .iter()
+            .map(|(prefix, dir)| (prefix.as_path(), dir.as_fd()))
+    }
+}
+
+/// The registry that [`open`] and [`create`] use, if one was installed.
+pub(crate) fn installed() -> Option<Arc<Preopens>> {
+    INSTALLED.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+impl fmt::Debug for Preopens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.dirs.iter().map(|(prefix, dir)| (prefix, dir)))
+            .finish()
+    }
+}
+
+/// Resolve `..` and `.` in an absolute path, without touching the file
+/// system.  `..` never climbs above the root.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::Normal(name) => normal.push(name),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => (),
+        }
+    }
+    normal
+}
+
+/// Wrap the result of `open(2)` or `openat(2)`.
+pub(crate) fn file(fd: libc::c_int) -> io::Result<File> {
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+}
+
+pub(crate) fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+/// Open a file through the installed [`Preopens`], like `open(2)` would.
+fn open_installed(path: &Path, flags: libc::c_int, mode: libc::mode_t) -> io::Result<File> {
+    match installed() {
+        Some(preopens) => preopens.openat(path, flags, mode),
+        None => {
+            let path = cstring(path)?;
+            let flags = flags | libc::O_CLOEXEC;
+            file(unsafe { libc::open(path.as_ptr(), flags, libc::c_uint::from(mode)) })
+        }
+    }
+}
+
+/// Open a file for reading, like [`File::open`], through the installed
+/// [`Preopens`].
+///
+/// If no `Preopens` were installed, `path` is opened directly, so that the
+/// same code works before entering capability mode.
+///
+/// # Errors
+///
+/// * `ENOTCAPABLE` - No registered directory contains `path`.
+/// * Any error from `openat(2)`.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    open_installed(path.as_ref(), libc::O_RDONLY, 0)
+}
+
+/// Open a file for writing, like [`File::create`], through the installed
+/// [`Preopens`].  The file is created if it doesn't exist, and truncated if
+/// it does.
+///
+/// If no `Preopens` were installed, `path` is opened directly, so that the
+/// same code works before entering capability mode.
+///
+/// # Errors
+///
+/// * `ENOTCAPABLE` - No registered directory contains `path`.
+/// * Any error from `openat(2)`.
+pub fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+    open_installed(path.as_ref(), flags, 0o666)
+}
+
+/// The rights that a [`SandboxTempDir`] holds, and the most that the files
+/// and directories created in it may have.
+///
+/// `fdopendir(3)`, used to remove the directory's contents, needs
+/// `CAP_FSTAT`, and with glibc `CAP_FCNTL` too.
+const TEMP_DIR_RIGHTS: &[Right] = &[
+    Right::Lookup,
+    Right::Create,
+    Right::Read,
+    Right::Write,
+    Right::Seek,
+    Right::Ftruncate,
+    Right::Fsync,
+    Right::Fstat,
+    Right::Fcntl,
+    Right::Mkdirat,
+    Right::Unlinkat,
+];
+
+/// How many times to retry a name that already exists.
+const TEMP_ATTEMPTS: usize = 128;
+
+/// A temporary directory that can be used in capability mode.
+///
+/// The directory is created before entering capability mode, and its
+/// descriptor kept.  Files and subdirectories are then created in it with
+/// `openat(2)` and `mkdirat(2)`, each with a unique name, and limited to the
+/// rights that the caller asks for.  When the `SandboxTempDir` is dropped,
+/// its contents are removed with `unlinkat(2)`, and so is the directory
+/// itself.
+///
+/// The directory's descriptor is limited to `CAP_LOOKUP`, `CAP_CREATE`,
+/// `CAP_READ`, `CAP_WRITE`, `CAP_SEEK`, `CAP_FTRUNCATE`, `CAP_FSYNC`,
+/// `CAP_FSTAT`, `CAP_FCNTL`, `CAP_MKDIRAT`, and `CAP_UNLINKAT`.  To remove the
+/// directory itself, a descriptor for its parent is kept too, limited to
+/// `CAP_UNLINKAT`.
+///
+/// # Example
+/// ```
+/// use std::io::{Read, Seek, Write};
+///
+/// use capsicum::{fs::SandboxTempDir, FileRights, Right};
+///
+/// let tmp = SandboxTempDir::new().unwrap();
+/// capsicum::enter().unwrap();
+///
+/// let rights = *FileRights::new()
+///     .allow(Right::Read)
+///     .allow(Right::Write)
+///     .allow(Right::Seek);
+/// let (mut file, path) = tmp.create_file(rights).unwrap();
+/// assert!(path.starts_with(tmp.path()));
+/// file.write_all(b"scratch").unwrap();
+/// file.rewind().unwrap();
+/// let mut s = String::new();
+/// file.read_to_string(&mut s).unwrap();
+/// assert_eq!(s, "scratch");
+/// ```
+#[derive(Debug)]
+pub struct SandboxTempDir {
+    path: PathBuf,
+    dir: OwnedFd,
+    parent: OwnedFd,
+    name: CString,
+    removed: bool,
+}
+
+impl SandboxTempDir {
+    /// Create a new temporary directory in [`env::temp_dir`].
+    pub fn new() -> io::Result<SandboxTempDir> {
+        SandboxTempDir::new_in(env::temp_dir())
+    }
+
+    /// Create a new temporary directory in `dir`.
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> io::Result<SandboxTempDir> {
+        let dir = dir.as_ref();
+        let cpath = cstring(dir)?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let parent = OwnedFd::from(file(unsafe { libc::open(cpath.as_ptr(), flags) })?);
+        let name = unique(|name| {
+            let r = unsafe { libc::mkdirat(parent.as_raw_fd(), name.as_ptr(), 0o700) };
+            if r < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        })?;
+        let tmp = SandboxTempDir {
+            path: dir.join(OsStr::from_bytes(name.to_bytes())),
+            dir: open_subdir(parent.as_fd(), &name)?,
+            parent,
+            name,
+            removed: false,
+        };
+        let mut rights = FileRights::new();
+        for right in TEMP_DIR_RIGHTS {
+            rights.allow(*right);
+        }
+        rights.limit(&tmp.dir)?;
+        FileRights::new()
+            .allow(Right::Unlinkat)
+            .limit(&tmp.parent)?;
+        Ok(tmp)
+    }
+
+    /// The directory's path.  In capability mode it can't be used directly,
+    /// but it can be given to a [`Preopens`], or to another process.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create a new, uniquely named file, open for reading and writing, and
+    /// limit it to `rights`.  Returns the file and its path.
+    ///
+    /// # Errors
+    ///
+    /// * `ENOTCAPABLE` - On FreeBSD, `rights` include some right that the
+    ///   directory lacks.
+    /// * Any error from `openat(2)`.
+    pub fn create_file(&self, rights: FileRights) -> io::Result<(File, PathBuf)> {
+        let dir = self.dir.as_raw_fd();
+        let mut f = None;
+        let name = unique(|name| {
+            let flags = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC;
+            let mode = libc::c_uint::from(0o600 as libc::mode_t);
+            f = Some(file(unsafe {
+                libc::openat(dir, name.as_ptr(), flags, mode)
+            })?);
+            Ok(())
+        })?;
+        let f = f.unwrap();
+        self.limit_new(&f, &name, rights, 0)?;
+        Ok((f, self.path.join(OsStr::from_bytes(name.to_bytes()))))
+    }
+
+    /// Create a new, uniquely named subdirectory, open it, and limit it to
+    /// `rights`.  Returns the subdirectory and its path.
+    ///
+    /// # Errors
+    ///
+    /// * `ENOTCAPABLE` - On FreeBSD, `rights` include some right that the
+    ///   directory lacks.
+    /// * Any error from `mkdirat(2)` or `openat(2)`.
+    pub fn create_dir(&self, rights: FileRights) -> io::Result<(OwnedFd, PathBuf)> {
+        let dir = self.dir.as_raw_fd();
+        let name = unique(|name| {
+            if unsafe { libc::mkdirat(dir, name.as_ptr(), 0o700) } < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        })?;
+        let subdir = open_subdir(self.dir.as_fd(), &name)
+            .and_then(|subdir| {
+                self.limit_new(&subdir, &name, rights, libc::AT_REMOVEDIR)
+                    .map(|_| subdir)
+            })
+            .inspect_err(|_| unsafe {
+                libc::unlinkat(dir, name.as_ptr(), libc::AT_REMOVEDIR);
+            })?;
+        Ok((subdir, self.path.join(OsStr::from_bytes(name.to_bytes()))))
+    }
+
+    /// Limit `fd`, just created as `name`, to `rights`, or remove it if that
+    /// fails.
+    fn limit_new<F: AsFd>(
+        &self,
+        fd: &F,
+        name: &CStr,
+        rights: FileRights,
+        flags: libc::c_int,
+    ) -> io::Result<()> {
+        rights.limit(fd).inspect_err(|_| unsafe {
+            libc::unlinkat(self.dir.as_raw_fd(), name.as_ptr(), flags);
+        })
+    }
+
+    /// Remove the directory and everything in it, and report any error that
+    /// dropping it would have ignored.
+    pub fn close(mut self) -> io::Result<()> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> io::Result<()> {
+        self.removed = true;
+        remove_contents(self.dir.as_fd())?;
+        let r = unsafe {
+            libc::unlinkat(
+                self.parent.as_raw_fd(),
+                self.name.as_ptr(),
+                libc::AT_REMOVEDIR,
+            )
+        };
+        if r < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl AsFd for SandboxTempDir {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.dir.as_fd()
+    }
+}
+
+impl Drop for SandboxTempDir {
+    fn drop(&mut self) {
+        if !self.removed {
+            let _ = self.remove();
+        }
+    }
+}
+
+/// Call `create` with random names until one doesn't already exist.
+fn unique<F>(mut create: F) -> io::Result<CString>
+where
+    F: FnMut(&CStr) -> io::Result<()>,
+{
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    for _ in 0..TEMP_ATTEMPTS {
+        // Each RandomState is seeded differently, so even an empty hash is
+        // random.
+        let mut bits = RandomState::new().build_hasher().finish();
+        let mut name = b".tmp".to_vec();
+        for _ in 0..6 {
+            name.push(CHARS[(bits % CHARS.len() as u64) as usize]);
+            bits /= CHARS.len() as u64;
+        }
+        let name = CString::new(name).unwrap();
+        match create(&name) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            r => return r.map(|_| name),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "too many temporary files exist",
+    ))
+}
+
+/// Open the subdirectory `name` of `dir`, without following symlinks.
+fn open_subdir(dir: BorrowedFd<'_>, name: &CStr) -> io::Result<OwnedFd> {
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+    file(fd).map(OwnedFd::from)
+}
+
+/// Remove everything in `dir`, recursively.
+fn remove_contents(dir: BorrowedFd<'_>) -> io::Result<()> {
+    // List the entries first, because removing them while reading the
+    // directory may skip some.
+    let entries = {
+        let fd = open_subdir(dir, c".")?.into_raw_fd();
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        let mut entries = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                break;
+            }
+            let entry = unsafe { &*entry };
+            let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+            if name != c"." && name != c".." {
+                entries.push((name.to_owned(), entry.d_type));
+            }
+        }
+        unsafe { libc::closedir(stream) };
+        entries
+    };
+    for (name, d_type) in entries {
+        // A directory of unknown type shows itself when opened as one.
+        let subdir = match d_type {
+            libc::DT_DIR | libc::DT_UNKNOWN => open_subdir(dir, &name).ok(),
+            _ => None,
+        };
+        let flags = match subdir {
+            Some(subdir) => {
+                remove_contents(subdir.as_fd())?;
+                libc::AT_REMOVEDIR
+            }
+            None => 0,
+        };
+        if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
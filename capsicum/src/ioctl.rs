@@ -7,9 +7,11 @@ use std::{
     os::{fd::AsFd, unix::io::AsRawFd},
 };
 
-use libc::u_long;
-
-use crate::common::CapRights;
+use crate::{
+    common::CapRights,
+    sys::{self, u_long},
+    trace,
+};
 
 const CAP_IOCTLS_ALL: isize = isize::MAX;
 
@@ -29,12 +31,15 @@ const CAP_IOCTLS_ALL: isize = isize::MAX;
 /// #[macro_use(request_code_read)]
 /// extern crate nix;
 /// # use capsicum::IoctlsBuilder;
+/// # #[cfg(target_os = "freebsd")]
 /// const TIOCGETD: libc::u_long = request_code_read!(b't', 26, mem::size_of::<libc::c_int>());
 ///
 /// fn main() {
+/// # #[cfg(target_os = "freebsd")] {
 ///     let rights = IoctlsBuilder::new()
 ///         .allow(TIOCGETD)
 ///         .finalize();
+/// # }
 /// }
 #[derive(Clone, Debug, Default)]
 pub struct IoctlsBuilder(Vec<u_long>);
@@ -112,6 +117,7 @@ impl IoctlsBuilder {
 ///
 /// # Example
 /// ```
+/// # #[cfg(target_os = "freebsd")] {
 /// # use std::os::unix::io::AsRawFd;
 /// # use capsicum::{CapRights, IoctlsBuilder};
 /// # use tempfile::tempfile;
@@ -144,6 +150,7 @@ impl IoctlsBuilder {
 ///
 /// let e = unsafe{ fionwrite(fd1.as_raw_fd(), &mut n as *mut c_int) };
 /// assert_eq!(e, Err(Errno::ENOTCAPABLE));
+/// # }
 /// ```
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub enum IoctlRights {
@@ -173,7 +180,7 @@ impl IoctlRights {
         let mut cmds = Vec::with_capacity(len);
         let fd = f.as_fd().as_raw_fd();
         unsafe {
-            let res = libc::cap_ioctls_get(fd, cmds.as_mut_ptr(), len);
+            let res = sys::cap_ioctls_get(fd, cmds.as_mut_ptr(), len);
             if res == CAP_IOCTLS_ALL {
                 Ok(IoctlRights::Unlimited)
             } else if let Ok(rlen) = usize::try_from(res) {
@@ -188,6 +195,19 @@ impl IoctlRights {
             }
         }
     }
+
+    /// Like [`IoctlRights::from_file`], but sizes the buffer automatically.
+    pub(crate) fn from_file_unbounded<F: AsFd>(f: &F) -> io::Result<IoctlRights> {
+        let fd = f.as_fd().as_raw_fd();
+        let res = unsafe { sys::cap_ioctls_get(fd, std::ptr::null_mut(), 0) };
+        if res == CAP_IOCTLS_ALL {
+            Ok(IoctlRights::Unlimited)
+        } else if let Ok(len) = usize::try_from(res) {
+            IoctlRights::from_file(f, len)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
 }
 
 impl CapRights for IoctlRights {
@@ -195,11 +215,18 @@ impl CapRights for IoctlRights {
         if let IoctlRights::Limited(v) = self {
             let len = v.len();
             let fd = f.as_fd().as_raw_fd();
-            unsafe {
-                if libc::cap_ioctls_limit(fd, v.as_ptr(), len) < 0 {
-                    return Err(io::Error::last_os_error());
+            let result = unsafe {
+                if sys::cap_ioctls_limit(fd, v.as_ptr(), len) < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
                 }
-            }
+            };
+            let cmds = || {
+                let cmds = v.iter().map(|cmd| format!("{cmd:#x}")).collect::<Vec<_>>();
+                cmds.join("|")
+            };
+            return trace::limit(f, "ioctls", cmds, result);
         }
         Ok(())
     }
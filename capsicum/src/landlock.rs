@@ -0,0 +1,373 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Directory rights enforced with Landlock.
+//!
+//! The seccomp filter that emulates capability mode on Linux can't inspect
+//! the paths passed to the `*at` syscalls, so it can't stop `openat(2)` from
+//! following an absolute path or `..` out of a directory.  Nor can it limit
+//! what may be done to the files that are found there.  Landlock can.  A
+//! [`Ruleset`] translates the [`FileRights`] of some directory descriptors
+//! into Landlock access rights for the file hierarchies beneath them, and
+//! restricts the process when it enters capability mode.
+//!
+//! Landlock restricts paths, not descriptors.  So, unlike with Capsicum:
+//!
+//! * Once the ruleset is in effect, every file system access that it handles
+//!   is denied, unless it's beneath one of the ruleset's directories.  That
+//!   includes accesses through directories that were never added to it.
+//! * Two descriptors for the same directory share the union of their rights.
+//! * Operations on descriptors, like `fstat(2)` or `lseek(2)`, aren't
+//!   restricted at all.
+//!
+//! Forbidden accesses fail with `EACCES` or `EXDEV`.
+//!
+//! Landlock restricts threads, too, not processes.  So a process with a
+//! ruleset to apply must be single-threaded when it enters capability mode.
+//!
+//! # Example
+//! ```
+//! # use std::{fs, os::fd::AsRawFd};
+//! use capsicum::{landlock::Ruleset, FileRights, Right};
+//!
+//! # let tdir = tempfile::tempdir().unwrap();
+//! # fs::write(tdir.path().join("index.html"), b"Hello, World!").unwrap();
+//! let aa = cap_std::ambient_authority();
+//! let root = cap_std::fs::Dir::open_ambient_dir(tdir.path(), aa).unwrap();
+//! let rights = *FileRights::new()
+//!     .allow(Right::Lookup)
+//!     .allow(Right::Read)
+//!     .allow(Right::Fchflags);
+//!
+//! let ruleset = Ruleset::new().unwrap().allow(&root, &rights).unwrap();
+//! // FreeBSD's file flags have no equivalent on Linux.
+//! assert_eq!(
+//!     ruleset.unexpressible(),
+//!     &[(root.as_raw_fd(), *FileRights::new().allow(Right::Fchflags))]
+//! );
+//! ruleset.restrict_on_enter().unwrap();
+//!
+//! capsicum::enter().unwrap();
+//!
+//! assert_eq!(root.read_to_string("index.html").unwrap(), "Hello, World!");
+//! root.write("index.html", b"Goodbye").unwrap_err();
+//! ```
+
+use std::{
+    fs,
+    io,
+    mem,
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use libc::{c_int, c_long};
+
+use crate::{
+    linux::{capmode, rights, sys},
+    FileRights,
+    Right,
+};
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+const ACCESS_FS_REMOVE: u64 = ACCESS_FS_REMOVE_DIR | ACCESS_FS_REMOVE_FILE;
+const ACCESS_FS_MAKE: u64 = ACCESS_FS_MAKE_CHAR
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_BLOCK
+    | ACCESS_FS_MAKE_SYM;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Rights with a Landlock equivalent, their access rights, and the Landlock
+/// ABI version that introduced those.
+///
+/// Renaming or linking a file out of a directory removes it from there, and
+/// renaming or linking it into a directory creates it there.  So on FreeBSD,
+/// the rename and link rights convey the rights to remove and to make files,
+/// and Landlock requires them.
+#[rustfmt::skip]
+const ACCESS: &[(Right, u64, u32)] = &[
+    (Right::Read, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR, 1),
+    (Right::Write, ACCESS_FS_WRITE_FILE, 1),
+    (Right::Fexecve, ACCESS_FS_EXECUTE, 1),
+    (Right::Create, ACCESS_FS_MAKE_REG, 1),
+    (Right::Mkdirat, ACCESS_FS_MAKE_DIR, 1),
+    (Right::Mkfifoat, ACCESS_FS_MAKE_FIFO, 1),
+    (Right::Mknodat, ACCESS_FS_MAKE_CHAR | ACCESS_FS_MAKE_BLOCK, 1),
+    (Right::Symlinkat, ACCESS_FS_MAKE_SYM, 1),
+    (Right::Bindat, ACCESS_FS_MAKE_SOCK, 1),
+    (Right::Unlinkat, ACCESS_FS_REMOVE, 1),
+    (Right::RenameatSource, ACCESS_FS_REFER | ACCESS_FS_REMOVE, 2),
+    (Right::RenameatTarget, ACCESS_FS_REFER | ACCESS_FS_MAKE, 2),
+    (Right::LinkatSource, ACCESS_FS_REFER, 2),
+    (Right::LinkatTarget, ACCESS_FS_REFER | ACCESS_FS_MAKE, 2),
+    (Right::Ftruncate, ACCESS_FS_TRUNCATE, 3),
+    (Right::Ioctl, ACCESS_FS_IOCTL_DEV, 5),
+];
+
+/// Every access right known to a Landlock ABI version.
+fn handled(abi: u32) -> u64 {
+    match abi {
+        1 => ACCESS_FS_REFER - 1,
+        2 => ACCESS_FS_TRUNCATE - 1,
+        3 | 4 => ACCESS_FS_IOCTL_DEV - 1,
+        _ => (ACCESS_FS_IOCTL_DEV << 1) - 1,
+    }
+}
+
+/// Rulesets waiting for the process to enter capability mode.
+static PENDING: Mutex<Vec<OwnedFd>> = Mutex::new(Vec::new());
+
+/// Has this process, or the process that it forked from, been restricted?
+static RESTRICTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the Landlock ABI version supported by the running kernel.
+///
+/// # Errors
+///
+/// * `ENOSYS` - The kernel was built without Landlock support.
+/// * `EOPNOTSUPP` - Landlock is supported but disabled at boot time.
+pub fn abi() -> io::Result<u32> {
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            ptr::null::<RulesetAttr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(r as u32)
+    }
+}
+
+/// A set of directories, and the rights to the file hierarchies beneath them.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug)]
+pub struct Ruleset {
+    fd: OwnedFd,
+    abi: u32,
+    unexpressible: Vec<(RawFd, FileRights)>,
+}
+
+impl Ruleset {
+    /// Create an empty ruleset, that handles every file system access right
+    /// known to the running kernel.
+    ///
+    /// # Errors
+    ///
+    /// * `ENOSYS` - The kernel was built without Landlock support.
+    /// * `EOPNOTSUPP` - Landlock is supported but disabled at boot time.
+    pub fn new() -> io::Result<Ruleset> {
+        let abi = abi()?;
+        let attr = RulesetAttr {
+            handled_access_fs: handled(abi),
+        };
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(r as RawFd) };
+        Ok(Ruleset {
+            fd,
+            abi,
+            unexpressible: Vec::new(),
+        })
+    }
+
+    /// Allow the accesses that `rights` would allow through `dir` to every
+    /// file beneath it.
+    ///
+    /// Rights that can't be expressed with Landlock are ignored, and
+    /// reported by [`Ruleset::unexpressible`].
+    ///
+    /// # Errors
+    ///
+    /// * `EBADF` - `dir` is not open.
+    /// * `EINVAL` - `dir` is not a directory.
+    pub fn allow<F: AsFd>(mut self, dir: &F, rights: &FileRights) -> io::Result<Self> {
+        let fd = dir.as_fd().as_raw_fd();
+        let access = self.access(rights);
+        if access != 0 {
+            let attr = PathBeneathAttr {
+                allowed_access: access,
+                parent_fd: fd,
+            };
+            let r = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    self.fd.as_raw_fd(),
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &attr as *const PathBeneathAttr,
+                    0u32,
+                )
+            };
+            if r < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let unexpressible = self.unexpressible_rights(rights);
+        if unexpressible != FileRights::new() {
+            self.unexpressible.push((fd, unexpressible));
+        }
+        Ok(self)
+    }
+
+    /// The rights passed to [`Ruleset::allow`] that this ruleset couldn't
+    /// express, with the descriptors that they were meant for.
+    ///
+    /// Rights with a Landlock equivalent are unexpressible if the running
+    /// kernel's Landlock is too old to support it.  Other rights are
+    /// unexpressible unless the seccomp emulation enforces them on the
+    /// directory descriptor itself.  For example, `Right::Seek` is fine, but
+    /// `Right::Fchflags` and `Right::MacSet` have no meaning on Linux.
+    pub fn unexpressible(&self) -> &[(RawFd, FileRights)] {
+        &self.unexpressible
+    }
+
+    /// Restrict the process with this ruleset when it enters capability
+    /// mode.  If it already has, restrict it now.
+    ///
+    /// The restriction is permanent, and inherited by children.  Registering
+    /// several rulesets stacks them, so that an access must be allowed by
+    /// every one.
+    ///
+    /// Landlock only restricts the calling thread, and the threads that it
+    /// starts afterwards.  So the process must have a single thread at that
+    /// point, or [`enter`](crate::enter) fails.  And since threads can't be
+    /// counted in capability mode, restricting the process after it entered
+    /// always fails.
+    ///
+    /// # Errors
+    ///
+    /// * `ECAPMODE` - The process has already entered capability mode.
+    pub fn restrict_on_enter(self) -> io::Result<()> {
+        if capmode::sandboxed() {
+            restrict(&self.fd)
+        } else {
+            PENDING
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(self.fd);
+            Ok(())
+        }
+    }
+
+    fn access(&self, rights: &FileRights) -> u64 {
+        ACCESS
+            .iter()
+            .filter(|(right, _, abi)| rights.is_set(*right) && *abi <= self.abi)
+            .fold(0, |access, (_, a, _)| access | a)
+            & handled(self.abi)
+    }
+
+    fn unexpressible_rights(&self, rights: &FileRights) -> FileRights {
+        let mut left = rights.0;
+        // Landlock never restricts lookups.
+        left.clear(sys::CAP_LOOKUP);
+        let mut landlocked = sys::cap_rights_t::new();
+        for &(right, _, abi) in ACCESS {
+            landlocked.set(right as u64);
+            if abi <= self.abi {
+                left.clear(right as u64);
+            }
+        }
+        let mut emulated = rights::emulated();
+        unsafe { sys::cap_rights_remove(&mut emulated, &landlocked) };
+        unsafe { sys::cap_rights_remove(&mut left, &emulated) };
+        FileRights(left)
+    }
+}
+
+fn restrict(ruleset: &OwnedFd) -> io::Result<()> {
+    // landlock_restrict_self(2) only restricts the calling thread, and threads
+    // started later.
+    if threads()? > 1 {
+        return Err(io::Error::from_raw_os_error(libc::EBUSY));
+    }
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let r: c_long = libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32);
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    RESTRICTED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The number of threads in the process.  In capability mode, `/proc` is out
+/// of reach, so they can't be counted.
+fn threads() -> io::Result<usize> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:")?.trim().parse().ok())
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+/// Has a ruleset been applied to the process?
+///
+/// The kernel doesn't say, so only rulesets applied by this crate count, and
+/// only until the process calls `exec`.
+pub(crate) fn restricted() -> bool {
+    RESTRICTED.load(Ordering::Relaxed)
+}
+
+/// Restrict the process with every pending ruleset.
+pub(crate) fn restrict_pending() -> io::Result<()> {
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    for ruleset in pending.iter() {
+        restrict(ruleset)?;
+    }
+    pending.clear();
+    Ok(())
+}
@@ -33,6 +33,7 @@
 //! ## Limit capability rights to files
 //!
 //! ```
+//! # #[cfg(target_os = "freebsd")] {
 //! use capsicum::{CapRights, Right, FileRights};
 //! use std::fs::File;
 //! use std::io::Read;
@@ -45,6 +46,7 @@
 //!     .limit(&ok_file).unwrap();
 //!
 //! assert!(ok_file.read_to_string(&mut s).is_ok());
+//! # }
 //! ```
 //!
 //! ## Opening new files in a subdirectory after entering capability mode
@@ -67,26 +69,109 @@
 //!  // But we can still open children of our already-open directory
 //!  let passwd = etc.open("passwd").unwrap();
 //! ```
+//!
+//! ## Linux
+//!
+//! Linux has no Capsicum, but this crate can emulate some of it.  On Linux,
+//! [`enter`] installs a seccomp-bpf filter that forbids syscalls that use the
+//! global namespaces, like `open(2)`, `bind(2)`, and `kill(2)` of other
+//! processes.  Operations on already-open descriptors, including the `*at`
+//! family of syscalls, are still allowed.  Forbidden syscalls fail with
+//! `EPERM`.
+//!
+//! The emulation is an approximation.  A seccomp filter can only inspect a
+//! syscall's arguments, not the memory that they point to.  So, for example,
+//! it cannot prevent `openat(2)` from following an absolute path or `..` out
+//! of its directory.  Nor can it tell the caller's own process ID from
+//! another's, so unlike on FreeBSD, `kill(2)` may only be passed a pid of 0,
+//! which signals the caller's whole process group.  A sandboxed process that
+//! shouldn't be able to signal its parent needs a process group of its own.
+//! Thread-directed signals, like `tgkill(2)`, are forbidden outright, so
+//! `raise(3)` fails, though `abort(3)` still ends the process.
+//!
+//! [`FileRights`], [`FcntlRights`], and [`IoctlRights`] are emulated too.
+//! Limits are recorded when they are applied, but they are only enforced
+//! once the process enters capability mode.  After that, new limits take
+//! effect immediately, each by installing another seccomp filter.  Since
+//! filters can't be removed, that can happen at most 32 times; after that,
+//! limiting a descriptor fails with `ENOMEM`.  New descriptors that
+//! [`recv_fds`] and [`try_clone_with_rights`] limit are cheaper: they're
+//! moved to a range of 32 descriptor numbers, all limited by one filter, that
+//! they share with other new descriptors with the same limits.
+//!
+//! Emulated limits follow the descriptor number, not the open file.  So in
+//! capability mode, a limited descriptor can't be duplicated, since the
+//! duplicate would escape the limits.  Nor can it be closed, except by
+//! `exec`, since a new descriptor would inherit the limits along with the
+//! number, unless it's in one of those ranges.  For the same reason, once
+//! any limits are enforced, no descriptor can be sent over a Unix-domain
+//! socket.  Limits aren't received along with a descriptor, either, so
+//! [`recv_fds`] applies the limits that the sender claims.  And since a
+//! seccomp filter can't read `openat2(2)`'s flags, that syscall fails with
+//! `ENOSYS` on limited directories, so that libc falls back to `openat(2)`.
+//!
+//! For directories, the [`landlock`] module can back up the seccomp filter
+//! with a Landlock ruleset, which does restrict the paths that `openat(2)`
+//! and friends can reach.
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
-#[cfg(feature = "casper")]
+pub mod audit;
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "casper")))]
 pub mod casper;
 mod common;
+mod error;
 mod fcntl;
+mod fdpass;
+pub mod fs;
 mod ioctl;
-mod process;
+#[cfg(target_os = "linux")]
+#[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+pub mod landlock;
+pub mod limited;
+mod limits;
+#[cfg(target_os = "linux")]
+mod linux;
+pub mod logfile;
+#[cfg(feature = "manifest")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manifest")))]
+pub mod manifest;
+mod operations;
+#[cfg(feature = "policy")]
+#[cfg_attr(docsrs, doc(cfg(feature = "policy")))]
+pub mod policy;
+#[cfg(feature = "privsep")]
+#[cfg_attr(docsrs, doc(cfg(feature = "privsep")))]
+pub mod privsep;
+pub mod process;
 mod right;
+mod sandbox;
+mod selftest;
+mod stdio;
+mod trace;
 /// Deprecated utilities
 pub mod util;
+pub mod warm;
 
+pub use error::{CapError, CapErrorKind};
 #[allow(deprecated)]
 pub use fcntl::FcntlsBuilder;
 pub use fcntl::{Fcntl, FcntlRights};
+pub use fdpass::{recv_fds, send_fds};
 pub use ioctl::{IoctlRights, IoctlsBuilder};
-pub use process::{enter, get_mode, sandboxed};
+// The FreeBSD libc interfaces for Capsicum, or their Linux emulation.
+#[cfg(target_os = "freebsd")]
+use libc as sys;
+pub use limited::Limited;
+pub use limits::{try_clone_with_rights, FdLimits};
+pub use process::{enter, enter_strict, get_mode, mode, sandboxed, Mode, ProcessDescriptor};
 #[allow(deprecated)]
 pub use right::RightsBuilder;
 pub use right::{FileRights, Right};
+pub use sandbox::{Sandbox, SandboxFds};
+pub use selftest::{selftest, SelftestCheck, SelftestOutcome, SelftestReport};
+pub use stdio::{limit_stdio, StdioPolicy};
 
 pub use crate::common::CapRights;
+#[cfg(target_os = "linux")]
+use crate::linux::sys;
@@ -0,0 +1,233 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! File descriptors whose capability rights are encoded in their type.
+//!
+//! A [`Limited`] wraps a descriptor together with a type-level rights set,
+//! such as [`ReadOnly`].  The rights are applied with [`FileRights::limit`]
+//! when the wrapper is created, and the wrapper only implements
+//! [`Read`](std::io::Read), [`Write`](std::io::Write) and
+//! [`Seek`](std::io::Seek) when its rights set permits them.  So a function
+//! that accepts a `Limited<File, ReadOnly>` is known, at compile time, to be
+//! unable to write to or seek its argument.
+//!
+//! # Example
+//! ```
+//! use std::io::Read;
+//! use capsicum::limited::{Limited, ReadOnly, ReadSeek};
+//! # use tempfile::tempfile;
+//!
+//! fn consume(mut stream: Limited<std::fs::File, ReadOnly>) -> String {
+//!     let mut s = String::new();
+//!     stream.read_to_string(&mut s).unwrap();
+//!     s
+//! }
+//!
+//! let file = Limited::<_, ReadSeek>::new(tempfile().unwrap()).unwrap();
+//! let file = file.narrow::<ReadOnly>().unwrap();
+//! assert_eq!(consume(file), "");
+//! ```
+//!
+//! Rights may be narrowed, but never widened:
+//! ```compile_fail
+//! use capsicum::limited::{Limited, ReadOnly, ReadWrite};
+//! # use tempfile::tempfile;
+//!
+//! let file = Limited::<_, ReadOnly>::new(tempfile().unwrap()).unwrap();
+//! let file = file.narrow::<ReadWrite>().unwrap();
+//! ```
+//!
+//! And operations that the rights don't permit are rejected by the compiler:
+//! ```compile_fail
+//! use std::io::Write;
+//! use capsicum::limited::{Limited, ReadOnly};
+//! # use tempfile::tempfile;
+//!
+//! let mut file = Limited::<_, ReadOnly>::new(tempfile().unwrap()).unwrap();
+//! file.write_all(b"Hello, World!").unwrap();
+//! ```
+
+use std::{
+    fmt,
+    io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+};
+
+use crate::{common::CapRights, FileRights, Right};
+
+/// A type-level boolean, used to compare [`Rights`] sets.
+#[derive(Clone, Copy, Debug)]
+pub struct Flag<const B: bool>;
+
+/// Logical implication between two [`Flag`]s.
+///
+/// `Flag<A>: Implies<Flag<B>>` holds unless `A` is `true` and `B` is `false`.
+pub trait Implies<T> {}
+
+impl<const B: bool> Implies<Flag<B>> for Flag<false> {}
+impl Implies<Flag<true>> for Flag<true> {}
+
+/// A set of capability rights known at compile time.
+pub trait RightsSet {
+    /// The run-time equivalent of this set.
+    fn file_rights() -> FileRights;
+}
+
+/// A compile-time set of capability rights.
+///
+/// Each parameter grants the corresponding [`Right`]: `READ` grants
+/// [`Right::Read`], `WRITE` grants [`Right::Write`], and `SEEK` grants
+/// [`Right::Seek`].  No other rights are granted.  Most code should use one
+/// of the aliases, like [`ReadOnly`], rather than naming this type directly.
+#[derive(Clone, Copy, Debug)]
+pub struct Rights<const READ: bool, const WRITE: bool, const SEEK: bool>;
+
+impl<const READ: bool, const WRITE: bool, const SEEK: bool> RightsSet
+    for Rights<READ, WRITE, SEEK>
+{
+    fn file_rights() -> FileRights {
+        let mut rights = FileRights::new();
+        if READ {
+            rights.allow(Right::Read);
+        }
+        if WRITE {
+            rights.allow(Right::Write);
+        }
+        if SEEK {
+            rights.allow(Right::Seek);
+        }
+        rights
+    }
+}
+
+/// Implemented when every right in `Self` is also present in `R`.
+pub trait SubsetOf<R>: RightsSet {}
+
+impl<
+        const R1: bool,
+        const W1: bool,
+        const S1: bool,
+        const R2: bool,
+        const W2: bool,
+        const S2: bool,
+    > SubsetOf<Rights<R2, W2, S2>> for Rights<R1, W1, S1>
+where
+    Flag<R1>: Implies<Flag<R2>>,
+    Flag<W1>: Implies<Flag<W2>>,
+    Flag<S1>: Implies<Flag<S2>>,
+{
+}
+
+/// Only [`Right::Read`].
+pub type ReadOnly = Rights<true, false, false>;
+/// Only [`Right::Write`].
+pub type WriteOnly = Rights<false, true, false>;
+/// [`Right::Read`] and [`Right::Write`].
+pub type ReadWrite = Rights<true, true, false>;
+/// [`Right::Read`] and [`Right::Seek`].
+pub type ReadSeek = Rights<true, false, true>;
+/// [`Right::Write`] and [`Right::Seek`].
+pub type WriteSeek = Rights<false, true, true>;
+/// [`Right::Read`], [`Right::Write`], and [`Right::Seek`].
+pub type ReadWriteSeek = Rights<true, true, true>;
+
+/// A file descriptor limited to the rights set `R`.
+///
+/// The only way to construct one is with [`Limited::new`] or
+/// [`Limited::narrow`], both of which apply `R` with [`FileRights::limit`].
+/// See the [module documentation](self) for details.
+pub struct Limited<T: AsFd, R: RightsSet> {
+    inner: T,
+    _rights: PhantomData<R>,
+}
+
+impl<T: AsFd, R: RightsSet> Limited<T, R> {
+    /// Limit `inner` to the rights in `R` and wrap it.
+    ///
+    /// # Errors
+    ///
+    /// * `ENOTCAPABLE` - `inner` already lacks some of the rights in `R`.
+    pub fn new(inner: T) -> io::Result<Self> {
+        R::file_rights().limit(&inner)?;
+        Ok(Limited {
+            inner,
+            _rights: PhantomData,
+        })
+    }
+
+    /// Further limit the descriptor to `R2`, which must be a subset of `R`.
+    pub fn narrow<R2: SubsetOf<R>>(self) -> io::Result<Limited<T, R2>> {
+        Limited::new(self.inner)
+    }
+
+    /// Unwrap the descriptor.
+    ///
+    /// The rights remain limited; only the compile-time record of them is lost.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// The run-time equivalent of this descriptor's rights.
+    pub fn rights(&self) -> FileRights {
+        R::file_rights()
+    }
+}
+
+impl<T: AsFd, R: RightsSet> AsFd for Limited<T, R> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl<T: AsFd, R: RightsSet> AsRawFd for Limited<T, R> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_fd().as_raw_fd()
+    }
+}
+
+impl<T: AsFd + fmt::Debug, R: RightsSet> fmt::Debug for Limited<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Limited")
+            .field("inner", &self.inner)
+            .field("rights", &R::file_rights())
+            .finish()
+    }
+}
+
+impl<T: AsFd + Read, const WRITE: bool, const SEEK: bool> Read
+    for Limited<T, Rights<true, WRITE, SEEK>>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        self.inner.read_vectored(bufs)
+    }
+}
+
+impl<T: AsFd + Write, const READ: bool, const SEEK: bool> Write
+    for Limited<T, Rights<READ, true, SEEK>>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsFd + Seek, const READ: bool, const WRITE: bool> Seek
+    for Limited<T, Rights<READ, WRITE, true>>
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
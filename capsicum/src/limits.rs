@@ -0,0 +1,192 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::{
+    io,
+    os::fd::{AsFd, OwnedFd},
+};
+
+#[cfg(target_os = "linux")]
+use crate::trace;
+use crate::{common::CapRights, sys, FcntlRights, FileRights, IoctlRights};
+
+/// The complete set of capability limits of a file descriptor: its
+/// [`FileRights`], its [`FcntlRights`], and its [`IoctlRights`].
+///
+/// # Example
+/// ```
+/// # use capsicum::{CapRights, Fcntl, FcntlRights, FdLimits, FileRights, Right};
+/// # use tempfile::tempfile;
+/// let file = tempfile().unwrap();
+/// let mut limits = FdLimits::from(*FileRights::new().allow(Right::Read).allow(Right::Fcntl));
+/// limits.fcntls = Some(*FcntlRights::new().allow(Fcntl::GetFL));
+///
+/// limits.limit(&file).unwrap();
+/// assert_eq!(FdLimits::from_file(&file).unwrap(), limits);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FdLimits {
+    /// Allowed capability rights.
+    pub rights: FileRights,
+    /// Allowed fcntl commands, or `None` to leave them unlimited.
+    pub fcntls: Option<FcntlRights>,
+    /// Allowed ioctl commands.
+    pub ioctls: IoctlRights,
+}
+
+impl FdLimits {
+    /// Create a new `FdLimits` with the given rights, and with unlimited fcntl
+    /// and ioctl commands.
+    pub fn new(rights: FileRights) -> Self {
+        FdLimits {
+            rights,
+            fcntls: None,
+            ioctls: IoctlRights::Unlimited,
+        }
+    }
+
+    /// Retrieve the limits currently in effect for the given file.
+    pub fn from_file<F: AsFd>(f: &F) -> io::Result<FdLimits> {
+        let rights = FileRights::from_file(f)?;
+        let fcntls = Some(FcntlRights::from_file(f)?).filter(|fcntls| *fcntls != FcntlRights::ALL);
+        let ioctls = IoctlRights::from_file_unbounded(f)?;
+        Ok(FdLimits {
+            rights,
+            fcntls,
+            ioctls,
+        })
+    }
+
+    /// Checks if `self` allows everything that `other` allows.
+    ///
+    /// # Example
+    /// ```
+    /// # use capsicum::{FdLimits, FileRights, IoctlsBuilder, Right};
+    /// let rw = FdLimits::new(*FileRights::new().allow(Right::Read).allow(Right::Write));
+    /// let mut ro = FdLimits::new(*FileRights::new().allow(Right::Read));
+    /// assert!(rw.contains(&ro));
+    /// assert!(!ro.contains(&rw));
+    ///
+    /// ro.ioctls = IoctlsBuilder::new().finalize();
+    /// assert!(rw.contains(&ro));
+    /// ```
+    pub fn contains(&self, other: &FdLimits) -> bool {
+        let fcntls = match (&self.fcntls, &other.fcntls) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(mine), Some(theirs)) => mine.contains(theirs),
+        };
+        let ioctls = match (&self.ioctls, &other.ioctls) {
+            (IoctlRights::Unlimited, _) => true,
+            (IoctlRights::Limited(_), IoctlRights::Unlimited) => false,
+            (IoctlRights::Limited(mine), IoctlRights::Limited(theirs)) => {
+                theirs.iter().all(|cmd| mine.contains(cmd))
+            }
+        };
+        self.rights.contains(&other.rights) && fcntls && ioctls
+    }
+}
+
+impl From<FileRights> for FdLimits {
+    fn from(rights: FileRights) -> Self {
+        FdLimits::new(rights)
+    }
+}
+
+impl CapRights for FdLimits {
+    fn limit<F: AsFd>(&self, f: &F) -> io::Result<()> {
+        self.rights.limit(f)?;
+        if let Some(fcntls) = &self.fcntls {
+            fcntls.limit(f)?;
+        }
+        self.ioctls.limit(f)
+    }
+}
+
+/// Duplicate a file descriptor, and limit the duplicate's rights.
+///
+/// The original descriptor is unaffected.  This is the usual way to hand a
+/// less-privileged view of a descriptor to some other component.  After
+/// limiting the duplicate, this function checks that it carries exactly the
+/// requested limits.  Fcntl and ioctl commands that `limits` leaves unlimited
+/// are inherited from `f`, whatever they are.
+///
+/// On Linux, in capability mode, the duplicate is numbered from a range that
+/// it shares with other new descriptors with the same limits.  Each range
+/// holds 32 descriptors, and takes a seccomp filter of its own, of which
+/// there may be at most 32.
+///
+/// # Errors
+///
+/// * `ENOTCAPABLE` - `limits` would grant some right that `f` lacks.
+/// * `ENOTCAPABLE` - On Linux, in capability mode, `f` is limited, and its
+///   duplicate wouldn't be.
+/// * `EMFILE` - On Linux, in capability mode, the range for `limits` is full.
+/// * `ENOMEM` - On Linux, in capability mode, there's no room for another
+///   range.
+///
+/// # Example
+/// ```
+/// # use std::{fs::File, io::Write};
+/// # use capsicum::{FileRights, Right};
+/// # use tempfile::tempfile;
+/// let mut file = tempfile().unwrap();
+/// let rights = *FileRights::new().allow(Right::Read);
+/// let mut weak = File::from(capsicum::try_clone_with_rights(&file, &rights.into()).unwrap());
+///
+/// # #[cfg(target_os = "freebsd")] // Linux only enforces limits in capability mode
+/// weak.write_all(b"Hello, World!").unwrap_err();
+/// file.write_all(b"Hello, World!").unwrap();
+/// ```
+pub fn try_clone_with_rights<F: AsFd>(f: &F, limits: &FdLimits) -> io::Result<OwnedFd> {
+    let fd = f.as_fd().try_clone_to_owned()?;
+    #[cfg(target_os = "linux")]
+    let fd = limit_new(fd, Some(f.as_fd()), limits)?;
+    #[cfg(not(target_os = "linux"))]
+    limits.limit(&fd)?;
+    let actual = FdLimits::from_file(&fd)?;
+    let wanted = FdLimits {
+        rights: limits.rights,
+        fcntls: limits.fcntls.or(actual.fcntls),
+        ioctls: match &limits.ioctls {
+            IoctlRights::Unlimited => actual.ioctls.clone(),
+            ioctls => ioctls.clone(),
+        },
+    };
+    if actual == wanted {
+        Ok(fd)
+    } else {
+        Err(io::Error::from_raw_os_error(sys::ENOTCAPABLE))
+    }
+}
+
+/// Limit a new descriptor to `limits`, on top of those of `source`, if it's a
+/// duplicate, and return it.
+///
+/// Linux's emulated limits aren't inherited by duplicates, nor received with
+/// descriptors, so the new descriptor starts out unlimited.  In capability
+/// mode, it's moved to a range of descriptor numbers, rather than costing a
+/// filter of its own.
+#[cfg(target_os = "linux")]
+pub(crate) fn limit_new(
+    fd: OwnedFd,
+    source: Option<BorrowedFd<'_>>,
+    limits: &FdLimits,
+) -> io::Result<OwnedFd> {
+    use crate::linux::rights::{self, Limits};
+
+    let inherited = match source {
+        Some(source) => rights::get(source.as_raw_fd()).map_err(io::Error::from_raw_os_error)?,
+        None => Limits::default(),
+    };
+    let fd = rights::limit_new(fd, |new| {
+        *new = inherited;
+        new.limit_all(limits)
+    })
+    .map_err(io::Error::from_raw_os_error)?;
+    trace::limit(&fd, "rights", || limits.rights.names().join("|"), Ok(()))?;
+    Ok(fd)
+}
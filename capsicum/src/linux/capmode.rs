@@ -0,0 +1,215 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Emulation of capability mode with seccomp-bpf.
+
+use std::{fs, io};
+
+use libc::{c_int, c_long};
+
+use super::{
+    rights,
+    seccomp::{Cond, Filter, Rule},
+};
+use crate::{landlock, selftest, Mode};
+
+/// The errno returned for syscalls that capability mode forbids.  Linux has
+/// no `ECAPMODE`.
+pub(crate) const ECAPMODE: i32 = libc::EPERM;
+
+/// `prctl` option used to detect whether our filter is installed.  The kernel
+/// would reject it with `EINVAL`, but the filter answers with `PROBE_ERRNO`.
+/// Unlike a flag in memory, this survives `exec`.
+const PROBE_OPTION: u32 = 0x4341_5053; // "CAPS"
+const PROBE_ERRNO: i32 = libc::EALREADY;
+
+const AT_FDCWD: u32 = libc::AT_FDCWD as u32;
+
+/// Added in Linux 6.6.  Same number on every supported architecture.
+const SYS_FCHMODAT2: c_long = 452;
+
+/// Syscalls that only operate on the global namespaces.
+#[rustfmt::skip]
+const GLOBAL: &[c_long] = &[
+    #[cfg(target_arch = "x86_64")] libc::SYS_open,
+    #[cfg(target_arch = "x86_64")] libc::SYS_creat,
+    #[cfg(target_arch = "x86_64")] libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")] libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")] libc::SYS_access,
+    #[cfg(target_arch = "x86_64")] libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")] libc::SYS_mkdir,
+    #[cfg(target_arch = "x86_64")] libc::SYS_rmdir,
+    #[cfg(target_arch = "x86_64")] libc::SYS_unlink,
+    #[cfg(target_arch = "x86_64")] libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")] libc::SYS_link,
+    #[cfg(target_arch = "x86_64")] libc::SYS_symlink,
+    #[cfg(target_arch = "x86_64")] libc::SYS_chmod,
+    #[cfg(target_arch = "x86_64")] libc::SYS_chown,
+    #[cfg(target_arch = "x86_64")] libc::SYS_lchown,
+    #[cfg(target_arch = "x86_64")] libc::SYS_utime,
+    #[cfg(target_arch = "x86_64")] libc::SYS_utimes,
+    #[cfg(target_arch = "x86_64")] libc::SYS_mknod,
+    #[cfg(target_arch = "x86_64")] libc::SYS_uselib,
+    libc::SYS_statfs,
+    libc::SYS_truncate,
+    libc::SYS_chdir,
+    libc::SYS_chroot,
+    libc::SYS_acct,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_open_tree,
+    libc::SYS_move_mount,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_mount_setattr,
+    libc::SYS_pivot_root,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_execve,
+    libc::SYS_getxattr,
+    libc::SYS_lgetxattr,
+    libc::SYS_setxattr,
+    libc::SYS_lsetxattr,
+    libc::SYS_listxattr,
+    libc::SYS_llistxattr,
+    libc::SYS_removexattr,
+    libc::SYS_lremovexattr,
+    libc::SYS_inotify_add_watch,
+    libc::SYS_open_by_handle_at,
+    // Sockets may be created, but not named.
+    libc::SYS_bind,
+    libc::SYS_connect,
+    // Other processes may not be inspected or manipulated.
+    libc::SYS_pidfd_open,
+    libc::SYS_pidfd_getfd,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_kcmp,
+    // Thread-directed signals name a thread group, and the filter can't tell
+    // whether it's the caller's own.
+    libc::SYS_tkill,
+    libc::SYS_tgkill,
+    libc::SYS_rt_tgsigqueueinfo,
+    // io_uring operations bypass seccomp entirely, and legacy AIO takes its
+    // descriptors from memory, out of the filter's reach.
+    libc::SYS_io_uring_setup,
+    libc::SYS_io_setup,
+    libc::SYS_io_submit,
+];
+
+/// Syscalls that look up paths relative to a directory descriptor, and the
+/// positions of their directory arguments.  Using `AT_FDCWD` is forbidden.
+#[rustfmt::skip]
+const AT: &[(c_long, u8)] = &[
+    #[cfg(target_arch = "x86_64")] (libc::SYS_futimesat, 0),
+    #[cfg(target_arch = "x86_64")] (libc::SYS_renameat, 0),
+    #[cfg(target_arch = "x86_64")] (libc::SYS_renameat, 2),
+    (libc::SYS_openat, 0),
+    (libc::SYS_openat2, 0),
+    (libc::SYS_newfstatat, 0),
+    (libc::SYS_statx, 0),
+    (libc::SYS_faccessat, 0),
+    (libc::SYS_faccessat2, 0),
+    (libc::SYS_mkdirat, 0),
+    (libc::SYS_mknodat, 0),
+    (libc::SYS_fchownat, 0),
+    (libc::SYS_fchmodat, 0),
+    (SYS_FCHMODAT2, 0),
+    (libc::SYS_unlinkat, 0),
+    (libc::SYS_renameat2, 0),
+    (libc::SYS_renameat2, 2),
+    (libc::SYS_linkat, 0),
+    (libc::SYS_linkat, 2),
+    (libc::SYS_symlinkat, 1),
+    (libc::SYS_readlinkat, 0),
+    (libc::SYS_utimensat, 0),
+    (libc::SYS_execveat, 0),
+    (libc::SYS_name_to_handle_at, 0),
+    (libc::SYS_fanotify_mark, 3),
+];
+
+/// Syscalls that signal processes, and the positions of their pid arguments.
+///
+/// The filter is inherited by children, so it can't tell the caller's own pid
+/// from another's.  Only pid 0, the caller's process group, is allowed.
+const SIGNAL: &[(c_long, u8)] = &[(libc::SYS_kill, 0), (libc::SYS_rt_sigqueueinfo, 0)];
+
+/// The rules that emulate capability mode.
+pub(crate) fn rules() -> Vec<Rule> {
+    let mut rules = Vec::new();
+    rules.extend(GLOBAL.iter().map(|&nr| Rule::deny(nr, ECAPMODE)));
+    rules.extend(
+        AT.iter()
+            .map(|&(nr, arg)| Rule::deny(nr, ECAPMODE).when(arg, Cond::Eq(AT_FDCWD))),
+    );
+    rules.extend(
+        SIGNAL
+            .iter()
+            .map(|&(nr, arg)| Rule::deny(nr, ECAPMODE).when(arg, Cond::Ne(0))),
+    );
+    // Datagrams may only be sent to a socket's connected peer.
+    rules.push(Rule::deny(libc::SYS_sendto, ECAPMODE).when(4, Cond::NonZero));
+    rules.push(Rule::deny(libc::SYS_prctl, PROBE_ERRNO).when(0, Cond::Eq(PROBE_OPTION)));
+    rules
+}
+
+pub(crate) fn enter() -> io::Result<()> {
+    landlock::restrict_pending()?;
+    if !sandboxed() {
+        selftest::baseline();
+    }
+    rights::enforce(|fd_rules| {
+        let mut filter = Filter::new();
+        filter.extend(rules());
+        filter.extend(fd_rules);
+        filter.compile()?.install()
+    })
+}
+
+pub(crate) fn sandboxed() -> bool {
+    get_mode().unwrap_or(false)
+}
+
+pub(crate) fn get_mode() -> io::Result<bool> {
+    let r = unsafe { libc::prctl(libc::PR_GET_SECCOMP, 0, 0, 0, 0) };
+    if r < 0 {
+        // The kernel was built without CONFIG_SECCOMP.
+        return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+    }
+    Ok(r > 0 && probe())
+}
+
+pub(crate) fn mode() -> Mode {
+    // In capability mode, /proc is out of reach, but prctl still works.
+    let status = fs::read_to_string("/proc/self/status").ok();
+    let field = |name: &str| {
+        status.as_deref().and_then(|status| {
+            status.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                value.trim().parse::<c_int>().ok()
+            })
+        })
+    };
+    let seccomp = field("Seccomp")
+        .unwrap_or_else(|| unsafe { libc::prctl(libc::PR_GET_SECCOMP, 0, 0, 0, 0) });
+    let no_new_privs = field("NoNewPrivs")
+        .unwrap_or_else(|| unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) });
+    // Unprivileged processes can only be restricted with no_new_privs set.
+    let landlock = no_new_privs > 0 && landlock::abi().is_ok() && landlock::restricted();
+    match (seccomp > 0, landlock) {
+        (false, false) => Mode::None,
+        (true, false) => Mode::SeccompFilter,
+        (false, true) => Mode::Landlock,
+        (true, true) => Mode::Multiple,
+    }
+}
+
+/// Is our own filter installed?
+fn probe() -> bool {
+    let r = unsafe { libc::prctl(PROBE_OPTION as i32, 0, 0, 0, 0) };
+    r < 0 && io::Error::last_os_error().raw_os_error() == Some(PROBE_ERRNO)
+}
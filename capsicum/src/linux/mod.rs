@@ -0,0 +1,25 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The Linux backend.
+//!
+//! Linux has no Capsicum, so capability mode is emulated with a seccomp-bpf
+//! filter.  The filter forbids syscalls that use the global namespaces, like
+//! `open(2)`, `bind(2)`, and `kill(2)` of other processes, and forbids the
+//! `*at` syscalls from using `AT_FDCWD`.  Operations on already-open
+//! descriptors are still allowed.
+//!
+//! The emulation is only an approximation.  A seccomp filter can only
+//! inspect a syscall's arguments, not the memory that they point to.  So, for
+//! example, it cannot prevent `openat(2)` from following an absolute path or
+//! `..` out of its directory, nor `sendmsg(2)` from naming a destination
+//! address.
+//!
+//! Per-descriptor rights, fcntl rights, and ioctl rights are emulated, too,
+//! by further seccomp rules that match a syscall's descriptor argument.
+
+pub(crate) mod capmode;
+pub(crate) mod rights;
+pub(crate) mod seccomp;
+pub(crate) mod sys;
@@ -0,0 +1,611 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Per-descriptor limits, enforced with seccomp.
+//!
+//! Linux has no per-descriptor rights, but a seccomp filter can match the
+//! descriptor number that a syscall receives.  Limits are recorded in a
+//! process-wide registry.  When the process enters capability mode, every
+//! recorded limit is compiled into the same filter as capability mode itself.
+//! Limits applied after that are enforced immediately, by a filter of their
+//! own.  Filters can't be removed, and each one slows down every syscall, so
+//! only [`MAX_LATE_FILTERS`] of those may be installed.
+//!
+//! New descriptors, like received ones, needn't each cost a filter, though.
+//! Instead, they're moved into a [`Range`] of descriptor numbers whose filter
+//! enforces the same limits on every number in it.
+//!
+//! Since the kernel knows nothing of these limits, they would not be
+//! inherited by duplicates of a descriptor.  So once they're enforced,
+//! limited descriptors can't be duplicated with `dup` or `fcntl`.  Nor can
+//! any descriptor be sent over a socket with `sendmsg` or `sendmmsg`, since
+//! the filter can't see which descriptors a message carries, and the
+//! receiver's copy would be unlimited.  Capability mode forbids the other
+//! ways to copy a descriptor, like `pidfd_getfd`, outright.
+//!
+//! And since the filter matches descriptor numbers, a limited descriptor
+//! can't be closed once its limits are enforced, or a new descriptor could
+//! take its number, and its limits.  The one exception is `exec`, which
+//! still closes limited descriptors that are close-on-exec.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+        MutexGuard,
+    },
+};
+
+use libc::{c_int, c_long};
+
+use super::{
+    capmode,
+    seccomp::{Cond, Filter, Rule},
+    sys::{self, cap_rights_t, u_long, ENOTCAPABLE},
+};
+use crate::{operations::OPERATIONS, selftest, FdLimits, IoctlRights};
+
+/// Everything that may be limited about one descriptor.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Limits {
+    pub rights: cap_rights_t,
+    pub fcntls: u32,
+    pub ioctls: Option<Vec<u_long>>,
+}
+
+impl Limits {
+    fn is_unlimited(&self) -> bool {
+        self.rights == cap_rights_t::ALL
+            && self.fcntls == sys::CAP_FCNTL_ALL
+            && self.ioctls.is_none()
+    }
+
+    pub fn limit_rights(&mut self, rights: &cap_rights_t) -> Result<(), c_int> {
+        if !self.rights.contains(rights) {
+            return Err(ENOTCAPABLE);
+        }
+        self.rights = *rights;
+        Ok(())
+    }
+
+    pub fn limit_fcntls(&mut self, fcntls: u32) -> Result<(), c_int> {
+        if fcntls & !sys::CAP_FCNTL_ALL != 0 {
+            return Err(libc::EINVAL);
+        }
+        if self.fcntls & fcntls != fcntls {
+            return Err(ENOTCAPABLE);
+        }
+        self.fcntls = fcntls;
+        Ok(())
+    }
+
+    pub fn limit_ioctls(&mut self, cmds: &[u_long]) -> Result<(), c_int> {
+        match &self.ioctls {
+            Some(old) if !cmds.iter().all(|cmd| old.contains(cmd)) => Err(ENOTCAPABLE),
+            _ => {
+                self.ioctls = Some(cmds.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    /// Apply all of `limits` at once, like [`CapRights::limit`] would one by
+    /// one.
+    ///
+    /// [`CapRights::limit`]: crate::CapRights::limit
+    pub fn limit_all(&mut self, limits: &FdLimits) -> Result<(), c_int> {
+        self.limit_rights(&limits.rights.0)?;
+        if let Some(fcntls) = &limits.fcntls {
+            self.limit_fcntls(fcntls.0)?;
+        }
+        if let IoctlRights::Limited(cmds) = &limits.ioctls {
+            self.limit_ioctls(cmds)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            rights: cap_rights_t::ALL,
+            fcntls: sys::CAP_FCNTL_ALL,
+            ioctls: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Device and inode of the file.
+    id: (u64, u64),
+    limits: Limits,
+    /// Has the kernel been told about these limits?
+    enforced: bool,
+}
+
+/// The most filters that limits applied in capability mode may install,
+/// ranges' filters included.
+pub(crate) const MAX_LATE_FILTERS: usize = 32;
+
+/// How many such filters have been installed.  Only updated with the
+/// registry locked.
+static LATE_FILTERS: AtomicUsize = AtomicUsize::new(0);
+
+static REGISTRY: Mutex<BTreeMap<RawFd, Entry>> = Mutex::new(BTreeMap::new());
+
+fn registry() -> MutexGuard<'static, BTreeMap<RawFd, Entry>> {
+    // The registry's invariants hold even if a panic interrupted an update.
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// How many descriptor numbers a range holds.
+const RANGE_LEN: RawFd = 32;
+
+/// Ranges are placed below this number, or below the limit on open
+/// descriptors if that's lower.  Any higher, and the kernel would grow the
+/// descriptor table for nothing.
+const RANGES_TOP: RawFd = 4096;
+
+/// [`RANGE_LEN`] descriptor numbers, starting with `lo`, whose limits are
+/// enforced by a single filter, installed in capability mode.
+///
+/// The filter applies to any descriptor that takes one of those numbers, so
+/// unlike other limited descriptors, these may be closed.  Ranges are placed
+/// in the upper half of the numbers available, out of the way of descriptors
+/// allocated as usual.
+#[derive(Debug)]
+struct Range {
+    lo: RawFd,
+    limits: Limits,
+}
+
+impl Range {
+    fn holds(&self, fd: RawFd) -> bool {
+        (self.lo..self.lo + RANGE_LEN).contains(&fd)
+    }
+}
+
+/// Only locked with the registry locked.
+static RANGES: Mutex<Vec<Range>> = Mutex::new(Vec::new());
+
+fn ranges() -> MutexGuard<'static, Vec<Range>> {
+    RANGES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The limits of the range that holds a descriptor, if any.
+fn range_limits(fd: RawFd) -> Option<Limits> {
+    ranges()
+        .iter()
+        .find(|range| range.holds(fd))
+        .map(|range| range.limits.clone())
+}
+
+fn errno() -> c_int {
+    io::Error::last_os_error().raw_os_error().unwrap()
+}
+
+// Not in libc for every architecture, but the same on each.
+const F_SETSIG: c_int = 10;
+const F_GETSIG: c_int = 11;
+const F_SETOWN_EX: c_int = 15;
+const F_GETOWN_EX: c_int = 16;
+
+/// Mark the open file description of a limited descriptor, so that a new
+/// description that reuses the same descriptor number can be told apart.
+///
+/// The mark is the signal sent when I/O is possible on an `O_ASYNC`
+/// descriptor.  New descriptions use 0, which means `SIGIO`; marked ones use
+/// `SIGIO` explicitly.  The only difference is that the latter's signal
+/// handler receives extra information.
+fn mark(fd: RawFd) -> Result<(), c_int> {
+    let r = unsafe { libc::fcntl(fd, F_GETSIG) };
+    if r < 0 || (r == 0 && unsafe { libc::fcntl(fd, F_SETSIG, libc::SIGIO) } < 0) {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+fn identify(fd: RawFd) -> Result<(u64, u64), c_int> {
+    let mut sb = unsafe { mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut sb) } < 0 {
+        Err(errno())
+    } else {
+        Ok((sb.st_dev, sb.st_ino))
+    }
+}
+
+/// Does this entry still describe the descriptor?  File systems may reuse an
+/// inode number as soon as a file is deleted, so checking the inode alone
+/// isn't enough.
+fn is_current(fd: RawFd, entry: &Entry) -> bool {
+    // Once enforced, the descriptor can't be closed, so its number can't be
+    // reused.  Besides, fstat may be forbidden by then.
+    entry.enforced || (unsafe { libc::fcntl(fd, F_GETSIG) } > 0 && identify(fd) == Ok(entry.id))
+}
+
+/// Check that a descriptor is open, with a syscall that limits never forbid.
+fn check_open(fd: RawFd) -> Result<(), c_int> {
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+/// Retrieve the limits of a descriptor.
+pub(crate) fn get(fd: RawFd) -> Result<Limits, c_int> {
+    check_open(fd)?;
+    let registry = registry();
+    match registry.get(&fd) {
+        Some(entry) if is_current(fd, entry) => Ok(entry.limits.clone()),
+        _ => Ok(range_limits(fd).unwrap_or_default()),
+    }
+}
+
+/// Forget the limits recorded for a new descriptor.  Its number may have
+/// belonged to a limited duplicate of the same open file, whose entry would
+/// otherwise seem current.  Limits that are already enforced can't be
+/// forgotten.
+pub(crate) fn forget(fd: RawFd) {
+    let mut registry = registry();
+    if registry.get(&fd).is_some_and(|entry| !entry.enforced) {
+        registry.remove(&fd);
+    }
+}
+
+/// Update the limits of a descriptor.
+///
+/// `f` is responsible for checking that the new limits don't exceed the old
+/// ones.
+///
+/// # Errors
+///
+/// * `ENOMEM` - In capability mode, [`MAX_LATE_FILTERS`] filters have already
+///   been installed.
+pub(crate) fn limit<F>(fd: RawFd, f: F) -> Result<(), c_int>
+where
+    F: FnOnce(&mut Limits) -> Result<(), c_int>,
+{
+    check_open(fd)?;
+    let mut registry = registry();
+    // Marking this descriptor will also mark its duplicates.  So first,
+    // forget stale entries that a duplicate might seem to revive.
+    registry.retain(|fd, entry| is_current(*fd, entry));
+    let (id, old, enforced_old) = match (registry.get(&fd), range_limits(fd)) {
+        (Some(entry), _) => (entry.id, entry.limits.clone(), entry.enforced),
+        // Ranges only exist in capability mode, where the entry will be
+        // enforced, so its id won't matter.  The range's filter is in force,
+        // but the rules for this number alone aren't.
+        (None, Some(limits)) => ((0, 0), limits, false),
+        (None, None) => (identify(fd)?, Limits::default(), false),
+    };
+    let mut limits = old.clone();
+    f(&mut limits)?;
+    mark(fd)?;
+    let enforced = capmode::sandboxed();
+    if enforced {
+        selftest::baseline_fd(fd, fd, |right| {
+            old.rights.is_set(right as u64) && !limits.rights.is_set(right as u64)
+        });
+        // The old limits' rules are in force already, if they were enforced.
+        let old = if enforced_old { old } else { Limits::default() };
+        let old = rules(&[(Fds::One(fd), &old)]);
+        let new = rules(&[(Fds::One(fd), &limits)])
+            .into_iter()
+            .filter(|rule| !old.contains(rule))
+            .collect::<Vec<_>>();
+        if !new.is_empty() {
+            install_late(new)?;
+        }
+    }
+    registry.insert(
+        fd,
+        Entry {
+            id,
+            limits,
+            enforced,
+        },
+    );
+    Ok(())
+}
+
+/// Install a filter after entering capability mode, if there's room for
+/// another.
+fn install_late(rules: Vec<Rule>) -> Result<(), c_int> {
+    let late = LATE_FILTERS.load(Ordering::Relaxed);
+    if late >= MAX_LATE_FILTERS {
+        return Err(libc::ENOMEM);
+    }
+    let mut filter = Filter::new();
+    filter.extend(rules);
+    filter
+        .compile()
+        .and_then(|prog| prog.install())
+        .map_err(|e| e.raw_os_error().unwrap_or(libc::EINVAL))?;
+    LATE_FILTERS.store(late + 1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Limit a new descriptor, which nothing refers to by number yet, and return
+/// it.
+///
+/// Outside of capability mode, that's the same as [`limit`].  In capability
+/// mode, rather than install a filter for the descriptor's number, this moves
+/// the descriptor into a [`Range`] whose filter already enforces the same
+/// limits, or into a new one.  So any number of new descriptors with the
+/// same limits cost only one filter between them.
+///
+/// # Errors
+///
+/// * `EMFILE` - The range for these limits is full.
+/// * `ENOMEM` - A new range was needed, but [`MAX_LATE_FILTERS`] filters have
+///   already been installed, or there's no room left for one.
+pub(crate) fn limit_new<F>(fd: OwnedFd, f: F) -> Result<OwnedFd, c_int>
+where
+    F: FnOnce(&mut Limits) -> Result<(), c_int>,
+{
+    forget(fd.as_raw_fd());
+    if !capmode::sandboxed() {
+        limit(fd.as_raw_fd(), f)?;
+        return Ok(fd);
+    }
+    let mut limits = Limits::default();
+    f(&mut limits)?;
+    if limits.is_unlimited() {
+        return Ok(fd);
+    }
+    let _registry = registry();
+    let mut ranges = ranges();
+    let lo = match ranges.iter().find(|range| range.limits == limits) {
+        Some(range) => range.lo,
+        None => {
+            let lo = reserve(&ranges)?;
+            install_late(rules(&[(Fds::Range(lo, lo + RANGE_LEN - 1), &limits)]))?;
+            ranges.push(Range {
+                lo,
+                limits: limits.clone(),
+            });
+            lo
+        }
+    };
+    // fcntl will pick the lowest free number in the range.
+    let Some(at) = (lo..lo + RANGE_LEN).find(|fd| check_open(*fd).is_err()) else {
+        return Err(libc::EMFILE);
+    };
+    selftest::baseline_fd(fd.as_raw_fd(), at, |right| {
+        !limits.rights.is_set(right as u64)
+    });
+    let cloexec = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) } & libc::FD_CLOEXEC;
+    let cmd = if cloexec != 0 {
+        libc::F_DUPFD_CLOEXEC
+    } else {
+        libc::F_DUPFD
+    };
+    let moved = unsafe { libc::fcntl(fd.as_raw_fd(), cmd, at) };
+    if moved < 0 {
+        return Err(errno());
+    }
+    let moved = unsafe { OwnedFd::from_raw_fd(moved) };
+    if !(lo..lo + RANGE_LEN).contains(&moved.as_raw_fd()) {
+        // Another thread took the last free number.
+        return Err(libc::EMFILE);
+    }
+    Ok(moved)
+}
+
+/// Find room for a new range, below every other one, where no descriptor is
+/// open.
+fn reserve(ranges: &[Range]) -> Result<RawFd, c_int> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } < 0 {
+        return Err(errno());
+    }
+    let top = rlim.rlim_cur.min(RANGES_TOP as libc::rlim_t) as RawFd;
+    let mut hi = ranges.iter().map(|range| range.lo).min().unwrap_or(top);
+    while hi - RANGE_LEN >= top / 2 {
+        let lo = hi - RANGE_LEN;
+        if (lo..hi).all(|fd| check_open(fd).is_err()) {
+            return Ok(lo);
+        }
+        hi = lo;
+    }
+    Err(libc::ENOMEM)
+}
+
+/// Compile every recorded limit into seccomp rules, and pass them to
+/// `install`.  If that succeeds, the limits are considered enforced.
+///
+/// Descriptors that were closed since they were limited are forgotten.
+pub(crate) fn enforce<F>(install: F) -> io::Result<()>
+where
+    F: FnOnce(Vec<Rule>) -> io::Result<()>,
+{
+    let mut registry = registry();
+    registry.retain(|fd, entry| is_current(*fd, entry));
+    for (fd, entry) in registry.iter().filter(|(_, entry)| !entry.enforced) {
+        selftest::baseline_fd(*fd, *fd, |right| !entry.limits.rights.is_set(right as u64));
+    }
+    let live = registry
+        .iter()
+        .map(|(fd, entry)| (Fds::One(*fd), &entry.limits))
+        .collect::<Vec<_>>();
+    install(rules(&live))?;
+    for entry in registry.values_mut() {
+        entry.enforced = true;
+    }
+    Ok(())
+}
+
+/// fcntl commands that require a right, and the fcntl right that they
+/// require, if any.
+#[rustfmt::skip]
+const FCNTLS: &[(c_int, u64, u32)] = &[
+    (libc::F_GETFL, sys::CAP_FCNTL, sys::CAP_FCNTL_GETFL),
+    (libc::F_SETFL, sys::CAP_FCNTL, sys::CAP_FCNTL_SETFL),
+    (libc::F_GETOWN, sys::CAP_FCNTL, sys::CAP_FCNTL_GETOWN),
+    (F_GETOWN_EX, sys::CAP_FCNTL, sys::CAP_FCNTL_GETOWN),
+    (libc::F_SETOWN, sys::CAP_FCNTL, sys::CAP_FCNTL_SETOWN),
+    (F_SETOWN_EX, sys::CAP_FCNTL, sys::CAP_FCNTL_SETOWN),
+    (libc::F_GETLK, sys::CAP_FLOCK, 0),
+    (libc::F_SETLK, sys::CAP_FLOCK, 0),
+    (libc::F_SETLKW, sys::CAP_FLOCK, 0),
+    (libc::F_OFD_GETLK, sys::CAP_FLOCK, 0),
+    (libc::F_OFD_SETLK, sys::CAP_FLOCK, 0),
+    (libc::F_OFD_SETLKW, sys::CAP_FLOCK, 0),
+];
+
+/// fcntl commands that duplicate a descriptor.
+const DUPFD: &[u32] = &[libc::F_DUPFD as u32, libc::F_DUPFD_CLOEXEC as u32];
+
+/// Every right that the emulation enforces.
+pub(crate) fn emulated() -> cap_rights_t {
+    let mut rights = cap_rights_t::new();
+    for op in OPERATIONS.iter().filter(|op| op.syscall.is_some()) {
+        rights.set(op.right as u64);
+    }
+    for &(_, right, _) in FCNTLS {
+        rights.set(right);
+    }
+    rights
+}
+
+/// Descriptors that share the same limits.
+#[derive(Clone, Copy, Debug)]
+enum Fds {
+    /// A single descriptor.  It can't be closed, lest a new descriptor take
+    /// its number and its limits.
+    One(RawFd),
+    /// Every descriptor numbered from one number to another, inclusive.
+    Range(RawFd, RawFd),
+}
+
+impl Fds {
+    /// The conditions on a descriptor argument that match these descriptors.
+    fn conds(self) -> Vec<Cond> {
+        match self {
+            Fds::One(fd) => vec![Cond::Eq(fd as u32)],
+            Fds::Range(lo, hi) => vec![Cond::Ge(lo as u32), Cond::Le(hi as u32)],
+        }
+    }
+}
+
+/// Add conditions on argument number `arg` to a rule.
+fn on(rule: Rule, arg: u8, conds: &[Cond]) -> Rule {
+    conds
+        .iter()
+        .fold(rule, |rule, cond| rule.when(arg, cond.clone()))
+}
+
+/// Compile the limits of some descriptors into seccomp rules.
+fn rules(limits: &[(Fds, &Limits)]) -> Vec<Rule> {
+    let ones_where = |pred: &dyn Fn(&Limits) -> bool| -> Vec<u32> {
+        limits
+            .iter()
+            .filter_map(|(fds, l)| match fds {
+                Fds::One(fd) if pred(l) => Some(*fd as u32),
+                _ => None,
+            })
+            .collect()
+    };
+    // The conditions of each rule needed to match every descriptor whose
+    // limits `pred` selects.
+    let fds_where = |pred: &dyn Fn(&Limits) -> bool| -> Vec<Vec<Cond>> {
+        let mut conds = vec![vec![Cond::In(ones_where(pred))]];
+        conds.extend(limits.iter().filter_map(|(fds, l)| match fds {
+            Fds::Range(..) if pred(l) => Some(fds.conds()),
+            _ => None,
+        }));
+        conds
+    };
+    let mut rules = Vec::new();
+    // Keep limited descriptors open, so that their numbers aren't reused.
+    let limited = ones_where(&|l| !l.is_unlimited());
+    rules.push(Rule::deny(libc::SYS_close, ENOTCAPABLE).when(0, Cond::In(limited.clone())));
+    #[cfg(target_arch = "x86_64")]
+    rules.push(Rule::deny(libc::SYS_dup2, ENOTCAPABLE).when(1, Cond::In(limited.clone())));
+    rules.push(Rule::deny(libc::SYS_dup3, ENOTCAPABLE).when(1, Cond::In(limited.clone())));
+    if let (Some(&min), Some(&max)) = (limited.iter().min(), limited.iter().max()) {
+        rules.push(
+            Rule::deny(libc::SYS_close_range, ENOTCAPABLE)
+                .when(0, Cond::Le(max))
+                .when(1, Cond::Ge(min)),
+        );
+    }
+    // Duplicates wouldn't inherit the limits.
+    for conds in fds_where(&|l| !l.is_unlimited()) {
+        let deny = |nr: c_long| on(Rule::deny(nr, ENOTCAPABLE), 0, &conds);
+        rules.push(deny(libc::SYS_dup));
+        #[cfg(target_arch = "x86_64")]
+        rules.push(deny(libc::SYS_dup2));
+        rules.push(deny(libc::SYS_dup3));
+        rules.push(deny(libc::SYS_fcntl).when(1, Cond::In(DUPFD.to_vec())));
+    }
+    // Nor would copies sent over a socket, and the filter can't see which
+    // descriptors a message carries.  So none may be sent at all.
+    if limits.iter().any(|(_, l)| !l.is_unlimited()) {
+        rules.push(Rule::deny(libc::SYS_sendmsg, ENOTCAPABLE));
+        rules.push(Rule::deny(libc::SYS_sendmmsg, ENOTCAPABLE));
+    }
+    // openat2's flags are behind a pointer, out of the filter's reach.  So
+    // for directories that lack any right that openat requires, openat2
+    // pretends not to exist, and libc falls back to openat.
+    let fds = fds_where(&|l| {
+        OPERATIONS.iter().any(|op| {
+            op.syscall
+                .as_ref()
+                .is_some_and(|sys| sys.nr == libc::SYS_openat)
+                && !l.rights.is_set(op.right as u64)
+        })
+    });
+    for conds in fds {
+        rules.push(on(Rule::deny(libc::SYS_openat2, libc::ENOSYS), 0, &conds));
+    }
+    for op in OPERATIONS {
+        let Some(sys) = &op.syscall else {
+            continue;
+        };
+        for conds in fds_where(&|l| !l.rights.is_set(op.right as u64)) {
+            let mut rule = on(Rule::deny(sys.nr, ENOTCAPABLE), op.arg, &conds);
+            rule.conds.extend(sys.when.iter().cloned());
+            rules.push(rule);
+        }
+    }
+    // Group the fcntl commands that are denied to the same descriptors.
+    let mut fcntls: Vec<(Vec<Vec<Cond>>, Vec<u32>)> = Vec::new();
+    for &(cmd, right, fcntl) in FCNTLS {
+        let fds = fds_where(&|l| !l.rights.is_set(right) || l.fcntls & fcntl != fcntl);
+        match fcntls.iter_mut().find(|(f, _)| *f == fds) {
+            Some((_, cmds)) => cmds.push(cmd as u32),
+            None => fcntls.push((fds, vec![cmd as u32])),
+        }
+    }
+    for (fds, cmds) in fcntls {
+        for conds in fds {
+            rules.push(
+                on(Rule::deny(libc::SYS_fcntl, ENOTCAPABLE), 0, &conds)
+                    .when(1, Cond::In(cmds.clone())),
+            );
+        }
+    }
+    for (fds, l) in limits {
+        if let Some(cmds) = &l.ioctls {
+            // The kernel truncates ioctl commands to 32 bits.
+            let cmds = cmds.iter().map(|cmd| *cmd as u32).collect();
+            rules.push(
+                on(Rule::deny(libc::SYS_ioctl, ENOTCAPABLE), 0, &fds.conds())
+                    .when(1, Cond::NotIn(cmds)),
+            );
+        }
+    }
+    rules
+}
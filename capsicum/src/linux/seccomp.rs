@@ -0,0 +1,428 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A tiny assembler for seccomp-bpf deny-lists.
+//!
+//! A [`Filter`] is a list of [`Rule`]s.  Each rule names a syscall and a set
+//! of conditions on its arguments; if the syscall matches and every
+//! condition holds, the syscall fails with the rule's errno.  Anything not
+//! denied by a rule is allowed.
+//!
+//! Rules that differ only in their syscall are assembled together, so that
+//! their conditions are only checked once.
+//!
+//! Compiling a filter allocates, but installing a compiled [`Program`] only
+//! makes syscalls, so it's safe to do in the child after a `fork`.
+
+use std::{io, mem};
+
+use libc::{c_long, sock_filter, sock_fprog};
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: u32 = 0xC000_00F3;
+
+/// Syscalls on x86_64 with this bit set use the x32 ABI.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// The kernel's limit on the length of a single filter.
+const BPF_MAXINSNS: usize = 4096;
+
+const OFFSET_NR: u32 = mem::offset_of!(libc::seccomp_data, nr) as u32;
+const OFFSET_ARCH: u32 = mem::offset_of!(libc::seccomp_data, arch) as u32;
+const OFFSET_ARGS: u32 = mem::offset_of!(libc::seccomp_data, args) as u32;
+
+/// Offset of the low 32 bits of a syscall argument.
+const fn arg_lo(arg: u8) -> u32 {
+    // All supported architectures are little-endian.
+    OFFSET_ARGS + 8 * arg as u32
+}
+
+/// Offset of the high 32 bits of a syscall argument.
+const fn arg_hi(arg: u8) -> u32 {
+    arg_lo(arg) + 4
+}
+
+/// A condition on one syscall argument.
+///
+/// Except for [`Cond::NonZero`], conditions only consider the low 32 bits of
+/// the argument.  That's appropriate for `int` arguments like file
+/// descriptors and flags, which the kernel truncates anyway.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Cond {
+    /// The argument equals the value.
+    Eq(u32),
+    /// The argument does not equal the value.
+    Ne(u32),
+    /// The argument equals any of the values.
+    In(Vec<u32>),
+    /// The argument equals none of the values.
+    NotIn(Vec<u32>),
+    /// The argument, considered as unsigned, is at most the value.
+    Le(u32),
+    /// The argument, considered as unsigned, is at least the value.
+    Ge(u32),
+    /// The argument, considered as a full 64-bit value, is not zero.
+    NonZero,
+    /// At least one bit of the mask is set in the argument.
+    AnySet(u32),
+    /// No bit of the mask is set in the argument.
+    NoneSet(u32),
+}
+
+/// Deny a syscall with `errno` if every condition holds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Rule {
+    pub nr: c_long,
+    pub conds: Vec<(u8, Cond)>,
+    pub errno: i32,
+}
+
+impl Rule {
+    /// Deny a syscall unconditionally.
+    pub fn deny(nr: c_long, errno: i32) -> Self {
+        Rule {
+            nr,
+            conds: Vec::new(),
+            errno,
+        }
+    }
+
+    /// Add a condition on argument number `arg`.
+    pub fn when(mut self, arg: u8, cond: Cond) -> Self {
+        self.conds.push((arg, cond));
+        self
+    }
+}
+
+/// The target of a conditional jump, before resolution.
+#[derive(Clone, Copy, Debug)]
+enum Jump {
+    /// Skip this many instructions.
+    Rel(u8),
+    /// Go to the end of the rule, where the syscall is allowed.
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Insn {
+    Load(u32),
+    Jeq(u32, Jump, Jump),
+    Jgt(u32, Jump, Jump),
+    Jge(u32, Jump, Jump),
+    Jset(u32, Jump, Jump),
+    Ret(u32),
+}
+
+const fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+const fn load(offset: u32) -> sock_filter {
+    stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset)
+}
+
+const fn ret(action: u32) -> sock_filter {
+    stmt(libc::BPF_RET | libc::BPF_K, action)
+}
+
+/// The largest number of values that a single [`Cond::In`], or a group of
+/// merged rules, can compare against, given the 8-bit range of conditional
+/// jumps.
+const MAX_IN: usize = 200;
+
+/// A seccomp filter under construction.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Filter {
+    rules: Vec<Rule>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    pub fn extend<I: IntoIterator<Item = Rule>>(&mut self, rules: I) {
+        self.rules.extend(rules);
+    }
+
+    /// Assemble the filter into a BPF program.
+    ///
+    /// # Errors
+    ///
+    /// * `E2BIG` - The program would exceed the kernel's size limit.
+    pub fn compile(&self) -> io::Result<Program> {
+        let mut prog = vec![
+            load(OFFSET_ARCH),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH,
+                1,
+                0,
+            ),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            load(OFFSET_NR),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        {
+            prog.push(jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                X32_SYSCALL_BIT,
+                0,
+                1,
+            ));
+            prog.push(ret(libc::SECCOMP_RET_KILL_PROCESS));
+        }
+        for (nrs, rule) in merge(&self.rules) {
+            for rule in split(rule) {
+                assemble(&nrs, &rule, &mut prog)?;
+            }
+        }
+        prog.push(ret(libc::SECCOMP_RET_ALLOW));
+        if prog.len() > BPF_MAXINSNS {
+            return Err(io::Error::from_raw_os_error(libc::E2BIG));
+        }
+        Ok(Program(prog))
+    }
+}
+
+/// Group rules that differ only in their syscall.  Each group takes the
+/// place of its first rule.  A rule joins a group only if no rule in between
+/// could match the same syscall with another errno.
+fn merge(rules: &[Rule]) -> Vec<(Vec<c_long>, &Rule)> {
+    let mut groups: Vec<(Vec<c_long>, &Rule)> = Vec::new();
+    for rule in rules {
+        let joinable = groups.iter().rposition(|(nrs, r)| {
+            r.conds == rule.conds && r.errno == rule.errno && nrs.len() < MAX_IN
+        });
+        let shadowed = |g: usize| {
+            groups[g + 1..]
+                .iter()
+                .any(|(nrs, r)| r.errno != rule.errno && nrs.contains(&rule.nr))
+        };
+        match joinable {
+            Some(g) if !shadowed(g) => {
+                if !groups[g].0.contains(&rule.nr) {
+                    groups[g].0.push(rule.nr);
+                }
+            }
+            _ => groups.push((vec![rule.nr], rule)),
+        }
+    }
+    groups
+}
+
+/// Split any rule whose `In` conditions are too long for 8-bit jumps.
+///
+/// A long `NotIn` condition can't be split.  Instead, compilation will fail.
+fn split(rule: &Rule) -> Vec<Rule> {
+    if rule
+        .conds
+        .iter()
+        .any(|(_, c)| matches!(c, Cond::In(v) if v.is_empty()))
+    {
+        // Can never match
+        return Vec::new();
+    }
+    let long = rule
+        .conds
+        .iter()
+        .position(|(_, c)| matches!(c, Cond::In(v) if v.len() > MAX_IN));
+    match long {
+        None => vec![rule.clone()],
+        Some(i) => {
+            let Cond::In(values) = &rule.conds[i].1 else {
+                unreachable!()
+            };
+            values
+                .chunks(MAX_IN)
+                .flat_map(|chunk| {
+                    let mut r = rule.clone();
+                    r.conds[i].1 = Cond::In(chunk.to_vec());
+                    split(&r)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Append the instructions for one rule to `prog`, applying it to each of
+/// the syscalls `nrs` instead of its own.
+///
+/// The accumulator holds the syscall number on entry and on exit.
+fn assemble(nrs: &[c_long], rule: &Rule, prog: &mut Vec<sock_filter>) -> io::Result<()> {
+    let mut body = Vec::new();
+    for (arg, cond) in &rule.conds {
+        match cond {
+            Cond::Eq(v) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jeq(*v, Jump::Rel(0), Jump::Fail));
+            }
+            Cond::Ne(v) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jeq(*v, Jump::Fail, Jump::Rel(0)));
+            }
+            Cond::In(values) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                let n = values.len();
+                for (i, v) in values.iter().enumerate() {
+                    if i + 1 == n {
+                        body.push(Insn::Jeq(*v, Jump::Rel(0), Jump::Fail));
+                    } else {
+                        let skip = u8::try_from(n - 1 - i).expect("In condition too long");
+                        body.push(Insn::Jeq(*v, Jump::Rel(skip), Jump::Rel(0)));
+                    }
+                }
+            }
+            Cond::NotIn(values) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                for v in values {
+                    body.push(Insn::Jeq(*v, Jump::Fail, Jump::Rel(0)));
+                }
+            }
+            Cond::Le(v) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jgt(*v, Jump::Fail, Jump::Rel(0)));
+            }
+            Cond::Ge(v) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jge(*v, Jump::Rel(0), Jump::Fail));
+            }
+            Cond::AnySet(mask) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jset(*mask, Jump::Rel(0), Jump::Fail));
+            }
+            Cond::NoneSet(mask) => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jset(*mask, Jump::Fail, Jump::Rel(0)));
+            }
+            Cond::NonZero => {
+                body.push(Insn::Load(arg_lo(*arg)));
+                body.push(Insn::Jeq(0, Jump::Rel(0), Jump::Rel(2)));
+                body.push(Insn::Load(arg_hi(*arg)));
+                body.push(Insn::Jeq(0, Jump::Fail, Jump::Rel(0)));
+            }
+        }
+    }
+    let errno = u32::try_from(rule.errno).unwrap() & libc::SECCOMP_RET_DATA;
+    body.push(Insn::Ret(libc::SECCOMP_RET_ERRNO | errno));
+
+    // Layout:
+    //   jeq nr1, <body>, 0
+    //   ...
+    //   jeq nrN, 1, 0
+    //   ja  <past the end>
+    //   <body>
+    //   ret errno
+    //   ld  nr          <- Fail
+    let reloads = usize::from(!rule.conds.is_empty());
+    let skip = u32::try_from(body.len() + reloads).unwrap();
+    for (i, nr) in nrs.iter().enumerate() {
+        let nr = u32::try_from(*nr).unwrap();
+        let to_body = u8::try_from(nrs.len() - i).unwrap();
+        prog.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            nr,
+            to_body,
+            0,
+        ));
+    }
+    prog.push(jump(libc::BPF_JMP | libc::BPF_JA, skip, 0, 0));
+    let fail = body.len();
+    let resolve = |i: usize, j: Jump| -> io::Result<u8> {
+        match j {
+            Jump::Rel(n) => Ok(n),
+            Jump::Fail => {
+                u8::try_from(fail - i - 1).map_err(|_| io::Error::from_raw_os_error(libc::E2BIG))
+            }
+        }
+    };
+    for (i, insn) in body.iter().enumerate() {
+        let insn = match *insn {
+            Insn::Load(offset) => load(offset),
+            Insn::Jeq(k, jt, jf) => jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                k,
+                resolve(i, jt)?,
+                resolve(i, jf)?,
+            ),
+            Insn::Jgt(k, jt, jf) => jump(
+                libc::BPF_JMP | libc::BPF_JGT | libc::BPF_K,
+                k,
+                resolve(i, jt)?,
+                resolve(i, jf)?,
+            ),
+            Insn::Jge(k, jt, jf) => jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                k,
+                resolve(i, jt)?,
+                resolve(i, jf)?,
+            ),
+            Insn::Jset(k, jt, jf) => jump(
+                libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K,
+                k,
+                resolve(i, jt)?,
+                resolve(i, jf)?,
+            ),
+            Insn::Ret(action) => ret(action),
+        };
+        prog.push(insn);
+    }
+    if reloads > 0 {
+        prog.push(load(OFFSET_NR));
+    }
+    Ok(())
+}
+
+/// An assembled seccomp-bpf program.
+#[derive(Clone)]
+pub(crate) struct Program(Vec<sock_filter>);
+
+impl Program {
+    /// Install the program for every thread of the process.
+    ///
+    /// This also sets the process's `no_new_privs` bit, which is a
+    /// prerequisite for unprivileged processes.  It performs no allocations.
+    pub fn install(&self) -> io::Result<()> {
+        let prog = sock_fprog {
+            len: self.0.len() as libc::c_ushort,
+            filter: self.0.as_ptr() as *mut sock_filter,
+        };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let r = libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_TSYNC,
+                &prog as *const sock_fprog,
+            );
+            match r {
+                0 => Ok(()),
+                // Some other thread couldn't be synchronized.
+                r if r > 0 => Err(io::Error::from_raw_os_error(libc::EAGAIN)),
+                _ => Err(io::Error::last_os_error()),
+            }
+        }
+    }
+}
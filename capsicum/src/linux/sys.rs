@@ -0,0 +1,339 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Userspace implementations of FreeBSD's Capsicum libc interfaces.
+//!
+//! The functions here have the same names, constants, and calling
+//! conventions as their counterparts in `libc`, including reporting errors
+//! through `errno`, so that the portable parts of the crate can use either.
+//! The bit layout of `cap_rights_t` matches FreeBSD's, too.
+#![allow(non_camel_case_types)]
+#![allow(clippy::missing_safety_doc)]
+
+use libc::{c_int, c_ulong};
+
+use super::rights;
+
+pub(crate) type u_long = c_ulong;
+
+/// Linux has no `ENOTCAPABLE`.  Operations denied by a descriptor's limits
+/// fail with `EPERM` instead, like those denied by capability mode.
+pub(crate) const ENOTCAPABLE: c_int = libc::EPERM;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct cap_rights_t {
+    cr_rights: [u64; 2],
+}
+
+pub(crate) const CAP_RIGHTS_VERSION: c_int = 0;
+
+const fn cap_right(idx: u64, bit: u64) -> u64 {
+    (1u64 << (57 + idx)) | bit
+}
+
+pub(crate) const CAP_READ: u64 = cap_right(0, 0x0000000000000001);
+pub(crate) const CAP_WRITE: u64 = cap_right(0, 0x0000000000000002);
+pub(crate) const CAP_SEEK_TELL: u64 = cap_right(0, 0x0000000000000004);
+pub(crate) const CAP_SEEK: u64 = CAP_SEEK_TELL | 0x0000000000000008;
+pub(crate) const CAP_PREAD: u64 = CAP_SEEK | CAP_READ;
+pub(crate) const CAP_PWRITE: u64 = CAP_SEEK | CAP_WRITE;
+pub(crate) const CAP_MMAP: u64 = cap_right(0, 0x0000000000000010);
+pub(crate) const CAP_MMAP_R: u64 = CAP_MMAP | CAP_SEEK | CAP_READ;
+pub(crate) const CAP_MMAP_W: u64 = CAP_MMAP | CAP_SEEK | CAP_WRITE;
+pub(crate) const CAP_MMAP_X: u64 = CAP_MMAP | CAP_SEEK | 0x0000000000000020;
+pub(crate) const CAP_MMAP_RW: u64 = CAP_MMAP_R | CAP_MMAP_W;
+pub(crate) const CAP_MMAP_RX: u64 = CAP_MMAP_R | CAP_MMAP_X;
+pub(crate) const CAP_MMAP_WX: u64 = CAP_MMAP_W | CAP_MMAP_X;
+pub(crate) const CAP_MMAP_RWX: u64 = CAP_MMAP_R | CAP_MMAP_W | CAP_MMAP_X;
+pub(crate) const CAP_CREATE: u64 = cap_right(0, 0x0000000000000040);
+pub(crate) const CAP_FEXECVE: u64 = cap_right(0, 0x0000000000000080);
+pub(crate) const CAP_FSYNC: u64 = cap_right(0, 0x0000000000000100);
+pub(crate) const CAP_FTRUNCATE: u64 = cap_right(0, 0x0000000000000200);
+pub(crate) const CAP_LOOKUP: u64 = cap_right(0, 0x0000000000000400);
+pub(crate) const CAP_FCHDIR: u64 = cap_right(0, 0x0000000000000800);
+pub(crate) const CAP_FCHFLAGS: u64 = cap_right(0, 0x0000000000001000);
+pub(crate) const CAP_CHFLAGSAT: u64 = CAP_FCHFLAGS | CAP_LOOKUP;
+pub(crate) const CAP_FCHMOD: u64 = cap_right(0, 0x0000000000002000);
+pub(crate) const CAP_FCHMODAT: u64 = CAP_FCHMOD | CAP_LOOKUP;
+pub(crate) const CAP_FCHOWN: u64 = cap_right(0, 0x0000000000004000);
+pub(crate) const CAP_FCHOWNAT: u64 = CAP_FCHOWN | CAP_LOOKUP;
+pub(crate) const CAP_FCNTL: u64 = cap_right(0, 0x0000000000008000);
+pub(crate) const CAP_FLOCK: u64 = cap_right(0, 0x0000000000010000);
+pub(crate) const CAP_FPATHCONF: u64 = cap_right(0, 0x0000000000020000);
+pub(crate) const CAP_FSCK: u64 = cap_right(0, 0x0000000000040000);
+pub(crate) const CAP_FSTAT: u64 = cap_right(0, 0x0000000000080000);
+pub(crate) const CAP_FSTATAT: u64 = CAP_FSTAT | CAP_LOOKUP;
+pub(crate) const CAP_FSTATFS: u64 = cap_right(0, 0x0000000000100000);
+pub(crate) const CAP_FUTIMES: u64 = cap_right(0, 0x0000000000200000);
+pub(crate) const CAP_FUTIMESAT: u64 = CAP_FUTIMES | CAP_LOOKUP;
+pub(crate) const CAP_LINKAT_TARGET: u64 = CAP_LOOKUP | 0x0000000000400000;
+pub(crate) const CAP_MKDIRAT: u64 = CAP_LOOKUP | 0x0000000000800000;
+pub(crate) const CAP_MKFIFOAT: u64 = CAP_LOOKUP | 0x0000000001000000;
+pub(crate) const CAP_MKNODAT: u64 = CAP_LOOKUP | 0x0000000002000000;
+pub(crate) const CAP_RENAMEAT_SOURCE: u64 = CAP_LOOKUP | 0x0000000004000000;
+pub(crate) const CAP_SYMLINKAT: u64 = CAP_LOOKUP | 0x0000000008000000;
+pub(crate) const CAP_UNLINKAT: u64 = CAP_LOOKUP | 0x0000000010000000;
+pub(crate) const CAP_ACCEPT: u64 = cap_right(0, 0x0000000020000000);
+pub(crate) const CAP_BIND: u64 = cap_right(0, 0x0000000040000000);
+pub(crate) const CAP_CONNECT: u64 = cap_right(0, 0x0000000080000000);
+pub(crate) const CAP_GETPEERNAME: u64 = cap_right(0, 0x0000000100000000);
+pub(crate) const CAP_GETSOCKNAME: u64 = cap_right(0, 0x0000000200000000);
+pub(crate) const CAP_GETSOCKOPT: u64 = cap_right(0, 0x0000000400000000);
+pub(crate) const CAP_LISTEN: u64 = cap_right(0, 0x0000000800000000);
+pub(crate) const CAP_PEELOFF: u64 = cap_right(0, 0x0000001000000000);
+pub(crate) const CAP_RECV: u64 = CAP_READ;
+pub(crate) const CAP_SEND: u64 = CAP_WRITE;
+pub(crate) const CAP_SETSOCKOPT: u64 = cap_right(0, 0x0000002000000000);
+pub(crate) const CAP_SHUTDOWN: u64 = cap_right(0, 0x0000004000000000);
+pub(crate) const CAP_BINDAT: u64 = CAP_LOOKUP | 0x0000008000000000;
+pub(crate) const CAP_CONNECTAT: u64 = CAP_LOOKUP | 0x0000010000000000;
+pub(crate) const CAP_LINKAT_SOURCE: u64 = CAP_LOOKUP | 0x0000020000000000;
+pub(crate) const CAP_RENAMEAT_TARGET: u64 = CAP_LOOKUP | 0x0000040000000000;
+pub(crate) const CAP_SOCK_CLIENT: u64 = CAP_CONNECT
+    | CAP_GETPEERNAME
+    | CAP_GETSOCKNAME
+    | CAP_GETSOCKOPT
+    | CAP_PEELOFF
+    | CAP_RECV
+    | CAP_SEND
+    | CAP_SETSOCKOPT
+    | CAP_SHUTDOWN;
+pub(crate) const CAP_SOCK_SERVER: u64 = CAP_ACCEPT
+    | CAP_BIND
+    | CAP_GETPEERNAME
+    | CAP_GETSOCKNAME
+    | CAP_GETSOCKOPT
+    | CAP_LISTEN
+    | CAP_PEELOFF
+    | CAP_RECV
+    | CAP_SEND
+    | CAP_SETSOCKOPT
+    | CAP_SHUTDOWN;
+pub(crate) const CAP_ALL0: u64 = cap_right(0, 0x000007FFFFFFFFFF);
+pub(crate) const CAP_UNUSED0_44: u64 = cap_right(0, 0x0000080000000000);
+pub(crate) const CAP_UNUSED0_57: u64 = cap_right(0, 0x0100000000000000);
+pub(crate) const CAP_MAC_GET: u64 = cap_right(1, 0x0000000000000001);
+pub(crate) const CAP_MAC_SET: u64 = cap_right(1, 0x0000000000000002);
+pub(crate) const CAP_SEM_GETVALUE: u64 = cap_right(1, 0x0000000000000004);
+pub(crate) const CAP_SEM_POST: u64 = cap_right(1, 0x0000000000000008);
+pub(crate) const CAP_SEM_WAIT: u64 = cap_right(1, 0x0000000000000010);
+pub(crate) const CAP_EVENT: u64 = cap_right(1, 0x0000000000000020);
+pub(crate) const CAP_KQUEUE_EVENT: u64 = cap_right(1, 0x0000000000000040);
+pub(crate) const CAP_IOCTL: u64 = cap_right(1, 0x0000000000000080);
+pub(crate) const CAP_TTYHOOK: u64 = cap_right(1, 0x0000000000000100);
+pub(crate) const CAP_PDGETPID: u64 = cap_right(1, 0x0000000000000200);
+pub(crate) const CAP_PDWAIT: u64 = cap_right(1, 0x0000000000000400);
+pub(crate) const CAP_PDKILL: u64 = cap_right(1, 0x0000000000000800);
+pub(crate) const CAP_EXTATTR_DELETE: u64 = cap_right(1, 0x0000000000001000);
+pub(crate) const CAP_EXTATTR_GET: u64 = cap_right(1, 0x0000000000002000);
+pub(crate) const CAP_EXTATTR_LIST: u64 = cap_right(1, 0x0000000000004000);
+pub(crate) const CAP_EXTATTR_SET: u64 = cap_right(1, 0x0000000000008000);
+pub(crate) const CAP_ACL_CHECK: u64 = cap_right(1, 0x0000000000010000);
+pub(crate) const CAP_ACL_DELETE: u64 = cap_right(1, 0x0000000000020000);
+pub(crate) const CAP_ACL_GET: u64 = cap_right(1, 0x0000000000040000);
+pub(crate) const CAP_ACL_SET: u64 = cap_right(1, 0x0000000000080000);
+pub(crate) const CAP_KQUEUE_CHANGE: u64 = cap_right(1, 0x0000000000100000);
+pub(crate) const CAP_KQUEUE: u64 = CAP_KQUEUE_EVENT | CAP_KQUEUE_CHANGE;
+pub(crate) const CAP_ALL1: u64 = cap_right(1, 0x00000000001FFFFF);
+pub(crate) const CAP_UNUSED1_22: u64 = cap_right(1, 0x0000000000200000);
+pub(crate) const CAP_UNUSED1_57: u64 = cap_right(1, 0x0100000000000000);
+
+pub(crate) const CAP_FCNTL_GETFL: u32 = 1 << 3;
+pub(crate) const CAP_FCNTL_SETFL: u32 = 1 << 4;
+pub(crate) const CAP_FCNTL_GETOWN: u32 = 1 << 5;
+pub(crate) const CAP_FCNTL_SETOWN: u32 = 1 << 6;
+pub(crate) const CAP_FCNTL_ALL: u32 =
+    CAP_FCNTL_GETFL | CAP_FCNTL_SETFL | CAP_FCNTL_GETOWN | CAP_FCNTL_SETOWN;
+
+/// The bits of a right that aren't its array index.
+const RIGHT_MASK: u64 = 0x01FF_FFFF_FFFF_FFFF;
+
+/// Which element of `cr_rights` holds this right.
+fn index(right: u64) -> usize {
+    match (right >> 57) & 0x1F {
+        1 => 0,
+        2 => 1,
+        _ => panic!("Invalid capability right {right:#x}"),
+    }
+}
+
+fn set_errno(e: c_int) -> c_int {
+    unsafe { *libc::__errno_location() = e };
+    -1
+}
+
+impl cap_rights_t {
+    /// A set of every right, which is what a descriptor has before it's ever
+    /// limited.
+    pub(crate) const ALL: cap_rights_t = cap_rights_t {
+        cr_rights: [CAP_ALL0, CAP_ALL1],
+    };
+
+    pub(crate) const fn new() -> cap_rights_t {
+        cap_rights_t {
+            cr_rights: [cap_right(0, 0), cap_right(1, 0)],
+        }
+    }
+
+    pub(crate) fn set(&mut self, right: u64) {
+        self.cr_rights[index(right)] |= right;
+    }
+
+    pub(crate) fn clear(&mut self, right: u64) {
+        self.cr_rights[index(right)] &= !(right & RIGHT_MASK);
+    }
+
+    pub(crate) fn is_set(&self, right: u64) -> bool {
+        self.cr_rights[index(right)] & right == right
+    }
+
+    pub(crate) fn contains(&self, other: &cap_rights_t) -> bool {
+        self.cr_rights
+            .iter()
+            .zip(other.cr_rights.iter())
+            .all(|(big, little)| big & little == *little)
+    }
+}
+
+// The shims below take the same arguments as the libc functions that they
+// replace.  The trailing `u64` stands in for the C functions' variadic
+// argument list, which the crate always terminates after a single right.
+
+pub(crate) unsafe fn __cap_rights_init(
+    _version: c_int,
+    rights: *mut cap_rights_t,
+    _end: u64,
+) -> *mut cap_rights_t {
+    *rights = cap_rights_t::new();
+    rights
+}
+
+pub(crate) unsafe fn __cap_rights_set(
+    rights: *mut cap_rights_t,
+    right: u64,
+    _end: u64,
+) -> *mut cap_rights_t {
+    (*rights).set(right);
+    rights
+}
+
+pub(crate) unsafe fn __cap_rights_clear(
+    rights: *mut cap_rights_t,
+    right: u64,
+    _end: u64,
+) -> *mut cap_rights_t {
+    (*rights).clear(right);
+    rights
+}
+
+pub(crate) unsafe fn __cap_rights_is_set(
+    rights: *const cap_rights_t,
+    right: u64,
+    _end: u64,
+) -> bool {
+    (*rights).is_set(right)
+}
+
+pub(crate) unsafe fn cap_rights_is_valid(rights: *const cap_rights_t) -> bool {
+    let [r0, r1] = (*rights).cr_rights;
+    r0 >> 62 == CAP_RIGHTS_VERSION as u64
+        && index(r0) == 0
+        && index(r1) == 1
+        && r0 & RIGHT_MASK & !CAP_ALL0 == 0
+        && r1 & RIGHT_MASK & !CAP_ALL1 == 0
+}
+
+pub(crate) unsafe fn cap_rights_merge(
+    dst: *mut cap_rights_t,
+    src: *const cap_rights_t,
+) -> *mut cap_rights_t {
+    for i in 0..2 {
+        (*dst).cr_rights[i] |= (*src).cr_rights[i];
+    }
+    dst
+}
+
+pub(crate) unsafe fn cap_rights_remove(
+    dst: *mut cap_rights_t,
+    src: *const cap_rights_t,
+) -> *mut cap_rights_t {
+    for i in 0..2 {
+        (*dst).cr_rights[i] &= !((*src).cr_rights[i] & RIGHT_MASK);
+    }
+    dst
+}
+
+pub(crate) unsafe fn cap_rights_contains(
+    big: *const cap_rights_t,
+    little: *const cap_rights_t,
+) -> bool {
+    (*big).contains(&*little)
+}
+
+pub(crate) unsafe fn cap_rights_limit(fd: c_int, rights: *const cap_rights_t) -> c_int {
+    match rights::limit(fd, |limits| limits.limit_rights(&*rights)) {
+        Ok(()) => 0,
+        Err(e) => set_errno(e),
+    }
+}
+
+pub(crate) unsafe fn __cap_rights_get(
+    _version: c_int,
+    fd: c_int,
+    rightsp: *mut cap_rights_t,
+) -> c_int {
+    match rights::get(fd) {
+        Ok(limits) => {
+            *rightsp = limits.rights;
+            0
+        }
+        Err(e) => set_errno(e),
+    }
+}
+
+pub(crate) unsafe fn cap_fcntls_limit(fd: c_int, fcntlrights: u32) -> c_int {
+    match rights::limit(fd, |limits| limits.limit_fcntls(fcntlrights)) {
+        Ok(()) => 0,
+        Err(e) => set_errno(e),
+    }
+}
+
+pub(crate) unsafe fn cap_fcntls_get(fd: c_int, fcntlrightsp: *mut u32) -> c_int {
+    match rights::get(fd) {
+        Ok(limits) => {
+            *fcntlrightsp = limits.fcntls;
+            0
+        }
+        Err(e) => set_errno(e),
+    }
+}
+
+pub(crate) unsafe fn cap_ioctls_limit(fd: c_int, cmds: *const u_long, ncmds: usize) -> c_int {
+    let cmds = if ncmds == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(cmds, ncmds)
+    };
+    match rights::limit(fd, |limits| limits.limit_ioctls(cmds)) {
+        Ok(()) => 0,
+        Err(e) => set_errno(e),
+    }
+}
+
+pub(crate) unsafe fn cap_ioctls_get(fd: c_int, cmds: *mut u_long, maxcmds: usize) -> isize {
+    match rights::get(fd) {
+        Ok(limits) => match limits.ioctls {
+            None => isize::MAX,
+            Some(v) => {
+                let n = v.len().min(maxcmds);
+                if n > 0 {
+                    std::ptr::copy_nonoverlapping(v.as_ptr(), cmds, n);
+                }
+                v.len() as isize
+            }
+        },
+        Err(e) => set_errno(e) as isize,
+    }
+}
@@ -0,0 +1,345 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Append-only log files, for sandboxed processes.
+//!
+//! An [`AppendLog`] writes to a file that was opened with `O_APPEND` before
+//! entering capability mode, and limited to `CAP_WRITE`.  Without `CAP_READ`,
+//! `CAP_SEEK`, `CAP_FTRUNCATE`, or `CAP_FCNTL`, a compromised process can add
+//! to the log, but it can't read it, truncate it, overwrite earlier records,
+//! or clear `O_APPEND`.
+//!
+//! That's all the protection there is, though, and it's weaker for a log
+//! that [rotates](AppendLog::rotating).  The process keeps a descriptor for
+//! the log's directory that allows creating and renaming files, so a
+//! compromised process can append to rotated logs too, rename them out of
+//! the way, and create files in their place.  It still can't remove or
+//! shorten any of them.  Logs that must be tamper-evident should be rotated
+//! by a process outside of the sandbox instead.
+//!
+//! `&AppendLog` implements [`Write`], so an `Arc<AppendLog>` can be used as
+//! the writer of a `tracing-subscriber` formatter.  With the `log` feature,
+//! `AppendLog` implements `log::Log` too.
+//!
+//! # Example
+//! ```
+//! use std::io::Write;
+//!
+//! use capsicum::logfile::AppendLog;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let path = dir.path().join("worker.log");
+//! let mut log = AppendLog::rotating(&path, 1 << 20).unwrap();
+//! capsicum::enter().unwrap();
+//!
+//! writeln!(log, "entered capability mode").unwrap();
+//! ```
+
+use std::{
+    ffi::CString,
+    fmt,
+    fs::File,
+    io::{self, Write},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    sync::Mutex,
+};
+
+use crate::{common::CapRights, fs, FileRights, Right};
+
+/// The flags that log files are opened with.
+const FLAGS: libc::c_int = libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_CLOEXEC;
+
+/// The mode that log files are created with, before the umask.
+const MODE: libc::c_uint = 0o666;
+
+/// Every right that a log file keeps.
+const LOG_RIGHTS: &[Right] = &[Right::Write];
+
+/// Every right that the directory of a rotating log keeps.  Besides renaming
+/// logs, creating the next one needs `CAP_LOOKUP` and `CAP_WRITE`, but
+/// opening a file for writing without `O_APPEND` would need `CAP_SEEK`, and
+/// truncating it `CAP_FTRUNCATE`.  Without `CAP_UNLINKAT`, no log can be
+/// removed, nor replaced by renaming another over it.  But any log, rotated
+/// or not, can be appended to, or renamed to a new name.
+const DIR_RIGHTS: &[Right] = &[
+    Right::Lookup,
+    Right::Create,
+    Right::Write,
+    Right::RenameatSource,
+    Right::RenameatTarget,
+];
+
+fn rights(list: &[Right]) -> FileRights {
+    let mut rights = FileRights::new();
+    for right in list {
+        rights.allow(*right);
+    }
+    rights
+}
+
+/// An append-only log file, usable in capability mode.
+///
+/// The log may rotate: once writing to it would make it exceed its maximum
+/// size, it's renamed, with a numeric suffix, and a new one is created in
+/// its place.  Rotated logs are never removed; that's up to some process
+/// outside of the sandbox.
+///
+/// Rotation happens between calls to [`write`](Write::write), so a record
+/// written with a single call is never split between two files.
+pub struct AppendLog {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    size: u64,
+    rotation: Option<Rotation>,
+}
+
+#[derive(Debug)]
+struct Rotation {
+    dir: OwnedFd,
+    name: CString,
+    max_size: u64,
+    next: u64,
+}
+
+impl AppendLog {
+    /// Open the log file at `path`, creating it if it doesn't exist.  It
+    /// never rotates.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<AppendLog> {
+        let path = fs::cstring(path.as_ref())?;
+        let file = fs::file(unsafe { libc::open(path.as_ptr(), FLAGS, MODE) })?;
+        AppendLog::new(file, None)
+    }
+
+    /// Open the log file at `path`, creating it if it doesn't exist, and
+    /// rotate it whenever it would grow past `max_size` bytes.
+    ///
+    /// Rotated logs are named like `path.1`, `path.2`, and so on, oldest
+    /// first.  The directory that contains `path` is kept open, limited to
+    /// `CAP_LOOKUP`, `CAP_CREATE`, `CAP_WRITE`, `CAP_RENAMEAT_SOURCE`, and
+    /// `CAP_RENAMEAT_TARGET`.  That prevents truncating or removing logs,
+    /// but not tampering with rotated logs: with those rights, the process
+    /// can append to them, and rename them.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidInput`](io::ErrorKind::InvalidInput) - `path` doesn't name
+    ///   a file.
+    /// * Any error from `open(2)` or `openat(2)`.
+    pub fn rotating<P: AsRef<Path>>(path: P, max_size: u64) -> io::Result<AppendLog> {
+        let path = path.as_ref();
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a file name", path.display()),
+            )
+        })?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let parent = fs::cstring(parent)?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let dir = OwnedFd::from(fs::file(unsafe { libc::open(parent.as_ptr(), flags) })?);
+        let name = CString::new(name.as_bytes())
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let file = fs::file(unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), FLAGS, MODE) })?;
+        rights(DIR_RIGHTS).limit(&dir)?;
+        let rotation = Rotation {
+            dir,
+            name,
+            max_size,
+            next: 1,
+        };
+        AppendLog::new(file, Some(rotation))
+    }
+
+    /// Use a file that is already open, like one received from a privileged
+    /// process.  It never rotates.
+    ///
+    /// # Errors
+    ///
+    /// * `EINVAL` - `file` is read-only, or was opened without `O_APPEND`.
+    pub fn from_file(file: File) -> io::Result<AppendLog> {
+        let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if flags & libc::O_ACCMODE == libc::O_RDONLY || flags & libc::O_APPEND == 0 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        AppendLog::new(file, None)
+    }
+
+    fn new(file: File, rotation: Option<Rotation>) -> io::Result<AppendLog> {
+        let size = file.metadata()?.len();
+        rights(LOG_RIGHTS).limit(&file)?;
+        let inner = Inner {
+            file,
+            size,
+            rotation,
+        };
+        Ok(AppendLog {
+            inner: Mutex::new(inner),
+        })
+    }
+}
+
+impl fmt::Debug for AppendLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("AppendLog")
+            .field("file", &inner.file)
+            .field("size", &inner.size)
+            .field("rotation", &inner.rotation)
+            .finish()
+    }
+}
+
+impl Inner {
+    /// Rotate the log, if writing `len` more bytes would make it too big.
+    fn make_room(&mut self, len: usize) -> io::Result<()> {
+        let Some(rotation) = &mut self.rotation else {
+            return Ok(());
+        };
+        if self.size == 0 || self.size.saturating_add(len as u64) <= rotation.max_size {
+            return Ok(());
+        }
+        self.file = rotation.rotate()?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Rotation {
+    /// Rename the log to the next free name, and create a new one.
+    fn rotate(&mut self) -> io::Result<File> {
+        loop {
+            let mut target = self.name.as_bytes().to_vec();
+            target.extend_from_slice(format!(".{}", self.next).as_bytes());
+            let target = CString::new(target).unwrap();
+            self.next += 1;
+            match rename_noreplace(self.dir.as_fd(), &self.name, &target) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                r => r?,
+            }
+            break;
+        }
+        let fd = unsafe { libc::openat(self.dir.as_raw_fd(), self.name.as_ptr(), FLAGS, MODE) };
+        let file = fs::file(fd)?;
+        rights(LOG_RIGHTS).limit(&file)?;
+        Ok(file)
+    }
+}
+
+/// Rename `from` to `to`, both in `dir`, unless `to` already exists.
+#[cfg(target_os = "linux")]
+fn rename_noreplace(dir: BorrowedFd<'_>, from: &CString, to: &CString) -> io::Result<()> {
+    let dir = dir.as_raw_fd();
+    let flags = libc::RENAME_NOREPLACE;
+    if unsafe { libc::renameat2(dir, from.as_ptr(), dir, to.as_ptr(), flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Rename `from` to `to`, both in `dir`, unless `to` already exists.
+///
+/// Replacing `to` would need `CAP_UNLINKAT`, which `dir` lacks, so an
+/// existing `to` fails with `ENOTCAPABLE`.
+#[cfg(target_os = "freebsd")]
+fn rename_noreplace(dir: BorrowedFd<'_>, from: &CString, to: &CString) -> io::Result<()> {
+    let dir = dir.as_raw_fd();
+    if unsafe { libc::renameat(dir, from.as_ptr(), dir, to.as_ptr()) } < 0 {
+        match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::ENOTCAPABLE) => {
+                Err(io::Error::from(io::ErrorKind::AlreadyExists))
+            }
+            e => Err(e),
+        }
+    } else {
+        Ok(())
+    }
+}
+
+impl Write for &AppendLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.make_room(buf.len())?;
+        let n = inner.file.write(buf)?;
+        inner.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for AppendLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+#[cfg(feature = "log")]
+#[cfg_attr(docsrs, doc(cfg(feature = "log")))]
+impl log::Log for AppendLog {
+    fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    /// Append a record, like `2026-10-18T13:52:01Z INFO  worker: started`.
+    fn log(&self, record: &log::Record<'_>) {
+        let line = format!(
+            "{} {:<5} {}: {}\n",
+            timestamp(std::time::SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // There's nowhere to report the error.
+        let _ = (&*self).write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Format a time in UTC, like `2026-10-18T13:52:01Z`.
+#[cfg(feature = "log")]
+fn timestamp(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Convert days since the epoch to a civil date, after Howard Hinnant's
+    // days_from_civil algorithm, in reverse.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
@@ -0,0 +1,362 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Machine-readable manifests of a process's sandbox.
+//!
+//! A [`Manifest`] records the sandbox mode, every limited descriptor with its
+//! role and its [`FdLimits`], and the Casper services that were opened.  It
+//! can be exported as JSON, for review or for an audit trail, and a manifest
+//! declared in advance can be checked against the live process with
+//! [`verify_manifest`].
+//!
+//! Descriptors registered with a [`Sandbox`](crate::Sandbox) take their names
+//! as their roles.  Others can be named with [`set_role`].
+//!
+//! # Example
+//! ```
+//! # use std::fs::File;
+//! # use capsicum::{manifest::{self, Manifest}, FdLimits, FileRights, Right, Sandbox};
+//! let declared = Manifest::from_json(r#"{
+//!     "mode": "seccomp-filter",
+//!     "descriptors": [
+//!         {"role": "passwd", "fd": 3, "limits": {"rights": ["CAP_READ"]}}
+//!     ]
+//! }"#).unwrap();
+//! # #[cfg(target_os = "freebsd")]
+//! # let declared = Manifest { mode: capsicum::Mode::Capsicum, ..declared };
+//!
+//! let passwd = File::open("/etc/passwd").unwrap();
+//! let rights = *FileRights::new().allow(Right::Read);
+//! let _fds = Sandbox::new()
+//!     .fd("passwd", passwd, FdLimits::new(rights))
+//!     .enter()
+//!     .unwrap();
+//!
+//! let drift = manifest::verify_manifest(&declared).unwrap();
+//! assert!(drift.is_empty(), "{drift:?}");
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{audit, process, FcntlRights, FdLimits, IoctlRights, Mode};
+
+/// The roles of descriptors, by number.
+static ROLES: Mutex<BTreeMap<RawFd, String>> = Mutex::new(BTreeMap::new());
+
+/// Name the role of `fd`, like `"listener"` or `"config"`, for
+/// [`Manifest::capture`].
+///
+/// The role belongs to the descriptor number, so it outlives the descriptor,
+/// and passes to any other that reuses the number.
+pub fn set_role<F: AsFd>(fd: &F, role: &str) {
+    let mut roles = ROLES.lock().unwrap_or_else(|e| e.into_inner());
+    roles.insert(fd.as_fd().as_raw_fd(), role.to_owned());
+}
+
+/// The role of `fd`: the one given to [`set_role`], or else a name like
+/// `"stdin"` or `"fd 7"`.
+fn role(fd: RawFd) -> String {
+    let roles = ROLES.lock().unwrap_or_else(|e| e.into_inner());
+    match (roles.get(&fd), fd) {
+        (Some(role), _) => role.clone(),
+        (None, 0) => "stdin".to_owned(),
+        (None, 1) => "stdout".to_owned(),
+        (None, 2) => "stderr".to_owned(),
+        (None, fd) => format!("fd {fd}"),
+    }
+}
+
+/// The sandbox of a process: its mode, its limited descriptors, and its
+/// Casper services.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Manifest {
+    /// The mechanism that confines the process.
+    pub mode: Mode,
+    /// Every descriptor that is limited, or that has a role.
+    pub descriptors: Vec<DescriptorManifest>,
+    /// The names of the Casper services that were opened, like
+    /// `"system.dns"`.  Always empty without the `casper` feature.
+    #[serde(default)]
+    pub casper_services: Vec<String>,
+}
+
+/// One descriptor in a [`Manifest`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DescriptorManifest {
+    /// What the descriptor is for.  Descriptors are matched by role, so
+    /// roles should be unique.
+    pub role: String,
+    /// The descriptor number.  [`verify_manifest`] ignores it.
+    pub fd: RawFd,
+    /// The limits in effect.
+    #[serde(with = "limits_repr")]
+    pub limits: FdLimits,
+}
+
+/// `FdLimits` by name, like
+/// `{"rights": ["CAP_READ"], "fcntls": ["CAP_FCNTL_GETFL"], "ioctls": null}`.
+/// Absent or null `fcntls` and `ioctls` are unlimited.
+mod limits_repr {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{sys::u_long, FcntlRights, FdLimits, FileRights, IoctlRights};
+
+    /// Names are serialized from `&'static str`, but deserialized into
+    /// `String`, since a name with escapes, or one read from a stream, can't
+    /// be borrowed.
+    #[derive(Deserialize, Serialize)]
+    struct Repr<S> {
+        rights: Vec<S>,
+        #[serde(default)]
+        fcntls: Option<Vec<S>>,
+        #[serde(default)]
+        ioctls: Option<Vec<u_long>>,
+    }
+
+    pub(super) fn serialize<S: Serializer>(limits: &FdLimits, s: S) -> Result<S::Ok, S::Error> {
+        let ioctls = match &limits.ioctls {
+            IoctlRights::Unlimited => None,
+            IoctlRights::Limited(cmds) => Some(cmds.clone()),
+        };
+        Repr {
+            rights: limits.rights.names(),
+            fcntls: limits.fcntls.map(|fcntls| fcntls.names()),
+            ioctls,
+        }
+        .serialize(s)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<FdLimits, D::Error> {
+        let repr = Repr::<String>::deserialize(d)?;
+        let unknown = |name| D::Error::custom(format!("unknown right {name:?}"));
+        let rights =
+            FileRights::from_names(repr.rights.iter().map(String::as_str)).map_err(unknown)?;
+        let fcntls = repr
+            .fcntls
+            .as_ref()
+            .map(|names| FcntlRights::from_names(names.iter().map(String::as_str)))
+            .transpose()
+            .map_err(unknown)?;
+        let ioctls = match repr.ioctls {
+            None => IoctlRights::Unlimited,
+            Some(cmds) => IoctlRights::Limited(cmds),
+        };
+        Ok(FdLimits {
+            rights,
+            fcntls,
+            ioctls,
+        })
+    }
+}
+
+impl Manifest {
+    /// Record the sandbox of the current process.
+    ///
+    /// On Linux, limits take effect on entering capability mode, but those
+    /// already set are recorded too.
+    pub fn capture() -> io::Result<Manifest> {
+        let descriptors = audit::open_descriptors()?
+            .into_iter()
+            .filter_map(|descriptor| {
+                let limits = normalize(descriptor.limits?);
+                let named = ROLES
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .contains_key(&descriptor.fd);
+                let unlimited = limits.rights.is_unlimited()
+                    && limits.fcntls.is_none()
+                    && limits.ioctls == IoctlRights::Unlimited;
+                (named || !unlimited).then(|| DescriptorManifest {
+                    role: role(descriptor.fd),
+                    fd: descriptor.fd,
+                    limits,
+                })
+            })
+            .collect();
+        #[cfg(all(feature = "casper", target_os = "freebsd"))]
+        let casper_services = crate::casper::opened_services();
+        #[cfg(not(all(feature = "casper", target_os = "freebsd")))]
+        let casper_services = Vec::new();
+        Ok(Manifest {
+            mode: process::mode(),
+            descriptors,
+            casper_services,
+        })
+    }
+
+    /// Export as pretty-printed JSON.
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string_pretty(self).map_err(io::Error::other)
+    }
+
+    /// Import from JSON, as exported by [`Manifest::to_json`].
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidData`](io::ErrorKind::InvalidData) - `json` is malformed,
+    ///   or names an unknown right.
+    pub fn from_json(json: &str) -> io::Result<Manifest> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Put `limits` in a canonical form, for comparison.
+fn normalize(mut limits: FdLimits) -> FdLimits {
+    if limits.fcntls == Some(FcntlRights::ALL) {
+        limits.fcntls = None;
+    }
+    if let IoctlRights::Limited(cmds) = &mut limits.ioctls {
+        cmds.sort_unstable();
+        cmds.dedup();
+    }
+    limits
+}
+
+/// A difference between a declared [`Manifest`] and the live process, as
+/// reported by [`verify_manifest`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Drift {
+    /// The process is confined by another mechanism.
+    Mode {
+        /// The declared mode.
+        declared: Mode,
+        /// The actual mode.
+        actual: Mode,
+    },
+    /// No open descriptor has a declared role.
+    Missing {
+        /// The declared role.
+        role: String,
+    },
+    /// A descriptor is limited, or has a role, but wasn't declared.
+    Undeclared {
+        /// The descriptor's role.
+        role: String,
+        /// The descriptor number.
+        fd: RawFd,
+    },
+    /// A descriptor's limits differ from the declared ones.
+    Limits {
+        /// The descriptor's role.
+        role: String,
+        /// The declared limits.
+        declared: FdLimits,
+        /// The actual limits.
+        actual: FdLimits,
+    },
+    /// A declared Casper service wasn't opened.
+    MissingService {
+        /// The service's name.
+        name: String,
+    },
+    /// A Casper service was opened, but wasn't declared.
+    UndeclaredService {
+        /// The service's name.
+        name: String,
+    },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Mode { declared, actual } => {
+                write!(f, "mode is {actual:?}, but {declared:?} was declared")
+            }
+            Drift::Missing { role } => write!(f, "{role} is not open"),
+            Drift::Undeclared { role, fd } => write!(f, "{role} (fd {fd}) was not declared"),
+            Drift::Limits {
+                role,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "{role} is limited to {}, but {} was declared",
+                Names(actual),
+                Names(declared)
+            ),
+            Drift::MissingService { name } => write!(f, "Casper service {name} was not opened"),
+            Drift::UndeclaredService { name } => {
+                write!(f, "Casper service {name} was not declared")
+            }
+        }
+    }
+}
+
+/// Display `FdLimits` by name.
+struct Names<'a>(&'a FdLimits);
+
+impl fmt::Display for Names<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self.0.rights.names().join(", "))?;
+        if let Some(fcntls) = &self.0.fcntls {
+            write!(f, " with fcntls [{}]", fcntls.names().join(", "))?;
+        }
+        if let IoctlRights::Limited(cmds) = &self.0.ioctls {
+            let cmds = cmds
+                .iter()
+                .map(|cmd| format!("{cmd:#x}"))
+                .collect::<Vec<_>>();
+            write!(f, " with ioctls [{}]", cmds.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare a declared manifest against the live process, and report every
+/// difference.
+///
+/// Descriptors are matched by role, not by number.  Their limits are
+/// compared exactly: a descriptor with fewer rights than declared is drift
+/// too, since the declaration no longer documents it.
+pub fn verify_manifest(declared: &Manifest) -> io::Result<Vec<Drift>> {
+    let actual = Manifest::capture()?;
+    let mut drift = Vec::new();
+    if declared.mode != actual.mode {
+        drift.push(Drift::Mode {
+            declared: declared.mode,
+            actual: actual.mode,
+        });
+    }
+    for d in &declared.descriptors {
+        match actual.descriptors.iter().find(|a| a.role == d.role) {
+            None => drift.push(Drift::Missing {
+                role: d.role.clone(),
+            }),
+            Some(a) if a.limits != normalize(d.limits.clone()) => drift.push(Drift::Limits {
+                role: d.role.clone(),
+                declared: d.limits.clone(),
+                actual: a.limits.clone(),
+            }),
+            Some(_) => (),
+        }
+    }
+    for a in &actual.descriptors {
+        if !declared.descriptors.iter().any(|d| d.role == a.role) {
+            drift.push(Drift::Undeclared {
+                role: a.role.clone(),
+                fd: a.fd,
+            });
+        }
+    }
+    for name in &declared.casper_services {
+        if !actual.casper_services.contains(name) {
+            drift.push(Drift::MissingService { name: name.clone() });
+        }
+    }
+    for name in &actual.casper_services {
+        if !declared.casper_services.contains(name) {
+            drift.push(Drift::UndeclaredService { name: name.clone() });
+        }
+    }
+    Ok(drift)
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Operations on descriptors, and the rights that they require.
+//!
+//! This one table serves both [`CapError`](crate::CapError), which looks up
+//! operations by name, and the Linux emulation, which compiles the ones with
+//! a syscall into its seccomp filter.  The rights follow
+//! [`rights(4)`](https://www.freebsd.org/cgi/man.cgi?query=rights).
+
+#[cfg(target_os = "linux")]
+use libc::c_long;
+
+#[cfg(target_os = "linux")]
+use crate::linux::seccomp::Cond;
+use crate::Right;
+
+/// An operation on a descriptor, and a right that it requires.
+pub(crate) struct Operation {
+    /// The name of the operation, usually that of the libc function.
+    pub name: &'static str,
+    /// The position of the descriptor argument.
+    pub arg: u8,
+    pub right: Right,
+    /// Whether the right is only required with some flags, which the name
+    /// alone doesn't imply.
+    pub flags: bool,
+    /// The syscall that performs the operation on Linux, if the filter can
+    /// check it.
+    #[cfg(target_os = "linux")]
+    pub syscall: Option<Syscall>,
+}
+
+/// A syscall that performs an [`Operation`].
+#[cfg(target_os = "linux")]
+pub(crate) struct Syscall {
+    pub nr: c_long,
+    /// Further conditions under which the right is required.
+    pub when: &'static [(u8, Cond)],
+}
+
+/// `op!(name, right)` is an operation that the Linux filter can't check.
+/// `op!(name, right, nr, arg, conds...)` is one that syscall `nr` performs,
+/// when its arguments meet `conds`.  And `op!(name, right, nr, arg, flags,
+/// conds...)` is one whose conditions aren't implied by its name.
+macro_rules! op {
+    ($name:literal, $right:ident) => {
+        Operation {
+            name: $name,
+            arg: 0,
+            right: Right::$right,
+            flags: false,
+            #[cfg(target_os = "linux")]
+            syscall: None,
+        }
+    };
+    ($name:literal, $right:ident, $nr:ident, $arg:literal, flags $(, $cond:expr)*) => {
+        Operation {
+            name: $name,
+            arg: $arg,
+            right: Right::$right,
+            flags: true,
+            #[cfg(target_os = "linux")]
+            syscall: Some(Syscall {
+                nr: libc::$nr,
+                when: &[$($cond),*],
+            }),
+        }
+    };
+    ($name:literal, $right:ident, $nr:ident, $arg:literal $(, $cond:expr)*) => {
+        Operation {
+            name: $name,
+            arg: $arg,
+            right: Right::$right,
+            flags: false,
+            #[cfg(target_os = "linux")]
+            syscall: Some(Syscall {
+                nr: libc::$nr,
+                when: &[$($cond),*],
+            }),
+        }
+    };
+}
+
+#[cfg(target_os = "linux")]
+const AT_EMPTY_PATH: u32 = libc::AT_EMPTY_PATH as u32;
+#[cfg(target_os = "linux")]
+const O_WRONLY: u32 = libc::O_WRONLY as u32;
+#[cfg(target_os = "linux")]
+const O_RDWR: u32 = libc::O_RDWR as u32;
+
+/// Operations on descriptors, and the rights that they require.
+///
+/// On Linux, syscalls that use a descriptor to look up a path require
+/// `CAP_LOOKUP`, unless they're passed `AT_EMPTY_PATH`.  An `AT_EMPTY_PATH`
+/// lookup that isn't actually empty will slip through, because the filter
+/// can't inspect the path.
+#[rustfmt::skip]
+pub(crate) const OPERATIONS: &[Operation] = &[
+    op!("read", Read, SYS_read, 0),
+    op!("readv", Read, SYS_readv, 0),
+    op!("recv", Read),
+    op!("recvfrom", Read, SYS_recvfrom, 0),
+    op!("recvmsg", Read, SYS_recvmsg, 0),
+    op!("recvmmsg", Read, SYS_recvmmsg, 0),
+    op!("pread", Pread, SYS_pread64, 0),
+    op!("preadv", Pread, SYS_preadv, 0),
+    op!("preadv2", Pread, SYS_preadv2, 0),
+    op!("readahead", Read, SYS_readahead, 0),
+    op!("getdents", Read, SYS_getdents64, 0),
+    op!("write", Write, SYS_write, 0),
+    op!("writev", Write, SYS_writev, 0),
+    op!("send", Write),
+    op!("sendto", Write, SYS_sendto, 0),
+    op!("sendmsg", Write, SYS_sendmsg, 0),
+    op!("sendmmsg", Write, SYS_sendmmsg, 0),
+    op!("pwrite", Pwrite, SYS_pwrite64, 0),
+    op!("pwritev", Pwrite, SYS_pwritev, 0),
+    op!("pwritev2", Pwrite, SYS_pwritev2, 0),
+    // FreeBSD's sendfile takes its descriptors the other way around.
+    #[cfg(target_os = "linux")]
+    op!("sendfile", Write, SYS_sendfile, 0),
+    #[cfg(target_os = "linux")]
+    op!("sendfile", Read, SYS_sendfile, 1),
+    op!("splice", Read, SYS_splice, 0),
+    op!("splice", Write, SYS_splice, 2),
+    op!("tee", Read, SYS_tee, 0),
+    op!("tee", Write, SYS_tee, 1),
+    // The filter can't tell which end of a pipe a descriptor is, so vmsplice
+    // requires both rights.
+    op!("vmsplice", Read, SYS_vmsplice, 0),
+    op!("vmsplice", Write, SYS_vmsplice, 0),
+    op!("copy_file_range", Read, SYS_copy_file_range, 0),
+    op!("copy_file_range", Write, SYS_copy_file_range, 2),
+    op!("lseek", Seek, SYS_lseek, 0),
+    op!("seek", Seek),
+    op!("mmap", Mmap, SYS_mmap, 4),
+    op!("mmap", MmapR, SYS_mmap, 4, flags, (2, Cond::AnySet(libc::PROT_READ as u32))),
+    op!("mmap", MmapX, SYS_mmap, 4, flags, (2, Cond::AnySet(libc::PROT_EXEC as u32))),
+    op!("mmap", MmapW, SYS_mmap, 4, flags,
+        (2, Cond::AnySet(libc::PROT_WRITE as u32)),
+        (3, Cond::AnySet(libc::MAP_SHARED as u32))),
+    op!("fexecve", Fexecve, SYS_execveat, 0),
+    op!("execveat", Lookup, SYS_execveat, 0, (4, Cond::NoneSet(AT_EMPTY_PATH))),
+    op!("fsync", Fsync, SYS_fsync, 0),
+    op!("fdatasync", Fsync, SYS_fdatasync, 0),
+    op!("sync_file_range", Fsync, SYS_sync_file_range, 0),
+    op!("syncfs", Fsync, SYS_syncfs, 0),
+    op!("ftruncate", Ftruncate, SYS_ftruncate, 0),
+    op!("fallocate", Ftruncate, SYS_fallocate, 0),
+    op!("fchdir", Fchdir, SYS_fchdir, 0),
+    op!("fchflags", Fchflags),
+    op!("fchmod", Fchmod, SYS_fchmod, 0),
+    op!("fchown", Fchown, SYS_fchown, 0),
+    op!("fchown", Fchown, SYS_fchownat, 0),
+    op!("fcntl", Fcntl),
+    op!("flock", Flock, SYS_flock, 0),
+    op!("fpathconf", Fpathconf),
+    // libc's fstat may be newfstatat, with an empty path.
+    op!("fstat", Fstat, SYS_fstat, 0),
+    op!("fstat", Fstat, SYS_newfstatat, 0),
+    op!("statx", Fstat, SYS_statx, 0),
+    op!("fstatfs", Fstatfs, SYS_fstatfs, 0),
+    // With a null path, utimensat operates on the descriptor itself.
+    op!("futimens", Futimes, SYS_utimensat, 0),
+    op!("futimes", Futimes),
+    op!("ioctl", Ioctl, SYS_ioctl, 0),
+    op!("openat", Lookup, SYS_openat, 0),
+    // O_RDONLY is zero, so any open without O_WRONLY can read.
+    op!("openat", Read, SYS_openat, 0, flags,
+        (2, Cond::NoneSet(O_WRONLY | libc::O_PATH as u32))),
+    op!("openat", Write, SYS_openat, 0, flags, (2, Cond::AnySet(O_WRONLY | O_RDWR))),
+    op!("openat", Seek, SYS_openat, 0, flags,
+        (2, Cond::AnySet(O_WRONLY | O_RDWR)),
+        (2, Cond::NoneSet(libc::O_APPEND as u32))),
+    op!("openat", Create, SYS_openat, 0, flags, (2, Cond::AnySet(libc::O_CREAT as u32))),
+    op!("openat", Ftruncate, SYS_openat, 0, flags, (2, Cond::AnySet(libc::O_TRUNC as u32))),
+    op!("chflagsat", Chflagsat),
+    op!("faccessat", Fstatat, SYS_faccessat, 0),
+    op!("faccessat", Fstat, SYS_faccessat2, 0),
+    op!("faccessat", Fstatat, SYS_faccessat2, 0, (3, Cond::NoneSet(AT_EMPTY_PATH))),
+    op!("fchmodat", Fchmodat, SYS_fchmodat, 0),
+    op!("fchownat", Fchownat, SYS_fchownat, 0, (4, Cond::NoneSet(AT_EMPTY_PATH))),
+    op!("fstatat", Fstatat, SYS_newfstatat, 0, (3, Cond::NoneSet(AT_EMPTY_PATH))),
+    op!("statx", Fstatat, SYS_statx, 0, (2, Cond::NoneSet(AT_EMPTY_PATH))),
+    #[cfg(any(target_os = "freebsd", target_arch = "x86_64"))]
+    op!("futimesat", Futimesat, SYS_futimesat, 0),
+    op!("utimensat", Futimesat, SYS_utimensat, 0, (1, Cond::NonZero)),
+    op!("readlinkat", Lookup, SYS_readlinkat, 0),
+    op!("linkat", LinkatSource, SYS_linkat, 0),
+    op!("linkat", LinkatTarget, SYS_linkat, 2),
+    op!("mkdirat", Mkdirat, SYS_mkdirat, 0),
+    op!("mkfifoat", Mkfifoat),
+    op!("mknodat", Mknodat, SYS_mknodat, 0),
+    #[cfg(any(target_os = "freebsd", target_arch = "x86_64"))]
+    op!("renameat", RenameatSource, SYS_renameat, 0),
+    #[cfg(any(target_os = "freebsd", target_arch = "x86_64"))]
+    op!("renameat", RenameatTarget, SYS_renameat, 2),
+    // Renaming over an existing file unlinks it.  The filter can't tell if
+    // the target exists, so renameat2 must promise not to replace it.
+    #[cfg(any(target_os = "freebsd", target_arch = "x86_64"))]
+    op!("renameat", Unlinkat, SYS_renameat, 2),
+    // Elsewhere, libc's renameat is renameat2.
+    #[cfg(all(target_os = "linux", not(target_arch = "x86_64")))]
+    op!("renameat", RenameatSource),
+    op!("renameat2", RenameatSource, SYS_renameat2, 0),
+    op!("renameat2", RenameatTarget, SYS_renameat2, 2),
+    op!("renameat2", Unlinkat, SYS_renameat2, 2, flags,
+        (4, Cond::NoneSet(libc::RENAME_NOREPLACE))),
+    op!("symlinkat", Symlinkat, SYS_symlinkat, 1),
+    op!("unlinkat", Unlinkat, SYS_unlinkat, 0),
+    op!("name_to_handle_at", Lookup, SYS_name_to_handle_at, 0),
+    op!("fanotify_mark", Lookup, SYS_fanotify_mark, 3),
+    op!("accept", Accept, SYS_accept, 0),
+    op!("accept4", Accept, SYS_accept4, 0),
+    op!("bind", Bind, SYS_bind, 0),
+    op!("bindat", Bindat),
+    op!("connect", Connect, SYS_connect, 0),
+    op!("connectat", Connectat),
+    op!("getpeername", Getpeername, SYS_getpeername, 0),
+    op!("getsockname", Getsockname, SYS_getsockname, 0),
+    op!("getsockopt", Getsockopt, SYS_getsockopt, 0),
+    op!("listen", Listen, SYS_listen, 0),
+    op!("setsockopt", Setsockopt, SYS_setsockopt, 0),
+    op!("shutdown", Shutdown, SYS_shutdown, 0),
+    op!("kevent", Event),
+    op!("epoll_ctl", Event, SYS_epoll_ctl, 2),
+    // poll and select take their descriptors from memory, out of the
+    // filter's reach.
+    op!("poll", Event),
+    op!("select", Event),
+    op!("pdgetpid", Pdgetpid),
+    op!("pdkill", Pdkill, SYS_pidfd_send_signal, 0),
+    op!("pdwait", Pdwait, SYS_waitid, 1, (0, Cond::Eq(libc::P_PIDFD))),
+    op!("extattr_delete_fd", ExtattrDelete, SYS_fremovexattr, 0),
+    op!("extattr_get_fd", ExtattrGet, SYS_fgetxattr, 0),
+    op!("extattr_list_fd", ExtattrList, SYS_flistxattr, 0),
+    op!("extattr_set_fd", ExtattrSet, SYS_fsetxattr, 0),
+];
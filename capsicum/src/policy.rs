@@ -0,0 +1,545 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Declarative sandbox policies, loaded from TOML.
+//!
+//! A policy file describes a process's sandbox: the files and directories to
+//! open before entering capability mode, with their limits; the limits of
+//! the standard streams; the Casper services to open, with their limits; and
+//! whether to enter capability mode at all.  Keeping it out of the binary
+//! lets a sandbox be reviewed, versioned, and tightened without recompiling.
+//!
+//! Every name is checked when the policy is loaded, so a typo fails early,
+//! rather than after some descriptors were already limited.
+//!
+//! On Linux, limits are only enforced in capability mode.  A policy with
+//! `enter = false` still records them, and they take effect if the process
+//! enters capability mode later.  But until then they aren't enforced, and
+//! if the process executes another program first, they're lost.
+//!
+//! # Format
+//!
+//! ```toml
+//! # Whether to enter capability mode.  The default is true.
+//! enter = true
+//!
+//! # Each preopen is opened with open(2), and registered with a Sandbox under
+//! # its name.
+//! [[preopen]]
+//! name = "data"
+//! path = "/var/db/app"
+//! # Open flags by name.  The default is ["O_RDONLY"].  O_CLOEXEC is implied.
+//! flags = ["O_RDONLY", "O_DIRECTORY"]
+//! # Capability rights, by the names of cap_rights_limit(2).
+//! rights = ["CAP_LOOKUP", "CAP_READ", "CAP_FSTAT", "CAP_FCNTL"]
+//! # Optional.  Fcntl commands, by the names of cap_fcntls_limit(2).
+//! fcntls = ["CAP_FCNTL_GETFL"]
+//! # Optional.  Ioctl commands, by name, like "FIONREAD", or by number.
+//! ioctls = []
+//!
+//! # Optional.  Limit the standard streams with limit_stdio.
+//! [stdio]
+//! seekable = false     # See StdioPolicy::seekable
+//! tty = true           # If false, see StdioPolicy::no_tty
+//! unlimited = []       # Streams to leave alone, like "stderr"
+//!
+//! # Casper services, by name, with their limits.  FreeBSD only, with the
+//! # casper feature.  Elsewhere, the policy is refused.
+//! [casper."system.grp"]
+//! limits = { cmds = ["getgrgid"], fields = ["gr_name"] }
+//! ```
+//!
+//! Casper limits are service-defined nvlists, so they are translated
+//! mechanically: a table becomes an nvlist, a string a string, a
+//! non-negative integer a number, and a boolean a bool.  An array of strings
+//! becomes an nvlist with a null value for each, which is how the `system.grp`
+//! and `system.pwd` services list their commands and fields.
+//!
+//! # Example
+//! ```
+//! # use std::{fs::File, io::Read};
+//! use capsicum::policy::Policy;
+//!
+//! let policy = Policy::from_toml(r#"
+//!     [[preopen]]
+//!     name = "passwd"
+//!     path = "/etc/passwd"
+//!     rights = ["CAP_READ"]
+//!
+//!     [stdio]
+//! "#).unwrap();
+//! let mut fds = policy.apply().unwrap();
+//!
+//! let mut passwd = File::from(fds.take("passwd").unwrap());
+//! let mut s = String::new();
+//! passwd.read_to_string(&mut s).unwrap();
+//! File::open("/etc/group").unwrap_err();
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io,
+    os::fd::{BorrowedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    fs,
+    process::{Executable, SandboxedCommand},
+    sys::u_long,
+    FcntlRights,
+    FdLimits,
+    FileRights,
+    IoctlRights,
+    Sandbox,
+    SandboxFds,
+    StdioPolicy,
+};
+
+/// The open flags that a preopen may name.
+const FLAGS: &[(libc::c_int, &str)] = &[
+    (libc::O_RDONLY, "O_RDONLY"),
+    (libc::O_WRONLY, "O_WRONLY"),
+    (libc::O_RDWR, "O_RDWR"),
+    (libc::O_APPEND, "O_APPEND"),
+    (libc::O_CREAT, "O_CREAT"),
+    (libc::O_TRUNC, "O_TRUNC"),
+    (libc::O_EXCL, "O_EXCL"),
+    (libc::O_DIRECTORY, "O_DIRECTORY"),
+    (libc::O_NOFOLLOW, "O_NOFOLLOW"),
+    (libc::O_NONBLOCK, "O_NONBLOCK"),
+    (libc::O_SYNC, "O_SYNC"),
+];
+
+/// The ioctls that a preopen may name.  Others may be given by number.
+#[cfg(target_os = "freebsd")]
+const IOCTLS: &[(u_long, &str)] = &[
+    (libc::FIOCLEX, "FIOCLEX"),
+    (libc::FIONCLEX, "FIONCLEX"),
+    (libc::FIONREAD, "FIONREAD"),
+    (libc::FIONBIO, "FIONBIO"),
+    (libc::FIOASYNC, "FIOASYNC"),
+    (libc::TIOCGETA, "TIOCGETA"),
+    (libc::TIOCGWINSZ, "TIOCGWINSZ"),
+];
+#[cfg(target_os = "linux")]
+const IOCTLS: &[(u_long, &str)] = &[
+    (libc::FIOCLEX as u_long, "FIOCLEX"),
+    (libc::FIONCLEX as u_long, "FIONCLEX"),
+    (libc::FIONREAD as u_long, "FIONREAD"),
+    (libc::FIONBIO as u_long, "FIONBIO"),
+    (libc::FIOASYNC as u_long, "FIOASYNC"),
+    (libc::TCGETS as u_long, "TCGETS"),
+    (libc::TCGETS2 as u_long, "TCGETS2"),
+    (libc::TIOCGWINSZ as u_long, "TIOCGWINSZ"),
+];
+
+/// The mode that preopens are created with, before the umask.
+const MODE: libc::c_uint = 0o666;
+
+/// A policy file, as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Raw {
+    #[serde(default = "yes")]
+    enter: bool,
+    #[serde(default)]
+    preopen: Vec<RawPreopen>,
+    stdio: Option<RawStdio>,
+    #[serde(default)]
+    casper: BTreeMap<String, RawService>,
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPreopen {
+    name: String,
+    path: PathBuf,
+    #[serde(default)]
+    flags: Vec<String>,
+    rights: Vec<String>,
+    fcntls: Option<Vec<String>>,
+    ioctls: Option<Vec<RawIoctl>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawIoctl {
+    Name(String),
+    Number(u64),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStdio {
+    #[serde(default)]
+    seekable: bool,
+    #[serde(default = "yes")]
+    tty: bool,
+    #[serde(default)]
+    unlimited: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawService {
+    #[serde(default)]
+    limits: toml::Table,
+}
+
+/// A sandbox policy, loaded from TOML and checked.
+///
+/// See the [module documentation](self) for the format.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    enter: bool,
+    preopens: Vec<Preopen>,
+    stdio: Option<StdioPolicy>,
+    casper: BTreeMap<String, toml::Table>,
+}
+
+#[derive(Clone, Debug)]
+struct Preopen {
+    name: String,
+    path: PathBuf,
+    flags: libc::c_int,
+    limits: FdLimits,
+}
+
+/// An error in a policy file.
+fn invalid<E: Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl Policy {
+    /// Parse and check a policy.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidData`](io::ErrorKind::InvalidData) - `toml` is malformed,
+    ///   names an unknown right, flag, ioctl, or stream, or declares two
+    ///   preopens with the same name.
+    /// * [`InvalidData`](io::ErrorKind::InvalidData) - `toml` declares Casper
+    ///   services, but `Policy::open_services` isn't available: on Linux, or
+    ///   without the `casper` feature.
+    pub fn from_toml(toml: &str) -> io::Result<Policy> {
+        let raw: Raw = toml::from_str(toml).map_err(invalid)?;
+        let preopens = raw
+            .preopen
+            .into_iter()
+            .map(Preopen::new)
+            .collect::<io::Result<Vec<_>>>()?;
+        for (i, preopen) in preopens.iter().enumerate() {
+            if preopens[..i].iter().any(|p| p.name == preopen.name) {
+                return Err(invalid(format!("preopen {}: declared twice", preopen.name)));
+            }
+        }
+        let stdio = raw.stdio.map(RawStdio::policy).transpose()?;
+        let casper = raw
+            .casper
+            .into_iter()
+            .map(|(name, service)| {
+                check_limits(&service.limits)
+                    .map_err(|e| invalid(format!("casper service {name}: {e}")))?;
+                if cfg!(not(all(feature = "casper", target_os = "freebsd"))) {
+                    return Err(invalid(format!(
+                        "casper service {name}: opening Casper services needs FreeBSD and the \
+                         casper feature"
+                    )));
+                }
+                Ok((name, service.limits))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Policy {
+            enter: raw.enter,
+            preopens,
+            stdio,
+            casper,
+        })
+    }
+
+    /// Read, parse, and check a policy file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Policy> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)?;
+        Policy::from_toml(&toml).map_err(|e| invalid(format!("{}: {e}", path.display())))
+    }
+
+    /// Does the policy enter capability mode?
+    pub fn enters(&self) -> bool {
+        self.enter
+    }
+
+    /// The names of the Casper services that the policy opens.
+    pub fn casper_services(&self) -> impl Iterator<Item = &str> {
+        self.casper.keys().map(String::as_str)
+    }
+
+    /// The names of the preopens, in the order they're declared.
+    pub fn preopen_names(&self) -> impl Iterator<Item = &str> {
+        self.preopens.iter().map(|preopen| preopen.name.as_str())
+    }
+
+    /// Open every preopen, and register it with a new [`Sandbox`] under its
+    /// name.  The standard streams are limited by a pre-enter hook.
+    ///
+    /// Use this to add more descriptors or hooks before entering capability
+    /// mode.  It doesn't consider whether the policy [`enters`](Self::enters).
+    pub fn sandbox(&self) -> io::Result<Sandbox> {
+        let mut sandbox = Sandbox::new();
+        for preopen in &self.preopens {
+            let file = preopen.open()?;
+            sandbox = sandbox.fd(&preopen.name, file, preopen.limits.clone());
+        }
+        if let Some(stdio) = self.stdio.clone() {
+            sandbox = sandbox.pre_enter(move || crate::limit_stdio(stdio));
+        }
+        Ok(sandbox)
+    }
+
+    /// Open and limit every preopen, limit the standard streams, and enter
+    /// capability mode, unless the policy says not to.
+    ///
+    /// Casper services must be opened beforehand, with
+    /// [`Policy::open_services`].
+    pub fn apply(&self) -> io::Result<SandboxFds> {
+        let sandbox = self.sandbox()?;
+        if self.enter {
+            sandbox.enter()
+        } else {
+            sandbox.limit()
+        }
+    }
+
+    /// Build a command that runs `executable` under the policy.
+    ///
+    /// The child inherits the preopens at consecutive descriptor numbers from
+    /// `first`, in the order they're declared, and the standard streams, each
+    /// limited as the policy says.  Streams that the policy leaves alone keep
+    /// the limits they have, and closed ones stay closed.  The child enters
+    /// capability mode before it executes the program, unless the policy says
+    /// not to.
+    ///
+    /// # Errors
+    ///
+    /// * `EINVAL` - `first` is less than 3.
+    ///
+    /// Any error from opening a preopen, or duplicating a standard stream, is
+    /// returned too.
+    pub fn command(&self, executable: Executable, first: RawFd) -> io::Result<SandboxedCommand> {
+        if first < 3 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let mut command = SandboxedCommand::from_executable(executable).capability_mode(self.enter);
+        for (preopen, fd) in self.preopens.iter().zip(first..) {
+            command = command.fd(fd, preopen.open()?, preopen.limits.clone());
+        }
+        for fd in 0..3 {
+            let stream = match unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned() {
+                Ok(stream) => stream,
+                Err(e) if e.raw_os_error() == Some(libc::EBADF) => continue,
+                Err(e) => return Err(e),
+            };
+            let limits = match self.stdio.as_ref() {
+                Some(stdio) => stdio.streams[fd as usize].clone(),
+                None => None,
+            };
+            let limits = match limits {
+                Some(limits) => limits,
+                None => FdLimits::from_file(&stream)?,
+            };
+            command = command.fd(fd, stream, limits);
+        }
+        Ok(command)
+    }
+
+    /// Open and limit every Casper service, then limit `casper` so that it
+    /// can open no others.
+    ///
+    /// The returned channels can be wrapped in the structs declared by
+    /// [`service_connection!`](crate::casper::service_connection).
+    #[cfg(all(feature = "casper", target_os = "freebsd"))]
+    #[cfg_attr(docsrs, doc(cfg(feature = "casper")))]
+    pub fn open_services(
+        &self,
+        casper: &mut crate::casper::Casper,
+    ) -> io::Result<BTreeMap<String, crate::casper::CapChannel>> {
+        let mut channels = BTreeMap::new();
+        let mut names = Vec::with_capacity(self.casper.len());
+        for (name, limits) in &self.casper {
+            let cname = std::ffi::CString::new(name.as_str()).map_err(invalid)?;
+            let mut chan = casper.service_open(&cname)?;
+            if !limits.is_empty() {
+                let nvl = nvlist(limits);
+                let e = unsafe { libnv_sys::nvlist_error(nvl) };
+                if e != 0 {
+                    unsafe { libnv_sys::nvlist_destroy(nvl) };
+                    return Err(io::Error::from_raw_os_error(e));
+                }
+                // cap_limit_set takes ownership of the nvlist.
+                let r = unsafe { crate::casper::sys::cap_limit_set(chan.as_mut_ptr(), nvl) };
+                if r < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            channels.insert(name.clone(), chan);
+            names.push(cname);
+        }
+        casper.limit_services(&names)?;
+        Ok(channels)
+    }
+}
+
+impl Preopen {
+    fn new(raw: RawPreopen) -> io::Result<Preopen> {
+        let context = |e: String| invalid(format!("preopen {}: {e}", raw.name));
+        let unknown = |what: &str, name: &str| context(format!("unknown {what} {name:?}"));
+        let mut flags = libc::O_RDONLY;
+        for name in &raw.flags {
+            let (flag, _) = FLAGS
+                .iter()
+                .find(|(_, n)| n == name)
+                .ok_or_else(|| unknown("flag", name))?;
+            flags |= flag;
+        }
+        let rights = FileRights::from_names(raw.rights.iter().map(String::as_str))
+            .map_err(|name| unknown("right", name))?;
+        let fcntls = raw
+            .fcntls
+            .as_ref()
+            .map(|names| FcntlRights::from_names(names.iter().map(String::as_str)))
+            .transpose()
+            .map_err(|name| unknown("fcntl", name))?;
+        let ioctls = match raw.ioctls {
+            None => IoctlRights::Unlimited,
+            Some(ioctls) => IoctlRights::Limited(
+                ioctls
+                    .iter()
+                    .map(|ioctl| match ioctl {
+                        RawIoctl::Name(name) => IOCTLS
+                            .iter()
+                            .find(|(_, n)| n == name)
+                            .map(|(cmd, _)| *cmd)
+                            .ok_or_else(|| unknown("ioctl", name)),
+                        RawIoctl::Number(cmd) => {
+                            u_long::try_from(*cmd).map_err(|_| unknown("ioctl", &cmd.to_string()))
+                        }
+                    })
+                    .collect::<io::Result<_>>()?,
+            ),
+        };
+        Ok(Preopen {
+            name: raw.name,
+            path: raw.path,
+            flags,
+            limits: FdLimits {
+                rights,
+                fcntls,
+                ioctls,
+            },
+        })
+    }
+
+    fn open(&self) -> io::Result<File> {
+        let path = fs::cstring(&self.path)?;
+        let flags = self.flags | libc::O_CLOEXEC;
+        fs::file(unsafe { libc::open(path.as_ptr(), flags, MODE) }).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("preopen {}: {}: {e}", self.name, self.path.display()),
+            )
+        })
+    }
+}
+
+impl RawStdio {
+    fn policy(self) -> io::Result<StdioPolicy> {
+        let mut policy = StdioPolicy::new();
+        if self.seekable {
+            policy = policy.seekable();
+        }
+        if !self.tty {
+            policy = policy.no_tty();
+        }
+        for stream in &self.unlimited {
+            policy = match stream.as_str() {
+                "stdin" => policy.stdin(None),
+                "stdout" => policy.stdout(None),
+                "stderr" => policy.stderr(None),
+                _ => return Err(invalid(format!("stdio: unknown stream {stream:?}"))),
+            };
+        }
+        Ok(policy)
+    }
+}
+
+/// Can `limits` be translated to an nvlist?
+fn check_limits(limits: &toml::Table) -> Result<(), String> {
+    let c_str = |s: &str| !s.contains('\0');
+    for (key, value) in limits {
+        let ok = c_str(key)
+            && match value {
+                toml::Value::String(s) => c_str(s),
+                toml::Value::Boolean(_) => true,
+                toml::Value::Integer(n) => *n >= 0,
+                toml::Value::Table(table) => {
+                    check_limits(table)?;
+                    true
+                }
+                toml::Value::Array(a) => a.iter().all(|v| v.as_str().is_some_and(c_str)),
+                _ => false,
+            };
+        if !ok {
+            return Err(format!("limit {key:?} can't be expressed as an nvlist"));
+        }
+    }
+    Ok(())
+}
+
+/// Translate Casper service limits to an nvlist, as described in the
+/// [module documentation](self).
+///
+/// Like all of libnv, this never fails outright.  Instead, the nvlist records
+/// the first error, for `nvlist_error(3)`.
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
+fn nvlist(limits: &toml::Table) -> *mut libnv_sys::nvlist_t {
+    use std::ffi::CString;
+
+    use libnv_sys::*;
+
+    // NULs were ruled out by check_limits.
+    let cstring = |s: &str| CString::new(s).unwrap();
+    let nvl = unsafe { nvlist_create(0) };
+    for (key, value) in limits {
+        let key = cstring(key);
+        match value {
+            toml::Value::String(s) => unsafe {
+                nvlist_add_string(nvl, key.as_ptr(), cstring(s).as_ptr())
+            },
+            toml::Value::Integer(n) => unsafe { nvlist_add_number(nvl, key.as_ptr(), *n as u64) },
+            toml::Value::Boolean(b) => unsafe { nvlist_add_bool(nvl, key.as_ptr(), *b) },
+            toml::Value::Table(table) => unsafe {
+                nvlist_move_nvlist(nvl, key.as_ptr(), nvlist(table))
+            },
+            toml::Value::Array(names) => {
+                let child = unsafe { nvlist_create(0) };
+                for name in names.iter().filter_map(toml::Value::as_str) {
+                    unsafe { nvlist_add_null(child, cstring(name).as_ptr()) }
+                }
+                unsafe { nvlist_move_nvlist(nvl, key.as_ptr(), child) }
+            }
+            // Ruled out by check_limits.
+            _ => unreachable!(),
+        }
+    }
+    nvl
+}
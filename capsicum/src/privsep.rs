@@ -0,0 +1,302 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Privilege separation: a privileged parent, and a sandboxed child that asks
+//! it for what it can't do itself.
+//!
+//! [`fork`] forks a child, which enters capability mode with a [`Sandbox`]
+//! and then runs a closure.  The two processes are connected by a socket.
+//! The child sends typed requests over it with [`Child::call`], and the
+//! parent answers them with [`Parent::serve`], after checking each against
+//! its policy.  Requests and responses are serialized with serde, and either
+//! may carry descriptors, each labeled with its [`FdLimits`] like with
+//! [`send_fds`](crate::send_fds).  On Linux, though, the child can't send
+//! descriptors, since its limits are enforced.  And it can only hold 32 of
+//! the descriptors that it receives with the same limits open at once, as
+//! explained at [`recv_fds`](crate::recv_fds).
+//!
+//! # Example
+//! ```
+//! use std::{fs::File, io::Read};
+//!
+//! use capsicum::{privsep, FdLimits, FileRights, Right, Sandbox};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize, Serialize)]
+//! enum Request {
+//!     Open(String),
+//! }
+//!
+//! let parent = unsafe {
+//!     privsep::fork(Sandbox::new(), |_fds, mut parent| {
+//!         let mut reply = parent.call(Request::Open("/etc/passwd".into()))?;
+//!         let (fd, _) = reply.fds.pop().unwrap();
+//!         let mut passwd = String::new();
+//!         File::from(fd).read_to_string(&mut passwd)?;
+//!
+//!         // The parent's policy only allows one file
+//!         parent.call(Request::Open("/etc/group".into())).unwrap_err();
+//!         Ok(())
+//!     })
+//! }
+//! .unwrap();
+//!
+//! let ro = FdLimits::new(*FileRights::new().allow(Right::Read));
+//! let status = parent
+//!     .policy(|Request::Open(path)| match path.as_str() {
+//!         "/etc/passwd" => Ok(()),
+//!         _ => Err(std::io::ErrorKind::PermissionDenied.into()),
+//!     })
+//!     .serve(|request| {
+//!         let Request::Open(path) = request.body;
+//!         let file = File::open(path)?;
+//!         Ok(privsep::Message::new(()).fd(file, ro.clone()))
+//!     })
+//!     .unwrap();
+//! assert!(status.success());
+//! ```
+
+use std::{
+    fmt,
+    io,
+    marker::PhantomData,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+    panic::{self, AssertUnwindSafe},
+    process::ExitStatus,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    fdpass,
+    process::{Fork, ProcessDescriptor},
+    CapRights,
+    FdLimits,
+    FileRights,
+    Right,
+    Sandbox,
+    SandboxFds,
+};
+
+/// A request or a response, and the descriptors that go with it.
+#[derive(Debug)]
+pub struct Message<T> {
+    /// The request or response itself.
+    pub body: T,
+    /// Descriptors, each with its limits.
+    pub fds: Vec<(OwnedFd, FdLimits)>,
+}
+
+impl<T> Message<T> {
+    /// Create a new `Message` with no descriptors.
+    pub fn new(body: T) -> Self {
+        Message {
+            body,
+            fds: Vec::new(),
+        }
+    }
+
+    /// Attach a descriptor, to be limited to `limits` before it's sent.
+    pub fn fd<F: Into<OwnedFd>>(mut self, fd: F, limits: FdLimits) -> Self {
+        self.fds.push((fd.into(), limits));
+        self
+    }
+}
+
+impl<T> From<T> for Message<T> {
+    fn from(body: T) -> Self {
+        Message::new(body)
+    }
+}
+
+/// What the parent sends back: a response, or the error that it got instead.
+#[derive(Deserialize, Serialize)]
+enum Reply<T> {
+    Ok(T),
+    Err { errno: Option<i32>, message: String },
+}
+
+type Policy<Req> = Box<dyn FnMut(&Req) -> io::Result<()>>;
+
+/// The parent's end of a privilege-separated pair of processes.
+pub struct Parent<Req, Resp> {
+    socket: UnixStream,
+    child: ProcessDescriptor,
+    policy: Option<Policy<Req>>,
+    _resp: PhantomData<fn(Resp)>,
+}
+
+impl<Req, Resp> Parent<Req, Resp>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+{
+    /// The child process.
+    pub fn child(&self) -> &ProcessDescriptor {
+        &self.child
+    }
+
+    /// Check every request with `policy` before it's handled.  If the policy
+    /// returns an error, the request is refused, and the child gets the
+    /// error.
+    pub fn policy<P>(mut self, policy: P) -> Self
+    where
+        P: FnMut(&Req) -> io::Result<()> + 'static,
+    {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Answer the child's requests with `handler`, until the child closes its
+    /// end of the socket, and then reap it.
+    ///
+    /// If the handler returns an error, the child gets it, and serving goes
+    /// on.  So do requests that can't be deserialized, which the child gets
+    /// `EBADMSG` for.
+    pub fn serve<H>(mut self, mut handler: H) -> io::Result<ExitStatus>
+    where
+        H: FnMut(Message<Req>) -> io::Result<Message<Resp>>,
+    {
+        loop {
+            let (fds, payload) = match fdpass::recv(&self.socket) {
+                Ok(message) => message,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            let response = serde_json::from_slice(&payload)
+                .map_err(|_| io::Error::from_raw_os_error(libc::EBADMSG))
+                .and_then(|body: Req| {
+                    if let Some(policy) = &mut self.policy {
+                        policy(&body)?;
+                    }
+                    handler(Message { body, fds })
+                });
+            match response {
+                Ok(response) => send(&self.socket, Reply::Ok(response.body), &response.fds)?,
+                Err(e) => {
+                    let reply = Reply::<Resp>::Err {
+                        errno: e.raw_os_error(),
+                        message: e.to_string(),
+                    };
+                    send(&self.socket, reply, &[])?
+                }
+            }
+        }
+        self.child.wait()
+    }
+}
+
+impl<Req, Resp> fmt::Debug for Parent<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Parent")
+            .field("socket", &self.socket)
+            .field("child", &self.child)
+            .field("policy", &self.policy.is_some())
+            .finish()
+    }
+}
+
+/// The sandboxed child's end of a privilege-separated pair of processes.
+#[derive(Debug)]
+pub struct Child<Req, Resp> {
+    socket: UnixStream,
+    _types: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req, Resp> Child<Req, Resp>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+{
+    /// Send a request to the parent, and wait for its response.
+    ///
+    /// # Errors
+    ///
+    /// Any error that the parent's policy or handler returned, or any error
+    /// from the socket.
+    pub fn call<M: Into<Message<Req>>>(&mut self, request: M) -> io::Result<Message<Resp>> {
+        let request = request.into();
+        send(&self.socket, request.body, &request.fds)?;
+        let (fds, payload) = fdpass::recv(&self.socket)?;
+        let reply = serde_json::from_slice(&payload)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EBADMSG))?;
+        match reply {
+            Reply::Ok(body) => Ok(Message { body, fds }),
+            Reply::Err {
+                errno: Some(errno), ..
+            } => Err(io::Error::from_raw_os_error(errno)),
+            Reply::Err { message, .. } => Err(io::Error::other(message)),
+        }
+    }
+}
+
+/// Serialize a message, and send it with its descriptors.
+fn send<S: AsFd, T: Serialize>(socket: &S, body: T, fds: &[(OwnedFd, FdLimits)]) -> io::Result<()> {
+    let payload = serde_json::to_vec(&body).map_err(io::Error::other)?;
+    let fds = fds
+        .iter()
+        .map(|(fd, limits)| (fd.as_fd(), limits))
+        .collect::<Vec<_>>();
+    fdpass::send(socket, &fds, &payload)
+}
+
+/// Fork a sandboxed child, connected to the parent by a socket.
+///
+/// The child enters capability mode with `sandbox`, and then runs `child`
+/// with the sandbox's descriptors and its end of the socket.  The child exits
+/// with status 0 if `child` returns `Ok`, 1 if it returns an error, and 101
+/// if it panics.  The parent gets its end of the socket, and should
+/// [`serve`](Parent::serve) the child's requests.
+///
+/// The child's end of the socket is limited to sending and receiving.
+///
+/// # Safety
+///
+/// The same as for [`ProcessDescriptor::fork`].  In particular, if the parent
+/// is multithreaded, then the child may deadlock if it allocates memory.
+pub unsafe fn fork<Req, Resp, F>(sandbox: Sandbox, child: F) -> io::Result<Parent<Req, Resp>>
+where
+    F: FnOnce(SandboxFds, Child<Req, Resp>) -> io::Result<()>,
+{
+    let (parent, socket) = UnixStream::pair()?;
+    match ProcessDescriptor::fork()? {
+        Fork::Child => {
+            drop(parent);
+            let run = || -> io::Result<()> {
+                FileRights::new()
+                    .allow(Right::Read)
+                    .allow(Right::Write)
+                    .allow(Right::Getsockopt)
+                    .limit(&socket)?;
+                let fds = sandbox.enter()?;
+                child(
+                    fds,
+                    Child {
+                        socket,
+                        _types: PhantomData,
+                    },
+                )
+            };
+            let status = match panic::catch_unwind(AssertUnwindSafe(run)) {
+                Ok(Ok(())) => 0,
+                Ok(Err(_)) => 1,
+                Err(_) => 101,
+            };
+            libc::_exit(status)
+        }
+        Fork::Parent(pd) => {
+            drop(sandbox);
+            drop(socket);
+            Ok(Parent {
+                socket: parent,
+                child: pd,
+                policy: None,
+                _resp: PhantomData,
+            })
+        }
+    }
+}
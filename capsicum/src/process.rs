@@ -2,35 +2,195 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::io;
+//! Capability mode, and the processes that run in it.
+
+use std::{io, os::fd::RawFd};
+
+pub use self::{
+    command::SandboxedCommand,
+    descriptor::{Fork, ProcessDescriptor},
+    executable::Executable,
+};
+
+mod command;
+mod descriptor;
+mod executable;
+
+#[cfg(target_os = "linux")]
+use crate::linux::capmode as imp;
+use crate::{audit, trace};
+
+#[cfg(target_os = "freebsd")]
+mod imp {
+    use std::io;
+
+    use crate::Mode;
+
+    pub(crate) fn enter() -> io::Result<()> {
+        if unsafe { libc::cap_enter() } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn sandboxed() -> bool {
+        unsafe { libc::cap_sandboxed() }
+    }
+
+    pub(crate) fn get_mode() -> io::Result<bool> {
+        let mut mode = 0;
+        unsafe {
+            if libc::cap_getmode(&mut mode) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(mode != 0)
+    }
+
+    pub(crate) fn mode() -> Mode {
+        match get_mode() {
+            Ok(true) => Mode::Capsicum,
+            _ => Mode::None,
+        }
+    }
+}
+
+/// The mechanisms that confine a process, as reported by [`mode`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(
+    feature = "manifest",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "kebab-case")
+)]
+#[non_exhaustive]
+pub enum Mode {
+    /// The process is not confined.
+    None,
+    /// The process is in Capsicum's capability mode.  FreeBSD only.
+    Capsicum,
+    /// The process is confined by a seccomp filter.  Linux only.
+    ///
+    /// That includes the filter installed by [`enter`], but also any other,
+    /// like one installed by a container runtime or a service manager.
+    SeccompFilter,
+    /// The process is confined by a [Landlock ruleset](crate::landlock).
+    /// Linux only.
+    Landlock,
+    /// The process is confined by more than one mechanism.  On Linux, that
+    /// means by both a seccomp filter and a Landlock ruleset.
+    Multiple,
+}
 
 /// Actually enter capability mode.
 ///
 /// After this point the process will no longer be allowed to access global namespaces.
+///
+/// On Linux, capability mode is emulated by installing a seccomp-bpf filter.
+/// See the [`linux`](crate#linux) section of the crate documentation for its
+/// limitations.
+///
+/// # Errors
+///
+/// * `EBUSY` - On Linux, a [Landlock ruleset](crate::landlock) is to be
+///   applied, but the process has more than one thread.  Landlock would only
+///   restrict the calling thread.
 pub fn enter() -> io::Result<()> {
-    if unsafe { libc::cap_enter() } < 0 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(())
+    trace::enter(imp::enter)
+}
+
+/// Enter capability mode, but only if every open descriptor, besides those in
+/// `allow_unlimited`, has been limited.
+///
+/// A descriptor that still holds every right, like an inherited log file, is
+/// usually one that was forgotten.  This makes limiting them all an enforced
+/// invariant instead of a convention.  See [`audit::open_descriptors`].
+///
+/// # Errors
+///
+/// If any other descriptor still holds every right, fails without entering
+/// capability mode, with an error that lists them.  Otherwise, any error
+/// from [`audit::open_descriptors`] or [`enter`].
+///
+/// # Example
+/// ```
+/// use std::fs::File;
+///
+/// use capsicum::{CapRights, FileRights, Right};
+///
+/// // Allow whatever the process started with, like stdio.
+/// let inherited = capsicum::audit::open_descriptors()
+///     .unwrap()
+///     .iter()
+///     .map(|d| d.fd)
+///     .collect::<Vec<_>>();
+/// let passwd = File::open("/etc/passwd").unwrap();
+/// capsicum::enter_strict(&inherited).unwrap_err();
+/// assert!(!capsicum::sandboxed());
+///
+/// FileRights::new().allow(Right::Read).limit(&passwd).unwrap();
+/// capsicum::enter_strict(&inherited).unwrap();
+/// assert!(capsicum::sandboxed());
+/// ```
+pub fn enter_strict(allow_unlimited: &[RawFd]) -> io::Result<()> {
+    let offenders = audit::open_descriptors()?
+        .into_iter()
+        .filter(|d| d.is_unlimited() && !allow_unlimited.contains(&d.fd))
+        .map(|d| {
+            let mut offender = format!("{} ({}", d.fd, d.kind);
+            if let Some(path) = &d.path {
+                offender += &format!(" {}", path.display());
+            } else if let Some(address) = &d.address {
+                offender += &format!(" {address}");
+            }
+            offender + ")"
+        })
+        .collect::<Vec<_>>();
+    if !offenders.is_empty() {
+        return Err(io::Error::other(format!(
+            "refusing to enter capability mode with unlimited descriptors: {}",
+            offenders.join(", ")
+        )));
     }
+    enter()
 }
 
 /// Returns true if the process is in a capability mode.
 pub fn sandboxed() -> bool {
-    unsafe { libc::cap_sandboxed() }
+    imp::sandboxed()
 }
 
 /// Returns true if the process is in a capability mode.
 ///
 /// # Errors
 ///
-/// * `ENOSYS` - The kernel was compiled without capability support.
+/// * `ENOSYS` - The kernel was compiled without capability support.  On
+///              Linux, that means without seccomp support.
 pub fn get_mode() -> io::Result<bool> {
-    let mut mode = 0;
-    unsafe {
-        if libc::cap_getmode(&mut mode) != 0 {
-            return Err(io::Error::last_os_error());
-        }
-    }
-    Ok(mode != 0)
+    imp::get_mode()
+}
+
+/// Returns the mechanism, if any, that confines the process.
+///
+/// Unlike [`get_mode`], this reports confinement by any supported mechanism,
+/// whichever OS the process runs on.  A mechanism that the kernel doesn't
+/// support is simply reported as inactive.
+///
+/// On Linux, seccomp and `no_new_privs` are read from `/proc/self/status`,
+/// or from `prctl(2)` when `/proc` is out of reach.  The kernel can't report
+/// Landlock restrictions, so only rulesets applied by this crate, since the
+/// process last called `exec`, are detected.
+///
+/// # Example
+/// ```
+/// use capsicum::Mode;
+///
+/// capsicum::enter().unwrap();
+/// # #[cfg(target_os = "freebsd")]
+/// assert_eq!(capsicum::mode(), Mode::Capsicum);
+/// # #[cfg(target_os = "linux")]
+/// # assert_eq!(capsicum::mode(), Mode::SeccompFilter);
+/// ```
+pub fn mode() -> Mode {
+    imp::mode()
 }
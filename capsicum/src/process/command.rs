@@ -0,0 +1,415 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{CString, OsStr, OsString},
+    fs::File,
+    io::{self, Read},
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+};
+
+use libc::c_int;
+
+use super::{
+    executable::{cstring, Executable},
+    Fork,
+    ProcessDescriptor,
+};
+use crate::{trace, CapRights, FdLimits};
+
+/// A builder for child processes that run in capability mode.
+///
+/// Like [`std::process::Command`], it runs a program with arguments and an
+/// environment.  But the child inherits only the descriptors that were
+/// explicitly given to it, each limited to its own [`FdLimits`], and it
+/// enters capability mode before it executes the program.  The parent gets a
+/// [`ProcessDescriptor`] for the child.
+///
+/// The program is opened by the parent, as an [`Executable`], and executed by
+/// the child with `fexecve(2)`.  So it must be able to start in capability
+/// mode.  On Linux, dynamically linked programs can't, because the runtime
+/// linker looks up libraries by path.  Programs that enter capability mode
+/// themselves may be run without it, with
+/// [`SandboxedCommand::capability_mode`].
+///
+/// # Example
+/// ```
+/// # use std::{io::Read, os::unix::net::UnixStream};
+/// use capsicum::{process::SandboxedCommand, FdLimits, FileRights, Right};
+///
+/// # #[cfg(target_os = "linux")]
+/// # let (program, arg) = ("/sbin/ldconfig", "--version");
+/// # #[cfg(target_os = "freebsd")]
+/// # let (program, arg) = ("/rescue/echo", "Hello, World!");
+/// // This program must be statically linked.
+/// let (mut reader, writer) = UnixStream::pair().unwrap();
+/// let stdout = FdLimits::new(*FileRights::new().allow(Right::Write));
+/// let child = SandboxedCommand::new(program)
+///     .arg(arg)
+///     .fd(1, writer, stdout)
+///     .spawn()
+///     .unwrap();
+/// let mut output = String::new();
+/// reader.read_to_string(&mut output).unwrap();
+/// assert!(child.wait().unwrap().success());
+/// ```
+#[derive(Debug)]
+pub struct SandboxedCommand {
+    program: OsString,
+    args: Vec<OsString>,
+    env: BTreeMap<OsString, OsString>,
+    fds: Vec<(RawFd, OwnedFd, FdLimits)>,
+    capability_mode: bool,
+    executable: Option<Executable>,
+}
+
+impl SandboxedCommand {
+    /// Create a new `SandboxedCommand` for the program at `program`.
+    ///
+    /// If `program` isn't a path, the directories in the `PATH` environment
+    /// variable are searched for it.  By default, the child inherits the
+    /// parent's environment, but no descriptors at all.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        SandboxedCommand {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env: env::vars_os().collect(),
+            fds: Vec::new(),
+            capability_mode: true,
+            executable: None,
+        }
+    }
+
+    /// Create a new `SandboxedCommand` for a program that was opened ahead of
+    /// time.
+    ///
+    /// Unlike with [`SandboxedCommand::new`], the child can be spawned from
+    /// capability mode.
+    pub fn from_executable(executable: Executable) -> Self {
+        let mut cmd = SandboxedCommand::new(executable.path());
+        cmd.executable = Some(executable);
+        cmd
+    }
+
+    /// Add an argument to pass to the program.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add several arguments to pass to the program.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Set an environment variable for the child.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, val: V) -> Self {
+        self.env
+            .insert(key.as_ref().to_owned(), val.as_ref().to_owned());
+        self
+    }
+
+    /// Remove an environment variable from the child's environment.
+    pub fn env_remove<K: AsRef<OsStr>>(mut self, key: K) -> Self {
+        self.env.remove(key.as_ref());
+        self
+    }
+
+    /// Clear the child's environment.
+    pub fn env_clear(mut self) -> Self {
+        self.env.clear();
+        self
+    }
+
+    /// Give the child a descriptor, as descriptor number `child_fd`, limited
+    /// to `limits`.
+    ///
+    /// The descriptor is closed in the parent when the child is spawned.  On
+    /// Linux, if the parent is in capability mode, the descriptor must not be
+    /// limited already, since limited descriptors can't be duplicated there.
+    pub fn fd<F: Into<OwnedFd>>(mut self, child_fd: RawFd, fd: F, limits: FdLimits) -> Self {
+        self.fds.push((child_fd, fd.into(), limits));
+        self
+    }
+
+    /// Whether the child should enter capability mode before it executes the
+    /// program.  The default is `true`.
+    ///
+    /// Disable this for programs that enter capability mode themselves, after
+    /// they've finished loading.  On FreeBSD, the child's descriptors are
+    /// limited either way.  On Linux, limits are only enforced in capability
+    /// mode, and they're forgotten when the program is executed, so without
+    /// it, the descriptors aren't limited at all.
+    pub fn capability_mode(mut self, enter: bool) -> Self {
+        self.capability_mode = enter;
+        self
+    }
+
+    /// Spawn the child.
+    ///
+    /// The child limits its descriptors and enters capability mode between
+    /// `fork` and `exec`.  That allocates memory, so if the parent is
+    /// multithreaded, no other thread should be allocating at the same time.
+    ///
+    /// # Errors
+    ///
+    /// * `EINVAL` - The same child descriptor number was given more than once,
+    ///              or an argument or environment variable contains a nul
+    ///              byte.
+    /// * `ENOENT` - The program couldn't be found.
+    /// * [`Unsupported`](io::ErrorKind::Unsupported) - The program can't start
+    ///   in capability mode.  See [`Executable`].
+    ///
+    /// Any error from the child, before it executes the program, is returned
+    /// too.  In that case, the child has already been reaped.
+    pub fn spawn(mut self) -> io::Result<ProcessDescriptor> {
+        let targets = self.targets()?;
+        let executable = self.executable()?;
+        let (argv, envp) = self.argv_envp()?;
+        let (mut reader, writer) = pipe()?;
+
+        match unsafe { ProcessDescriptor::fork() }? {
+            Fork::Child => {
+                drop(reader);
+                let mut errors = Some(writer);
+                let e = self.replace(&targets, executable, &mut errors, argv, envp);
+                let errno = e.raw_os_error().unwrap_or(libc::EINVAL);
+                unsafe {
+                    if let Some(errors) = errors {
+                        libc::write(errors.as_raw_fd(), errno.to_ne_bytes().as_ptr().cast(), 4);
+                    }
+                    libc::_exit(127);
+                }
+            }
+            Fork::Parent(pd) => {
+                drop(writer);
+                let mut buf = [0u8; 4];
+                let mut len = 0;
+                let read = loop {
+                    match reader.read(&mut buf[len..]) {
+                        Ok(0) => break Ok(len),
+                        Ok(n) => {
+                            len += n;
+                            if len == buf.len() {
+                                break Ok(len);
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => break Err(e),
+                    }
+                };
+                // The pipe is closed on exec, so reading nothing means success.
+                let e = match read {
+                    Ok(0) => return Ok(pd),
+                    Ok(4) => io::Error::from_raw_os_error(c_int::from_ne_bytes(buf)),
+                    Ok(_) => io::ErrorKind::UnexpectedEof.into(),
+                    Err(e) => {
+                        // The program may be running, and the caller won't
+                        // know about it.
+                        let _ = pd.kill(libc::SIGKILL);
+                        e
+                    }
+                };
+                pd.wait()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Replace the current process with the program, like `spawn`'s child
+    /// would, without forking.  Only returns on failure.
+    ///
+    /// If it fails after it has started rearranging the descriptor table, the
+    /// table is left half-done.  The descriptors given to
+    /// [`SandboxedCommand::fd`] are closed or moved by then, too.
+    pub fn exec(mut self) -> io::Error {
+        let mut prepare = || -> io::Result<_> {
+            let targets = self.targets()?;
+            let executable = self.executable()?;
+            let (argv, envp) = self.argv_envp()?;
+            Ok((targets, executable, argv, envp))
+        };
+        match prepare() {
+            Ok((targets, executable, argv, envp)) => {
+                self.replace(&targets, executable, &mut None, argv, envp)
+            }
+            Err(e) => e,
+        }
+    }
+
+    /// The child's descriptor numbers, sorted, after checking them.
+    fn targets(&self) -> io::Result<Vec<RawFd>> {
+        let mut targets = self.fds.iter().map(|(fd, ..)| *fd).collect::<Vec<_>>();
+        targets.sort_unstable();
+        let duplicate = targets.windows(2).any(|w| w[0] == w[1]);
+        if duplicate || targets.first().is_some_and(|fd| *fd < 0) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        Ok(targets)
+    }
+
+    /// Open the program, unless it already is, and check that it can start.
+    fn executable(&mut self) -> io::Result<Executable> {
+        let executable = match self.executable.take() {
+            Some(executable) => executable,
+            None => Executable::open(self.resolve()?)?,
+        };
+        executable.check(self.capability_mode || crate::sandboxed())?;
+        Ok(executable)
+    }
+
+    fn argv_envp(&self) -> io::Result<(Vec<CString>, Vec<CString>)> {
+        let argv = std::iter::once(self.program.as_os_str())
+            .chain(self.args.iter().map(OsString::as_os_str))
+            .map(cstring)
+            .collect::<io::Result<Vec<_>>>()?;
+        let envp = self
+            .env
+            .iter()
+            .map(|(k, v)| {
+                let mut var = k.clone();
+                var.push("=");
+                var.push(v);
+                cstring(&var)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok((argv, envp))
+    }
+
+    fn resolve(&self) -> io::Result<PathBuf> {
+        let program = Path::new(&self.program);
+        if program.as_os_str().as_bytes().contains(&b'/') {
+            return Ok(program.to_owned());
+        }
+        env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    /// Set up the descriptor table, and execute the program.  `errors`, if
+    /// any, is kept open too.
+    fn replace(
+        self,
+        targets: &[RawFd],
+        mut executable: Executable,
+        errors: &mut Option<OwnedFd>,
+        argv: Vec<CString>,
+        envp: Vec<CString>,
+    ) -> io::Error {
+        trace::silence();
+        // From here on, the descriptors are closed by close_range, not by drop.
+        let fds = self
+            .fds
+            .into_iter()
+            .map(|(target, fd, limits)| (target, fd.into_raw_fd(), limits))
+            .collect::<Vec<_>>();
+        let capability_mode = self.capability_mode;
+        // Move a descriptor out of the way of the child's descriptor numbers.
+        let min = targets.last().map_or(0, |fd| fd + 1);
+        let keep = |fd: RawFd| -> io::Result<OwnedFd> {
+            let r = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, min) };
+            if r < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(unsafe { OwnedFd::from_raw_fd(r) })
+            }
+        };
+        let mut setup = || -> io::Result<()> {
+            // Linux's emulated limits aren't inherited by duplicates.
+            #[cfg(target_os = "linux")]
+            let inherited = fds
+                .iter()
+                .map(|(_, fd, _)| FdLimits::from_file(&unsafe { BorrowedFd::borrow_raw(*fd) }))
+                .collect::<io::Result<Vec<_>>>()?;
+            let mut open = targets.to_vec();
+            let program = executable.descriptors_mut().into_iter();
+            for fd in program.chain(errors.as_mut()) {
+                // On Linux, a limited descriptor can't be duplicated in
+                // capability mode, so leave it be if it's out of the way.
+                if fd.as_raw_fd() < min {
+                    *fd = keep(fd.as_raw_fd())?;
+                }
+                open.push(fd.as_raw_fd());
+            }
+            let mut moved = Vec::with_capacity(fds.len());
+            for (target, fd, _) in &fds {
+                moved.push((*target, keep(*fd)?));
+            }
+            for (target, fd) in moved {
+                if unsafe { libc::dup2(fd.as_raw_fd(), target) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // Close everything else, including the original descriptors.
+            open.sort_unstable();
+            let mut lo = 0;
+            for fd in open {
+                close_range(lo, fd - 1);
+                lo = fd + 1;
+            }
+            close_range(lo, RawFd::MAX);
+
+            #[cfg(target_os = "linux")]
+            for ((target, ..), inherited) in fds.iter().zip(&inherited) {
+                crate::linux::rights::forget(*target);
+                inherited.limit(&unsafe { BorrowedFd::borrow_raw(*target) })?;
+            }
+            for (target, _, limits) in &fds {
+                limits.limit(&unsafe { BorrowedFd::borrow_raw(*target) })?;
+            }
+            if capability_mode {
+                crate::enter()?;
+            }
+            Ok(())
+        };
+        match setup() {
+            Ok(()) => executable.exec(argv, envp),
+            Err(e) => e,
+        }
+    }
+}
+
+fn pipe() -> io::Result<(File, OwnedFd)> {
+    let mut fds = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { Ok((File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+/// Close every descriptor from `lo` to `hi`, inclusive.
+fn close_range(lo: RawFd, hi: RawFd) {
+    if lo > hi {
+        return;
+    }
+    #[cfg(target_os = "freebsd")]
+    unsafe {
+        libc::close_range(lo as u32, hi as u32, 0);
+    }
+    #[cfg(target_os = "linux")]
+    unsafe {
+        if libc::syscall(libc::SYS_close_range, lo as u32, hi as u32, 0) < 0 {
+            // Before Linux 5.9
+            let max = libc::sysconf(libc::_SC_OPEN_MAX).clamp(0, RawFd::MAX as i64) as RawFd;
+            for fd in lo..=hi.min(max) {
+                libc::close(fd);
+            }
+        }
+    }
+}
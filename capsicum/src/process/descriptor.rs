@@ -0,0 +1,316 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        unix::process::ExitStatusExt,
+    },
+    process::ExitStatus,
+};
+
+use libc::{c_int, pid_t};
+
+/// The outcome of [`ProcessDescriptor::fork`], in either process.
+#[derive(Debug)]
+pub enum Fork {
+    /// This is the parent process, which holds a descriptor for the child.
+    Parent(ProcessDescriptor),
+    /// This is the child process.
+    Child,
+}
+
+/// A file descriptor that refers to a child process.
+///
+/// In capability mode, a process may not name other processes by their
+/// process IDs, so it can only manage its children through descriptors.  Like
+/// any other descriptor, a `ProcessDescriptor` can be limited with
+/// [`FileRights`](crate::FileRights): [`Right::Pdkill`], [`Right::Pdwait`],
+/// and [`Right::Pdgetpid`] govern its methods.
+///
+/// [`Right::Pdkill`]: crate::Right::Pdkill
+/// [`Right::Pdwait`]: crate::Right::Pdwait
+/// [`Right::Pdgetpid`]: crate::Right::Pdgetpid
+///
+/// On FreeBSD, it's a process descriptor created by `pdfork(2)`.  Closing the
+/// last descriptor for a child that is still running kills the child.
+///
+/// On Linux, it's a pidfd.  Closing it has no effect on the child.
+///
+/// # Example
+/// ```
+/// use capsicum::process::{Fork, ProcessDescriptor};
+///
+/// match unsafe { ProcessDescriptor::fork() }.unwrap() {
+///     Fork::Child => unsafe { libc::_exit(42) },
+///     Fork::Parent(child) => {
+///         capsicum::enter().unwrap();
+///         let status = child.wait().unwrap();
+///         assert_eq!(status.code(), Some(42));
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ProcessDescriptor {
+    fd: OwnedFd,
+    pid: pid_t,
+}
+
+impl ProcessDescriptor {
+    /// Fork a child process, and return a descriptor for it to the parent.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `fork(2)`.  In particular, if the parent is
+    /// multithreaded, the child may only call async-signal-safe functions
+    /// until it calls `exec` or exits.
+    ///
+    /// On Linux, the child is created with `fork(3)`, and the parent then opens
+    /// a pidfd for it.  That's forbidden in capability mode, so there, the
+    /// child is created with `clone3(2)` instead, which returns the pidfd
+    /// directly.  But unlike `fork(3)`, it doesn't run libc's `pthread_atfork`
+    /// handlers, so if the parent is multithreaded, the child may deadlock
+    /// even in `malloc(3)`.
+    pub unsafe fn fork() -> io::Result<Fork> {
+        imp::fork()
+    }
+
+    /// Returns the process ID of the child.
+    ///
+    /// This requires [`Right::Pdgetpid`](crate::Right::Pdgetpid).  On Linux, the process ID is
+    /// returned even if the child has been reaped since.
+    pub fn pid(&self) -> io::Result<pid_t> {
+        imp::pid(self)
+    }
+
+    /// Send a signal to the child.
+    ///
+    /// This requires [`Right::Pdkill`](crate::Right::Pdkill).
+    pub fn kill(&self, signal: c_int) -> io::Result<()> {
+        imp::kill(self, signal)
+    }
+
+    /// Wait for the child to exit, and reap it.
+    ///
+    /// This requires [`Right::Pdwait`](crate::Right::Pdwait).
+    pub fn wait(&self) -> io::Result<ExitStatus> {
+        imp::wait(self)
+    }
+}
+
+impl AsFd for ProcessDescriptor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for ProcessDescriptor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<ProcessDescriptor> for OwnedFd {
+    fn from(pd: ProcessDescriptor) -> OwnedFd {
+        pd.fd
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+mod imp {
+    use std::{
+        io,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        process::ExitStatus,
+    };
+
+    use libc::{c_int, pid_t};
+
+    use super::{ExitStatusExt, Fork, ProcessDescriptor};
+    use crate::{FileRights, Right};
+
+    pub(super) unsafe fn fork() -> io::Result<Fork> {
+        let mut fd = -1;
+        match libc::pdfork(&mut fd, libc::PD_CLOEXEC) {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(Fork::Child),
+            pid => Ok(Fork::Parent(ProcessDescriptor {
+                fd: OwnedFd::from_raw_fd(fd),
+                pid,
+            })),
+        }
+    }
+
+    pub(super) fn pid(pd: &ProcessDescriptor) -> io::Result<pid_t> {
+        let mut pid = 0;
+        if unsafe { libc::pdgetpid(pd.as_raw_fd(), &mut pid) } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(pid)
+        }
+    }
+
+    pub(super) fn kill(pd: &ProcessDescriptor, signal: c_int) -> io::Result<()> {
+        if unsafe { libc::pdkill(pd.as_raw_fd(), signal) } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(super) fn wait(pd: &ProcessDescriptor) -> io::Result<ExitStatus> {
+        // pdwait(2) isn't available everywhere, but capability mode allows
+        // waiting for children by process ID.  That bypasses the
+        // descriptor's rights, so check them here.
+        if !FileRights::from_file(pd)?.is_set(Right::Pdwait) {
+            return Err(io::Error::from_raw_os_error(libc::ENOTCAPABLE));
+        }
+        let mut status = 0;
+        loop {
+            if unsafe { libc::waitpid(pd.pid, &mut status, 0) } >= 0 {
+                return Ok(ExitStatus::from_raw(status));
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        io,
+        mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        process::ExitStatus,
+        ptr,
+    };
+
+    use libc::{c_int, pid_t};
+
+    use super::{ExitStatusExt, Fork, ProcessDescriptor};
+    use crate::{FileRights, Right};
+
+    /// The first version of `struct clone_args`, which is all we need.
+    #[repr(C)]
+    #[derive(Default)]
+    struct CloneArgs {
+        flags: u64,
+        pidfd: u64,
+        child_tid: u64,
+        parent_tid: u64,
+        exit_signal: u64,
+        stack: u64,
+        stack_size: u64,
+        tls: u64,
+    }
+
+    pub(super) unsafe fn fork() -> io::Result<Fork> {
+        // pidfd_open(2) is forbidden in capability mode.
+        if crate::linux::capmode::sandboxed() {
+            return clone3();
+        }
+        let pid = match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Ok(Fork::Child),
+            pid => pid,
+        };
+        let fd = libc::syscall(libc::SYS_pidfd_open, pid, 0) as c_int;
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            // Don't leave the child running unmanaged.  If it's gone already,
+            // its pid may have been reused.
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                libc::kill(pid, libc::SIGKILL);
+                libc::waitpid(pid, ptr::null_mut(), 0);
+            }
+            return Err(e);
+        }
+        Ok(Fork::Parent(ProcessDescriptor {
+            fd: OwnedFd::from_raw_fd(fd),
+            pid,
+        }))
+    }
+
+    /// Fork with `clone3(2)`, which returns a pidfd for the child directly.
+    unsafe fn clone3() -> io::Result<Fork> {
+        let mut fd: c_int = -1;
+        let args = CloneArgs {
+            flags: libc::CLONE_PIDFD as u64,
+            pidfd: &mut fd as *mut c_int as u64,
+            exit_signal: libc::SIGCHLD as u64,
+            ..Default::default()
+        };
+        let r = libc::syscall(
+            libc::SYS_clone3,
+            &args as *const CloneArgs,
+            mem::size_of::<CloneArgs>(),
+        );
+        match r {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(Fork::Child),
+            pid => Ok(Fork::Parent(ProcessDescriptor {
+                fd: OwnedFd::from_raw_fd(fd),
+                pid: pid as pid_t,
+            })),
+        }
+    }
+
+    pub(super) fn pid(pd: &ProcessDescriptor) -> io::Result<pid_t> {
+        // The kernel doesn't know about pidfds' rights, so check them here.
+        if !FileRights::from_file(pd)?.is_set(Right::Pdgetpid) {
+            return Err(io::Error::from_raw_os_error(crate::sys::ENOTCAPABLE));
+        }
+        Ok(pd.pid)
+    }
+
+    pub(super) fn kill(pd: &ProcessDescriptor, signal: c_int) -> io::Result<()> {
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                pd.as_raw_fd(),
+                signal,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if r < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(super) fn wait(pd: &ProcessDescriptor) -> io::Result<ExitStatus> {
+        let mut info = unsafe { mem::zeroed::<libc::siginfo_t>() };
+        loop {
+            let r = unsafe {
+                libc::waitid(
+                    libc::P_PIDFD,
+                    pd.as_raw_fd() as libc::id_t,
+                    &mut info,
+                    libc::WEXITED,
+                )
+            };
+            if r >= 0 {
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        // Reassemble the status that waitpid(2) would have returned.
+        let status = unsafe { info.si_status() };
+        let raw = match info.si_code {
+            libc::CLD_EXITED => (status & 0xff) << 8,
+            libc::CLD_DUMPED => status | 0x80,
+            _ => status,
+        };
+        Ok(ExitStatus::from_raw(raw))
+    }
+}
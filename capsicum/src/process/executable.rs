@@ -0,0 +1,468 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    ffi::{CString, OsStr, OsString},
+    fs::File,
+    io,
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            fs::FileExt,
+        },
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use libc::c_char;
+
+use crate::{CapRights, FileRights, Right};
+
+/// The most of a script's `#!` line that the kernel reads.
+const SHEBANG_MAX: usize = 256;
+
+/// How many interpreters deep a program may be, counting the runtime linker.
+const NESTING_MAX: u32 = 4;
+
+/// `PT_INTERP`, the ELF program header that names the runtime linker.
+const PT_INTERP: u32 = 3;
+
+/// A program, opened ahead of time so that it can be executed in capability
+/// mode.
+///
+/// In capability mode, programs can't be looked up by path.  The only way to
+/// run one is `fexecve(2)`, on a descriptor that was opened beforehand.  An
+/// `Executable` is such a descriptor, limited to [`Right::Fexecve`],
+/// [`Right::Read`], and [`Right::Fstat`].  If the program is a script, its
+/// interpreter is opened too.  Run it with
+/// [`SandboxedCommand::from_executable`](super::SandboxedCommand::from_executable).
+///
+/// Not every program can start in capability mode, and an `Executable` that
+/// can't fails to run with an error of kind
+/// [`Unsupported`](io::ErrorKind::Unsupported):
+///
+/// * A dynamically linked program needs its libraries.  On FreeBSD, the
+///   directories that contain them may be given with
+///   [`Executable::library_dir`], and the program is run by the runtime
+///   linker, which looks for them there.  On Linux, the runtime linker only
+///   looks for libraries by path, so dynamically linked programs can't start
+///   in capability mode at all.
+/// * A script's interpreter is given the script's path as `/dev/fd/N`, and
+///   must open it by that path.
+///
+/// # Example
+/// ```
+/// # use std::{io::Read, os::unix::net::UnixStream};
+/// use capsicum::{
+///     process::{Executable, SandboxedCommand},
+///     FdLimits,
+///     FileRights,
+///     Right,
+/// };
+///
+/// # #[cfg(target_os = "linux")]
+/// # let (program, arg) = ("/sbin/ldconfig", "--version");
+/// # #[cfg(target_os = "freebsd")]
+/// # let (program, arg) = ("/rescue/echo", "Hello, World!");
+/// let exe = Executable::open(program).unwrap();
+/// capsicum::enter().unwrap();
+///
+/// let (mut reader, writer) = UnixStream::pair().unwrap();
+/// let stdout = FdLimits::new(*FileRights::new().allow(Right::Write));
+/// let child = SandboxedCommand::from_executable(exe)
+///     .arg(arg)
+///     .fd(1, writer, stdout)
+///     .spawn()
+///     .unwrap();
+/// let mut output = String::new();
+/// reader.read_to_string(&mut output).unwrap();
+/// assert!(child.wait().unwrap().success());
+/// ```
+#[derive(Debug)]
+pub struct Executable {
+    path: PathBuf,
+    file: OwnedFd,
+    kind: Kind,
+    #[cfg(target_os = "freebsd")]
+    libraries: Vec<OwnedFd>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// A statically linked program.
+    Static,
+    /// A dynamically linked program, and its runtime linker.
+    Dynamic {
+        interpreter: PathBuf,
+        #[cfg(target_os = "freebsd")]
+        linker: Box<Executable>,
+    },
+    /// A script, its interpreter, and the interpreter's optional argument.
+    Script {
+        interpreter: Box<Executable>,
+        arg: Option<OsString>,
+    },
+}
+
+impl Executable {
+    /// Open the program at `path`, and, if it's a script, its interpreter.
+    ///
+    /// # Errors
+    ///
+    /// * `ELOOP` - The program's interpreters are nested too deeply.
+    /// * `ENOEXEC` - The program is a script with an empty `#!` line, or a
+    ///               malformed ELF file.
+    ///
+    /// Any error from opening the program or its interpreter is returned too.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Executable> {
+        Executable::open_nested(path.as_ref(), 0)
+    }
+
+    fn open_nested(path: &Path, depth: u32) -> io::Result<Executable> {
+        if depth > NESTING_MAX {
+            return Err(io::Error::from_raw_os_error(libc::ELOOP));
+        }
+        let file = File::open(path)?;
+        let mut header = [0u8; SHEBANG_MAX];
+        let len = read_header(&file, &mut header)?;
+        let header = &header[..len];
+        let mut rights = FileRights::new();
+        rights
+            .allow(Right::Fexecve)
+            .allow(Right::Read)
+            .allow(Right::Fstat);
+
+        let kind = if let Some(line) = header.strip_prefix(b"#!") {
+            let (interpreter, arg) = shebang(line)?;
+            let interpreter = Executable::open_nested(&interpreter, depth + 1)?;
+            Kind::Script {
+                interpreter: Box::new(interpreter),
+                arg,
+            }
+        } else if let Some(interpreter) = elf_interpreter(&file, header)? {
+            #[cfg(target_os = "freebsd")]
+            {
+                // The runtime linker maps the program itself.
+                rights.allow(Right::MmapRX);
+                let linker = Executable::open_nested(&interpreter, depth + 1)?;
+                Kind::Dynamic {
+                    interpreter,
+                    linker: Box::new(linker),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Kind::Dynamic { interpreter }
+        } else {
+            Kind::Static
+        };
+        rights.limit(&file)?;
+        Ok(Executable {
+            path: path.to_owned(),
+            file: file.into(),
+            kind,
+            #[cfg(target_os = "freebsd")]
+            libraries: Vec::new(),
+        })
+    }
+
+    /// Give the runtime linker a directory in which to look for the
+    /// program's libraries, in capability mode.
+    ///
+    /// The directory needs at least [`Right::Lookup`], [`Right::Read`],
+    /// [`Right::Fstat`], and [`Right::MmapRX`].  Directories are searched in
+    /// the order that they were given.  They're ignored if the program is
+    /// statically linked, or isn't run in capability mode.
+    #[cfg(target_os = "freebsd")]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "freebsd")))]
+    pub fn library_dir<F: Into<OwnedFd>>(mut self, dir: F) -> Self {
+        self.libraries.push(dir.into());
+        self
+    }
+
+    /// The path that the program was opened with.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The program's interpreter, if it has one: the runtime linker of a
+    /// dynamically linked program, or the interpreter on a script's `#!`
+    /// line.
+    pub fn interpreter(&self) -> Option<&Path> {
+        match &self.kind {
+            Kind::Static => None,
+            Kind::Dynamic { interpreter, .. } => Some(interpreter),
+            Kind::Script { interpreter, .. } => Some(interpreter.path()),
+        }
+    }
+
+    /// Check that the program can start, in capability mode or not.
+    pub(super) fn check(&self, sandboxed: bool) -> io::Result<()> {
+        match &self.kind {
+            Kind::Static => Ok(()),
+            Kind::Dynamic { .. } if !sandboxed => Ok(()),
+            #[cfg(target_os = "freebsd")]
+            Kind::Dynamic { linker, .. } if !self.libraries.is_empty() => linker.check(sandboxed),
+            #[cfg(target_os = "freebsd")]
+            Kind::Dynamic { .. } => Err(unsupported(format!(
+                "{} is dynamically linked, but no library directories were given",
+                self.path.display()
+            ))),
+            #[cfg(target_os = "linux")]
+            Kind::Dynamic { interpreter } => Err(unsupported(format!(
+                "{} is dynamically linked, and {} can't load its libraries in capability mode",
+                self.path.display(),
+                interpreter.display()
+            ))),
+            Kind::Script { interpreter, .. } if !sandboxed => interpreter.check(sandboxed),
+            Kind::Script { interpreter, .. } => Err(unsupported(format!(
+                "{} is a script, and {} can't open it in capability mode",
+                self.path.display(),
+                interpreter.path().display()
+            ))),
+        }
+    }
+
+    /// All of the descriptors that must remain open until the program is
+    /// executed.
+    pub(super) fn descriptors_mut(&mut self) -> Vec<&mut OwnedFd> {
+        let mut fds = vec![&mut self.file];
+        #[cfg(target_os = "freebsd")]
+        fds.extend(&mut self.libraries);
+        match &mut self.kind {
+            Kind::Static => (),
+            #[cfg(target_os = "linux")]
+            Kind::Dynamic { .. } => (),
+            #[cfg(target_os = "freebsd")]
+            Kind::Dynamic { linker, .. } => fds.extend(linker.descriptors_mut()),
+            Kind::Script { interpreter, .. } => fds.extend(interpreter.descriptors_mut()),
+        }
+        fds
+    }
+
+    /// Replace the current process with the program.  Only returns on
+    /// failure.
+    pub(super) fn exec(&self, argv: Vec<CString>, envp: Vec<CString>) -> io::Error {
+        let sandboxed = crate::sandboxed();
+        if let Err(e) = self.check(sandboxed) {
+            return e;
+        }
+        match &self.kind {
+            Kind::Script { interpreter, arg } => {
+                // Like the kernel would, but the script can't be found by its
+                // path, so pass its descriptor instead.
+                if let Err(e) = inherit(&self.file) {
+                    return e;
+                }
+                let script = format!("/dev/fd/{}", self.file.as_raw_fd());
+                let mut args = vec![cstring(interpreter.path().as_os_str())];
+                args.extend(arg.as_deref().map(cstring));
+                args.push(cstring(OsStr::new(&script)));
+                args.extend(argv.into_iter().skip(1).map(Ok));
+                match args.into_iter().collect() {
+                    Ok(args) => interpreter.exec(args, envp),
+                    Err(e) => e,
+                }
+            }
+            #[cfg(target_os = "freebsd")]
+            Kind::Dynamic { linker, .. } if sandboxed => {
+                // The kernel can't open the runtime linker in capability mode,
+                // so run it directly, and tell it where everything is.
+                let mut libraries = Vec::new();
+                for fd in std::iter::once(&self.file).chain(&self.libraries) {
+                    if let Err(e) = inherit(fd) {
+                        return e;
+                    }
+                    libraries.push(fd.as_raw_fd().to_string());
+                }
+                let program = libraries.remove(0);
+                let mut args = vec![
+                    cstring(linker.path().as_os_str()),
+                    cstring(OsStr::new("-f")),
+                    cstring(OsStr::new(&program)),
+                    cstring(OsStr::new("--")),
+                ];
+                args.extend(argv.into_iter().map(Ok));
+                let var = format!("LD_LIBRARY_PATH_FDS={}", libraries.join(":"));
+                let mut envp = envp;
+                envp.retain(|var| !var.as_bytes().starts_with(b"LD_LIBRARY_PATH_FDS="));
+                envp.push(cstring(OsStr::new(&var)).unwrap());
+                match args.into_iter().collect() {
+                    Ok(args) => linker.exec(args, envp),
+                    Err(e) => e,
+                }
+            }
+            Kind::Static | Kind::Dynamic { .. } => {
+                fexecve(self.file.as_raw_fd(), &argv, &envp);
+                io::Error::last_os_error()
+            }
+        }
+    }
+}
+
+pub(super) fn cstring(s: &OsStr) -> io::Result<CString> {
+    CString::new(s.as_bytes()).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+fn unsupported(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, msg)
+}
+
+/// Let a descriptor be inherited across `exec`.
+fn inherit(fd: &OwnedFd) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, 0) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Read as much of the start of a file as fits in `buf`.
+fn read_header(file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read_at(&mut buf[len..], len as u64) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Parse the rest of a `#!` line into an interpreter and its argument.  Like
+/// Linux, but unlike FreeBSD, everything after the interpreter is one
+/// argument.
+fn shebang(line: &[u8]) -> io::Result<(PathBuf, Option<OsString>)> {
+    let line = line.split(|b| *b == b'\n').next().unwrap_or_default();
+    let line = line.trim_ascii();
+    let (interpreter, arg) = match line.iter().position(|b| *b == b' ' || *b == b'\t') {
+        Some(i) => (&line[..i], line[i..].trim_ascii()),
+        None => (line, &[][..]),
+    };
+    if interpreter.is_empty() {
+        return Err(io::Error::from_raw_os_error(libc::ENOEXEC));
+    }
+    let arg = (!arg.is_empty()).then(|| OsStr::from_bytes(arg).to_owned());
+    Ok((OsStr::from_bytes(interpreter).into(), arg))
+}
+
+/// If `file` is a dynamically linked ELF file, return its runtime linker.
+fn elf_interpreter(file: &File, header: &[u8]) -> io::Result<Option<PathBuf>> {
+    if header.len() < 52 || !header.starts_with(b"\x7fELF") {
+        return Ok(None);
+    }
+    let wide = match header[4] {
+        1 => false,
+        2 if header.len() >= 64 => true,
+        _ => return Err(io::Error::from_raw_os_error(libc::ENOEXEC)),
+    };
+    let big = header[5] == 2;
+    let u16_at = |b: &[u8], i: usize| {
+        let b = [b[i], b[i + 1]];
+        u64::from(if big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    };
+    let u32_at = |b: &[u8], i: usize| {
+        let b = b[i..i + 4].try_into().unwrap();
+        u64::from(if big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    };
+    let u64_at = |b: &[u8], i: usize| {
+        let b = b[i..i + 8].try_into().unwrap();
+        if big {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        }
+    };
+    // Where the program headers are, and where their fields are.
+    let (phoff, phentsize, phnum) = if wide {
+        (
+            u64_at(header, 0x20),
+            u16_at(header, 0x36),
+            u16_at(header, 0x38),
+        )
+    } else {
+        (
+            u32_at(header, 0x1c),
+            u16_at(header, 0x2a),
+            u16_at(header, 0x2c),
+        )
+    };
+    let (offset, size, len) = if wide {
+        (0x08, 0x20, 0x28)
+    } else {
+        (0x04, 0x10, 0x14)
+    };
+    let enoexec = || io::Error::from_raw_os_error(libc::ENOEXEC);
+    if phentsize < len {
+        return Err(enoexec());
+    }
+    // The whole table must lie within the range of file offsets.
+    phnum
+        .checked_mul(phentsize)
+        .and_then(|size| phoff.checked_add(size))
+        .ok_or_else(enoexec)?;
+
+    let mut phdr = [0u8; 0x28];
+    let phdr = &mut phdr[..len as usize];
+    for i in 0..phnum {
+        file.read_exact_at(phdr, phoff + i * phentsize)?;
+        if u32_at(phdr, 0) != u64::from(PT_INTERP) {
+            continue;
+        }
+        let (offset, size) = if wide {
+            (u64_at(phdr, offset), u64_at(phdr, size))
+        } else {
+            (u32_at(phdr, offset), u32_at(phdr, size))
+        };
+        offset.checked_add(size).ok_or_else(enoexec)?;
+        let mut interpreter = vec![0; size.min(libc::PATH_MAX as u64) as usize];
+        file.read_exact_at(&mut interpreter, offset)?;
+        if let Some(nul) = interpreter.iter().position(|b| *b == 0) {
+            interpreter.truncate(nul);
+        }
+        return Ok(Some(OsString::from_vec(interpreter).into()));
+    }
+    Ok(None)
+}
+
+fn nul_terminated(strings: &[CString]) -> Vec<*const c_char> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain(std::iter::once(ptr::null()))
+        .collect()
+}
+
+fn fexecve(fd: RawFd, argv: &[CString], envp: &[CString]) {
+    let argv = nul_terminated(argv);
+    let envp = nul_terminated(envp);
+    #[cfg(target_os = "freebsd")]
+    unsafe {
+        libc::fexecve(fd, argv.as_ptr(), envp.as_ptr());
+    }
+    // glibc's fexecve(3) falls back to /proc/self/fd, which capability mode
+    // forbids.
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::syscall(
+            libc::SYS_execveat,
+            fd,
+            c"".as_ptr(),
+            argv.as_ptr(),
+            envp.as_ptr(),
+            libc::AT_EMPTY_PATH,
+        );
+    }
+}
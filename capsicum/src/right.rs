@@ -17,9 +17,11 @@ use std::{
     },
 };
 
-use libc::cap_rights_t;
-
-use crate::common::CapRights;
+use crate::{
+    common::CapRights,
+    sys::{self, cap_rights_t},
+    trace,
+};
 
 /// Capsicum capability rights for file descriptors.
 ///
@@ -29,104 +31,104 @@ use crate::common::CapRights;
 #[allow(missing_docs)] // Individual bits are documented via the external link.
 pub enum Right {
     Null = 0,
-    Read = libc::CAP_READ,
-    Write = libc::CAP_WRITE,
-    SeekTell = libc::CAP_SEEK_TELL,
-    Seek = libc::CAP_SEEK,
-    Pread = libc::CAP_PREAD,
-    Pwrite = libc::CAP_PWRITE,
-    Mmap = libc::CAP_MMAP,
-    MmapR = libc::CAP_MMAP_R,
-    MmapW = libc::CAP_MMAP_W,
-    MmapX = libc::CAP_MMAP_X,
-    MmapRW = libc::CAP_MMAP_RW,
-    MmapRX = libc::CAP_MMAP_RX,
-    MmapWX = libc::CAP_MMAP_WX,
-    MmapRWX = libc::CAP_MMAP_RWX,
-    Create = libc::CAP_CREATE,
-    Fexecve = libc::CAP_FEXECVE,
-    Fsync = libc::CAP_FSYNC,
-    Ftruncate = libc::CAP_FTRUNCATE,
-    Lookup = libc::CAP_LOOKUP,
-    Fchdir = libc::CAP_FCHDIR,
-    Fchflags = libc::CAP_FCHFLAGS,
-    Chflagsat = libc::CAP_CHFLAGSAT,
-    Fchmod = libc::CAP_FCHMOD,
-    Fchmodat = libc::CAP_FCHMODAT,
-    Fchown = libc::CAP_FCHOWN,
-    Fchownat = libc::CAP_FCHOWNAT,
-    Fcntl = libc::CAP_FCNTL,
-    Flock = libc::CAP_FLOCK,
-    Fpathconf = libc::CAP_FPATHCONF,
-    Fsck = libc::CAP_FSCK,
-    Fstat = libc::CAP_FSTAT,
-    Fstatat = libc::CAP_FSTATAT,
-    Fstatfs = libc::CAP_FSTATFS,
-    Futimes = libc::CAP_FUTIMES,
-    Futimesat = libc::CAP_FUTIMESAT,
-    LinkatTarget = libc::CAP_LINKAT_TARGET,
-    Mkdirat = libc::CAP_MKDIRAT,
-    Mkfifoat = libc::CAP_MKFIFOAT,
-    Mknodat = libc::CAP_MKNODAT,
-    RenameatSource = libc::CAP_RENAMEAT_SOURCE,
-    RenameatTarget = libc::CAP_RENAMEAT_TARGET,
-    Symlinkat = libc::CAP_SYMLINKAT,
-    Unlinkat = libc::CAP_UNLINKAT,
-    Accept = libc::CAP_ACCEPT,
-    Bind = libc::CAP_BIND,
-    Connect = libc::CAP_CONNECT,
-    Getpeername = libc::CAP_GETPEERNAME,
-    Getsockname = libc::CAP_GETSOCKNAME,
-    Getsockopt = libc::CAP_GETSOCKOPT,
-    Listen = libc::CAP_LISTEN,
-    Peeloff = libc::CAP_PEELOFF,
-    Setsockopt = libc::CAP_SETSOCKOPT,
-    Shutdown = libc::CAP_SHUTDOWN,
-    Bindat = libc::CAP_BINDAT,
-    Connectat = libc::CAP_CONNECTAT,
-    LinkatSource = libc::CAP_LINKAT_SOURCE,
-    SockClient = libc::CAP_SOCK_CLIENT,
-    SockServer = libc::CAP_SOCK_SERVER,
+    Read = sys::CAP_READ,
+    Write = sys::CAP_WRITE,
+    SeekTell = sys::CAP_SEEK_TELL,
+    Seek = sys::CAP_SEEK,
+    Pread = sys::CAP_PREAD,
+    Pwrite = sys::CAP_PWRITE,
+    Mmap = sys::CAP_MMAP,
+    MmapR = sys::CAP_MMAP_R,
+    MmapW = sys::CAP_MMAP_W,
+    MmapX = sys::CAP_MMAP_X,
+    MmapRW = sys::CAP_MMAP_RW,
+    MmapRX = sys::CAP_MMAP_RX,
+    MmapWX = sys::CAP_MMAP_WX,
+    MmapRWX = sys::CAP_MMAP_RWX,
+    Create = sys::CAP_CREATE,
+    Fexecve = sys::CAP_FEXECVE,
+    Fsync = sys::CAP_FSYNC,
+    Ftruncate = sys::CAP_FTRUNCATE,
+    Lookup = sys::CAP_LOOKUP,
+    Fchdir = sys::CAP_FCHDIR,
+    Fchflags = sys::CAP_FCHFLAGS,
+    Chflagsat = sys::CAP_CHFLAGSAT,
+    Fchmod = sys::CAP_FCHMOD,
+    Fchmodat = sys::CAP_FCHMODAT,
+    Fchown = sys::CAP_FCHOWN,
+    Fchownat = sys::CAP_FCHOWNAT,
+    Fcntl = sys::CAP_FCNTL,
+    Flock = sys::CAP_FLOCK,
+    Fpathconf = sys::CAP_FPATHCONF,
+    Fsck = sys::CAP_FSCK,
+    Fstat = sys::CAP_FSTAT,
+    Fstatat = sys::CAP_FSTATAT,
+    Fstatfs = sys::CAP_FSTATFS,
+    Futimes = sys::CAP_FUTIMES,
+    Futimesat = sys::CAP_FUTIMESAT,
+    LinkatTarget = sys::CAP_LINKAT_TARGET,
+    Mkdirat = sys::CAP_MKDIRAT,
+    Mkfifoat = sys::CAP_MKFIFOAT,
+    Mknodat = sys::CAP_MKNODAT,
+    RenameatSource = sys::CAP_RENAMEAT_SOURCE,
+    RenameatTarget = sys::CAP_RENAMEAT_TARGET,
+    Symlinkat = sys::CAP_SYMLINKAT,
+    Unlinkat = sys::CAP_UNLINKAT,
+    Accept = sys::CAP_ACCEPT,
+    Bind = sys::CAP_BIND,
+    Connect = sys::CAP_CONNECT,
+    Getpeername = sys::CAP_GETPEERNAME,
+    Getsockname = sys::CAP_GETSOCKNAME,
+    Getsockopt = sys::CAP_GETSOCKOPT,
+    Listen = sys::CAP_LISTEN,
+    Peeloff = sys::CAP_PEELOFF,
+    Setsockopt = sys::CAP_SETSOCKOPT,
+    Shutdown = sys::CAP_SHUTDOWN,
+    Bindat = sys::CAP_BINDAT,
+    Connectat = sys::CAP_CONNECTAT,
+    LinkatSource = sys::CAP_LINKAT_SOURCE,
+    SockClient = sys::CAP_SOCK_CLIENT,
+    SockServer = sys::CAP_SOCK_SERVER,
     #[deprecated(since = "0.4.4", note = "May change in later OS versions")]
     #[allow(deprecated)]
-    All0 = libc::CAP_ALL0,
+    All0 = sys::CAP_ALL0,
     #[deprecated(since = "0.4.4", note = "May disappear in later OS versions")]
     #[allow(deprecated)]
-    Unused044 = libc::CAP_UNUSED0_44,
+    Unused044 = sys::CAP_UNUSED0_44,
     #[deprecated(since = "0.4.4", note = "May disappear in later OS versions")]
     #[allow(deprecated)]
-    Unused057 = libc::CAP_UNUSED0_57,
-    MacGet = libc::CAP_MAC_GET,
-    MacSet = libc::CAP_MAC_SET,
-    SemGetvalue = libc::CAP_SEM_GETVALUE,
-    SemPost = libc::CAP_SEM_POST,
-    SemWait = libc::CAP_SEM_WAIT,
-    Event = libc::CAP_EVENT,
-    KqueueEvent = libc::CAP_KQUEUE_EVENT,
-    Ioctl = libc::CAP_IOCTL,
-    Ttyhook = libc::CAP_TTYHOOK,
-    Pdgetpid = libc::CAP_PDGETPID,
-    Pdwait = libc::CAP_PDWAIT,
-    Pdkill = libc::CAP_PDKILL,
-    ExtattrDelete = libc::CAP_EXTATTR_DELETE,
-    ExtattrGet = libc::CAP_EXTATTR_GET,
-    ExtattrList = libc::CAP_EXTATTR_LIST,
-    ExtattrSet = libc::CAP_EXTATTR_SET,
-    AclCheck = libc::CAP_ACL_CHECK,
-    AclDelete = libc::CAP_ACL_DELETE,
-    AclGet = libc::CAP_ACL_GET,
-    AclSet = libc::CAP_ACL_SET,
-    KqueueChange = libc::CAP_KQUEUE_CHANGE,
-    Kqueue = libc::CAP_KQUEUE,
+    Unused057 = sys::CAP_UNUSED0_57,
+    MacGet = sys::CAP_MAC_GET,
+    MacSet = sys::CAP_MAC_SET,
+    SemGetvalue = sys::CAP_SEM_GETVALUE,
+    SemPost = sys::CAP_SEM_POST,
+    SemWait = sys::CAP_SEM_WAIT,
+    Event = sys::CAP_EVENT,
+    KqueueEvent = sys::CAP_KQUEUE_EVENT,
+    Ioctl = sys::CAP_IOCTL,
+    Ttyhook = sys::CAP_TTYHOOK,
+    Pdgetpid = sys::CAP_PDGETPID,
+    Pdwait = sys::CAP_PDWAIT,
+    Pdkill = sys::CAP_PDKILL,
+    ExtattrDelete = sys::CAP_EXTATTR_DELETE,
+    ExtattrGet = sys::CAP_EXTATTR_GET,
+    ExtattrList = sys::CAP_EXTATTR_LIST,
+    ExtattrSet = sys::CAP_EXTATTR_SET,
+    AclCheck = sys::CAP_ACL_CHECK,
+    AclDelete = sys::CAP_ACL_DELETE,
+    AclGet = sys::CAP_ACL_GET,
+    AclSet = sys::CAP_ACL_SET,
+    KqueueChange = sys::CAP_KQUEUE_CHANGE,
+    Kqueue = sys::CAP_KQUEUE,
     #[deprecated(since = "0.4.4", note = "May change in later OS versions")]
     #[allow(deprecated)]
-    All1 = libc::CAP_ALL1,
+    All1 = sys::CAP_ALL1,
     #[deprecated(since = "0.4.4", note = "May disappear in later OS versions")]
     #[allow(deprecated)]
-    Unused122 = libc::CAP_UNUSED1_22,
+    Unused122 = sys::CAP_UNUSED1_22,
     #[deprecated(since = "0.4.4", note = "May disappear in later OS versions")]
     #[allow(deprecated)]
-    Unused157 = libc::CAP_UNUSED1_57,
+    Unused157 = sys::CAP_UNUSED1_57,
 }
 
 impl Right {
@@ -148,6 +150,91 @@ impl Right {
     pub const Renameat: Right = Right::RenameatSource;
 }
 
+/// The names that [`rights(4)`](https://www.freebsd.org/cgi/man.cgi?query=rights)
+/// gives the rights, in declaration order.  Deprecated rights are left out.
+const NAMES: &[(Right, &str)] = &[
+    (Right::Read, "CAP_READ"),
+    (Right::Write, "CAP_WRITE"),
+    (Right::SeekTell, "CAP_SEEK_TELL"),
+    (Right::Seek, "CAP_SEEK"),
+    (Right::Pread, "CAP_PREAD"),
+    (Right::Pwrite, "CAP_PWRITE"),
+    (Right::Mmap, "CAP_MMAP"),
+    (Right::MmapR, "CAP_MMAP_R"),
+    (Right::MmapW, "CAP_MMAP_W"),
+    (Right::MmapX, "CAP_MMAP_X"),
+    (Right::MmapRW, "CAP_MMAP_RW"),
+    (Right::MmapRX, "CAP_MMAP_RX"),
+    (Right::MmapWX, "CAP_MMAP_WX"),
+    (Right::MmapRWX, "CAP_MMAP_RWX"),
+    (Right::Create, "CAP_CREATE"),
+    (Right::Fexecve, "CAP_FEXECVE"),
+    (Right::Fsync, "CAP_FSYNC"),
+    (Right::Ftruncate, "CAP_FTRUNCATE"),
+    (Right::Lookup, "CAP_LOOKUP"),
+    (Right::Fchdir, "CAP_FCHDIR"),
+    (Right::Fchflags, "CAP_FCHFLAGS"),
+    (Right::Chflagsat, "CAP_CHFLAGSAT"),
+    (Right::Fchmod, "CAP_FCHMOD"),
+    (Right::Fchmodat, "CAP_FCHMODAT"),
+    (Right::Fchown, "CAP_FCHOWN"),
+    (Right::Fchownat, "CAP_FCHOWNAT"),
+    (Right::Fcntl, "CAP_FCNTL"),
+    (Right::Flock, "CAP_FLOCK"),
+    (Right::Fpathconf, "CAP_FPATHCONF"),
+    (Right::Fsck, "CAP_FSCK"),
+    (Right::Fstat, "CAP_FSTAT"),
+    (Right::Fstatat, "CAP_FSTATAT"),
+    (Right::Fstatfs, "CAP_FSTATFS"),
+    (Right::Futimes, "CAP_FUTIMES"),
+    (Right::Futimesat, "CAP_FUTIMESAT"),
+    (Right::LinkatTarget, "CAP_LINKAT_TARGET"),
+    (Right::Mkdirat, "CAP_MKDIRAT"),
+    (Right::Mkfifoat, "CAP_MKFIFOAT"),
+    (Right::Mknodat, "CAP_MKNODAT"),
+    (Right::RenameatSource, "CAP_RENAMEAT_SOURCE"),
+    (Right::RenameatTarget, "CAP_RENAMEAT_TARGET"),
+    (Right::Symlinkat, "CAP_SYMLINKAT"),
+    (Right::Unlinkat, "CAP_UNLINKAT"),
+    (Right::Accept, "CAP_ACCEPT"),
+    (Right::Bind, "CAP_BIND"),
+    (Right::Connect, "CAP_CONNECT"),
+    (Right::Getpeername, "CAP_GETPEERNAME"),
+    (Right::Getsockname, "CAP_GETSOCKNAME"),
+    (Right::Getsockopt, "CAP_GETSOCKOPT"),
+    (Right::Listen, "CAP_LISTEN"),
+    (Right::Peeloff, "CAP_PEELOFF"),
+    (Right::Setsockopt, "CAP_SETSOCKOPT"),
+    (Right::Shutdown, "CAP_SHUTDOWN"),
+    (Right::Bindat, "CAP_BINDAT"),
+    (Right::Connectat, "CAP_CONNECTAT"),
+    (Right::LinkatSource, "CAP_LINKAT_SOURCE"),
+    (Right::SockClient, "CAP_SOCK_CLIENT"),
+    (Right::SockServer, "CAP_SOCK_SERVER"),
+    (Right::MacGet, "CAP_MAC_GET"),
+    (Right::MacSet, "CAP_MAC_SET"),
+    (Right::SemGetvalue, "CAP_SEM_GETVALUE"),
+    (Right::SemPost, "CAP_SEM_POST"),
+    (Right::SemWait, "CAP_SEM_WAIT"),
+    (Right::Event, "CAP_EVENT"),
+    (Right::KqueueEvent, "CAP_KQUEUE_EVENT"),
+    (Right::Ioctl, "CAP_IOCTL"),
+    (Right::Ttyhook, "CAP_TTYHOOK"),
+    (Right::Pdgetpid, "CAP_PDGETPID"),
+    (Right::Pdwait, "CAP_PDWAIT"),
+    (Right::Pdkill, "CAP_PDKILL"),
+    (Right::ExtattrDelete, "CAP_EXTATTR_DELETE"),
+    (Right::ExtattrGet, "CAP_EXTATTR_GET"),
+    (Right::ExtattrList, "CAP_EXTATTR_LIST"),
+    (Right::ExtattrSet, "CAP_EXTATTR_SET"),
+    (Right::AclCheck, "CAP_ACL_CHECK"),
+    (Right::AclDelete, "CAP_ACL_DELETE"),
+    (Right::AclGet, "CAP_ACL_GET"),
+    (Right::AclSet, "CAP_ACL_SET"),
+    (Right::KqueueChange, "CAP_KQUEUE_CHANGE"),
+    (Right::Kqueue, "CAP_KQUEUE"),
+];
+
 /// Used to construct a new set of allowed file rights.
 ///
 /// # Example
@@ -169,8 +256,8 @@ impl RightsBuilder {
         // cap_rights_init is documented as infalliable.
         let inner_rights = unsafe {
             let mut inner_rights = mem::zeroed();
-            libc::__cap_rights_init(
-                libc::CAP_RIGHTS_VERSION,
+            sys::__cap_rights_init(
+                sys::CAP_RIGHTS_VERSION,
                 &mut inner_rights as *mut cap_rights_t,
                 0u64,
             );
@@ -189,7 +276,7 @@ impl RightsBuilder {
 
     /// Add a new `Right` to the list of allowed rights.
     pub fn allow(&mut self, right: Right) -> &mut RightsBuilder {
-        let result = unsafe { libc::__cap_rights_set(self.as_mut_ptr(), right as u64, 0u64) };
+        let result = unsafe { sys::__cap_rights_set(self.as_mut_ptr(), right as u64, 0u64) };
         debug_assert!(!result.is_null()); // documented as infalliable
         self
     }
@@ -204,7 +291,7 @@ impl RightsBuilder {
     }
 
     fn is_valid(&self) -> bool {
-        unsafe { libc::cap_rights_is_valid(&self.0) }
+        unsafe { sys::cap_rights_is_valid(&self.0) }
     }
 
     #[allow(missing_docs)]
@@ -215,7 +302,7 @@ impl RightsBuilder {
 
     /// Remove another `Right` from the list of allowed rights.
     pub fn deny(&mut self, right: Right) -> &mut RightsBuilder {
-        let result = unsafe { libc::__cap_rights_clear(self.as_mut_ptr(), right as u64, 0u64) };
+        let result = unsafe { sys::__cap_rights_clear(self.as_mut_ptr(), right as u64, 0u64) };
         debug_assert!(!result.is_null()); // documented as infalliable
         self
     }
@@ -250,10 +337,13 @@ impl Default for RightsBuilder {
 /// file.read(&mut buf[..]).unwrap();
 ///
 /// let e = file.write(&buf[..]).unwrap_err();
+/// # #[cfg(target_os = "freebsd")]
 /// assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+/// # #[cfg(target_os = "linux")]
+/// # assert_eq!(e.raw_os_error(), Some(libc::EPERM));
 /// ```
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct FileRights(cap_rights_t);
+pub struct FileRights(pub(crate) cap_rights_t);
 
 impl FileRights {
     /// Initialize a new `FileRights` which will deny all rights.
@@ -261,8 +351,8 @@ impl FileRights {
         // cap_rights_init is documented as infalliable.
         let inner_rights = unsafe {
             let mut inner_rights = mem::zeroed();
-            libc::__cap_rights_init(
-                libc::CAP_RIGHTS_VERSION,
+            sys::__cap_rights_init(
+                sys::CAP_RIGHTS_VERSION,
                 &mut inner_rights as *mut cap_rights_t,
                 0u64,
             );
@@ -297,8 +387,8 @@ impl FileRights {
         let fd = f.as_fd().as_raw_fd();
         let inner_rights = unsafe {
             let mut inner_rights = unsafe { mem::zeroed() };
-            let res = libc::__cap_rights_get(
-                libc::CAP_RIGHTS_VERSION,
+            let res = sys::__cap_rights_get(
+                sys::CAP_RIGHTS_VERSION,
                 fd,
                 &mut inner_rights as *mut cap_rights_t,
             );
@@ -314,7 +404,7 @@ impl FileRights {
 
     /// Add a new `Right` to the list of allowed rights.
     pub fn allow(&mut self, right: Right) -> &mut Self {
-        let result = unsafe { libc::__cap_rights_set(self.as_mut_ptr(), right as u64, 0u64) };
+        let result = unsafe { sys::__cap_rights_set(self.as_mut_ptr(), right as u64, 0u64) };
         debug_assert!(!result.is_null()); // documented as infalliable
         self
     }
@@ -337,7 +427,7 @@ impl FileRights {
     /// assert!(!rights1.contains(&rights3));
     /// ```
     pub fn contains(&self, other: &FileRights) -> bool {
-        unsafe { libc::cap_rights_contains(&self.0, &other.0) }
+        unsafe { sys::cap_rights_contains(&self.0, &other.0) }
     }
 
     /// Is the given [`Right`] set here?
@@ -352,7 +442,7 @@ impl FileRights {
     /// assert!(!rights.is_set(Right::Write));
     /// ```
     pub fn is_set(&self, right: Right) -> bool {
-        unsafe { libc::__cap_rights_is_set(&self.0 as *const cap_rights_t, right as u64, 0u64) }
+        unsafe { sys::__cap_rights_is_set(&self.0 as *const cap_rights_t, right as u64, 0u64) }
     }
 
     #[deprecated(since = "0.4.0", note = "Unnecessary unless you use FileRights::new")]
@@ -362,13 +452,68 @@ impl FileRights {
     }
 
     fn is_valid_priv(&self) -> bool {
-        unsafe { libc::cap_rights_is_valid(&self.0) }
+        unsafe { sys::cap_rights_is_valid(&self.0) }
+    }
+
+    /// Are all of the rights set here?
+    pub(crate) fn is_unlimited(&self) -> bool {
+        NAMES.iter().all(|(right, _)| self.is_set(*right))
+    }
+
+    /// The names of the rights set here.  Rights that are merely
+    /// combinations of others, like `CAP_PREAD`, are spelled out as their
+    /// parts, and rights that are part of another one, like `CAP_SEEK_TELL`
+    /// of `CAP_SEEK`, are left out.
+    pub(crate) fn names(&self) -> Vec<&'static str> {
+        let of = |right: Right| *FileRights::new().allow(right);
+        let implies = |a: Right, b: Right| a != b && of(a).is_set(b);
+        let composite = |right: Right| {
+            let mut parts = FileRights::new();
+            for &(part, _) in NAMES.iter().filter(|(part, _)| implies(right, *part)) {
+                parts.allow(part);
+            }
+            parts == of(right)
+        };
+        let set = NAMES
+            .iter()
+            .filter(|(right, _)| self.is_set(*right) && !composite(*right))
+            .collect::<Vec<_>>();
+        set.iter()
+            .filter(|(right, _)| !set.iter().any(|(other, _)| implies(*other, *right)))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// The inverse of [`FileRights::names`], which also accepts the names of
+    /// composite rights.  Fails with the first name that isn't known.
+    #[cfg(any(feature = "manifest", feature = "policy"))]
+    pub(crate) fn from_names<'a, I>(names: I) -> Result<FileRights, &'a str>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut rights = FileRights::new();
+        for name in names {
+            let (right, _) = NAMES.iter().find(|(_, n)| *n == name).ok_or(name)?;
+            rights.allow(*right);
+        }
+        Ok(rights)
+    }
+
+    /// The words of the underlying `cap_rights_t`, for serialization.
+    pub(crate) fn to_raw(self) -> [u64; 2] {
+        unsafe { mem::transmute::<cap_rights_t, [u64; 2]>(self.0) }
+    }
+
+    /// The inverse of [`FileRights::to_raw`], if the words are valid.
+    pub(crate) fn from_raw(raw: [u64; 2]) -> Option<FileRights> {
+        let rights = FileRights(unsafe { mem::transmute::<[u64; 2], cap_rights_t>(raw) });
+        rights.is_valid_priv().then_some(rights)
     }
 
     /// Add all rights present in `other` to this structure.
     pub fn merge(&mut self, other: &FileRights) -> io::Result<()> {
         unsafe {
-            let result = libc::cap_rights_merge(self.as_mut_ptr(), &other.0);
+            let result = sys::cap_rights_merge(self.as_mut_ptr(), &other.0);
             if result.is_null() {
                 Err(io::Error::last_os_error())
             } else {
@@ -380,7 +525,7 @@ impl FileRights {
     /// Remove any rights present in `other` from this structure, if they are set.
     pub fn remove(&mut self, other: &FileRights) -> io::Result<()> {
         unsafe {
-            let result = libc::cap_rights_remove(self.as_mut_ptr(), &other.0);
+            let result = sys::cap_rights_remove(self.as_mut_ptr(), &other.0);
             if result.is_null() {
                 Err(io::Error::last_os_error())
             } else {
@@ -405,7 +550,7 @@ impl FileRights {
 
     /// Remove an allowed `Right` from the list.
     pub fn deny(&mut self, right: Right) -> &mut Self {
-        let result = unsafe { libc::__cap_rights_clear(self.as_mut_ptr(), right as u64, 0u64) };
+        let result = unsafe { sys::__cap_rights_clear(self.as_mut_ptr(), right as u64, 0u64) };
         debug_assert!(!result.is_null()); // documented as infalliable
         self
     }
@@ -420,13 +565,14 @@ impl Default for FileRights {
 impl CapRights for FileRights {
     fn limit<F: AsFd>(&self, f: &F) -> io::Result<()> {
         let fd = f.as_fd().as_raw_fd();
-        unsafe {
-            let res = libc::cap_rights_limit(fd, &self.0 as *const cap_rights_t);
+        let result = unsafe {
+            let res = sys::cap_rights_limit(fd, &self.0 as *const cap_rights_t);
             if res < 0 {
                 Err(io::Error::last_os_error())
             } else {
                 Ok(())
             }
-        }
+        };
+        trace::limit(f, "rights", || self.names().join("|"), result)
     }
 }
@@ -0,0 +1,150 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io,
+    os::fd::{AsFd, BorrowedFd, OwnedFd},
+};
+
+use crate::{common::CapRights, process, FdLimits};
+
+type Hook = Box<dyn FnOnce() -> io::Result<()>>;
+
+/// Used to set up a process's sandbox and enter capability mode.
+///
+/// A `Sandbox` collects named file descriptors, each with the limits that
+/// should apply to it, and a list of hooks to run beforehand.
+/// [`Sandbox::enter`] then performs every step in the proper order:
+///
+/// 1. Run the pre-enter hooks, in the order they were registered.
+/// 2. Limit every descriptor.
+/// 3. Enter capability mode.
+/// 4. Check that the process is really [`sandboxed`](crate::sandboxed).
+///
+/// # Example
+/// ```
+/// # use std::{fs::File, io::Read};
+/// # use capsicum::{FdLimits, FileRights, Right, Sandbox};
+/// let passwd = File::open("/etc/passwd").unwrap();
+/// let rights = *FileRights::new().allow(Right::Read);
+/// let mut fds = Sandbox::new()
+///     .fd("passwd", passwd, FdLimits::new(rights))
+///     .pre_enter(|| {
+///         println!("About to enter capability mode");
+///         Ok(())
+///     })
+///     .enter()
+///     .unwrap();
+///
+/// let mut passwd = File::from(fds.take("passwd").unwrap());
+/// let mut s = String::new();
+/// passwd.read_to_string(&mut s).unwrap();
+/// File::open("/etc/group").unwrap_err();
+/// ```
+#[derive(Default)]
+pub struct Sandbox {
+    fds: Vec<(String, OwnedFd, FdLimits)>,
+    hooks: Vec<Hook>,
+}
+
+impl Sandbox {
+    /// Create a new `Sandbox` with no descriptors and no hooks.
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    /// Register a file descriptor, to be limited to `limits` before entering
+    /// capability mode.
+    pub fn fd<F: Into<OwnedFd>>(mut self, name: &str, fd: F, limits: FdLimits) -> Self {
+        self.fds.push((name.to_owned(), fd.into(), limits));
+        self
+    }
+
+    /// Register a hook to run before any descriptors are limited.
+    ///
+    /// Hooks are a good place to open any last resources, or to initialize
+    /// library state that would otherwise be lazily loaded from the global
+    /// namespace.  If any hook fails, [`Sandbox::enter`] will return its error
+    /// without entering capability mode.
+    pub fn pre_enter<H>(mut self, hook: H) -> Self
+    where
+        H: FnOnce() -> io::Result<()> + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Run the hooks, limit the descriptors, and enter capability mode.
+    ///
+    /// # Errors
+    ///
+    /// * `EINVAL` - Two descriptors were registered with the same name.
+    /// * Any error returned by a hook, by [`CapRights::limit`], or by
+    ///   [`enter`](crate::enter).
+    pub fn enter(self) -> io::Result<SandboxFds> {
+        let fds = self.limit()?;
+        process::enter()?;
+        if !process::sandboxed() {
+            return Err(io::Error::other("capability mode was not entered"));
+        }
+        Ok(fds)
+    }
+
+    /// Run the hooks and limit the descriptors, but don't enter capability
+    /// mode.
+    pub(crate) fn limit(self) -> io::Result<SandboxFds> {
+        let mut fds = HashMap::with_capacity(self.fds.len());
+        for (name, fd, limits) in self.fds {
+            if fds.contains_key(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("descriptor {name:?} was registered twice"),
+                ));
+            }
+            fds.insert(name, (fd, limits));
+        }
+        for hook in self.hooks {
+            hook()?;
+        }
+        for (_name, (fd, limits)) in fds.iter() {
+            limits.limit(fd)?;
+            #[cfg(feature = "manifest")]
+            crate::manifest::set_role(fd, _name);
+        }
+        Ok(SandboxFds(fds))
+    }
+}
+
+impl fmt::Debug for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sandbox")
+            .field("fds", &self.fds)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+/// The descriptors registered with a [`Sandbox`], after entering capability
+/// mode.
+#[derive(Debug)]
+pub struct SandboxFds(HashMap<String, (OwnedFd, FdLimits)>);
+
+impl SandboxFds {
+    /// Borrow the descriptor registered as `name`.
+    pub fn get(&self, name: &str) -> Option<BorrowedFd<'_>> {
+        self.0.get(name).map(|(fd, _)| fd.as_fd())
+    }
+
+    /// The limits that were applied to the descriptor registered as `name`.
+    pub fn limits(&self, name: &str) -> Option<&FdLimits> {
+        self.0.get(name).map(|(_, limits)| limits)
+    }
+
+    /// Take ownership of the descriptor registered as `name`.
+    pub fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        self.0.remove(name).map(|(fd, _)| fd)
+    }
+}
@@ -0,0 +1,375 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[cfg(target_os = "linux")]
+use std::{collections::BTreeSet, sync::Mutex};
+use std::{
+    fmt,
+    io,
+    mem,
+    net::Ipv4Addr,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+use crate::{
+    audit::{self, DescriptorType},
+    fs,
+    process,
+    Right,
+};
+
+/// The rights that [`selftest`] exercises on each limited descriptor that
+/// lacks them, and the system calls that it uses.  Each call is harmless
+/// even if it succeeds.
+const PROBES: &[(Right, &str)] = &[
+    (Right::Read, "read"),
+    (Right::Write, "write"),
+    (Right::SeekTell, "lseek"),
+    (Right::Fstat, "fstat"),
+    (Right::Fcntl, "fcntl"),
+    (Right::Ioctl, "ioctl"),
+    (Right::Lookup, "openat"),
+];
+
+/// What happened when [`selftest`] attempted a forbidden operation.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SelftestOutcome {
+    /// Capability mode, or the descriptor's limits, denied the operation.
+    Denied,
+    /// The operation succeeded, though it should have been denied.
+    Allowed,
+    /// The operation failed for some other reason, so the check says
+    /// nothing.
+    Inconclusive(io::Error),
+}
+
+/// One forbidden operation that [`selftest`] attempted.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SelftestCheck {
+    /// The operation, like `open("/")` or `write on fd 3`.
+    pub operation: String,
+    /// The descriptor that the operation used, if any.
+    pub fd: Option<RawFd>,
+    /// The right that the descriptor lacks, for checks of its limits.
+    pub right: Option<Right>,
+    /// What happened.
+    pub outcome: SelftestOutcome,
+}
+
+/// The results of [`selftest`].
+#[derive(Debug)]
+pub struct SelftestReport {
+    checks: Vec<SelftestCheck>,
+}
+
+impl SelftestReport {
+    /// Every check that was run.
+    pub fn checks(&self) -> &[SelftestCheck] {
+        &self.checks
+    }
+
+    /// The checks whose operations succeeded, though they should have been
+    /// denied.
+    pub fn breaches(&self) -> impl Iterator<Item = &SelftestCheck> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, SelftestOutcome::Allowed))
+    }
+
+    /// Was every operation that was attempted denied, or inconclusive?
+    pub fn is_ok(&self) -> bool {
+        self.breaches().next().is_none()
+    }
+}
+
+impl fmt::Display for SelftestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let breaches = self
+            .breaches()
+            .map(|c| c.operation.as_str())
+            .collect::<Vec<_>>();
+        if breaches.is_empty() {
+            write!(
+                f,
+                "all {} forbidden operations were denied",
+                self.checks.len()
+            )
+        } else {
+            write!(
+                f,
+                "{} of {} forbidden operations were allowed: {}",
+                breaches.len(),
+                self.checks.len(),
+                breaches.join(", ")
+            )
+        }
+    }
+}
+
+/// On Linux, the operations that didn't fail with `EPERM` just before the
+/// filter that forbids them was installed.
+#[cfg(target_os = "linux")]
+static UNREFUSED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Did `operation` fail with `e` because of capability mode, or because of a
+/// descriptor's limits?
+#[cfg(target_os = "freebsd")]
+fn is_denial(_operation: &str, e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ECAPMODE | libc::ENOTCAPABLE))
+}
+
+/// Did `operation` fail with `e` because of capability mode, or because of a
+/// descriptor's limits?
+///
+/// Linux reports both as `EPERM`, which the operation might fail with
+/// anyway.  So it only counts if the operation didn't, before the filter
+/// that forbids it was installed.
+#[cfg(target_os = "linux")]
+fn is_denial(operation: &str, e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EPERM)
+        && UNREFUSED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(operation)
+}
+
+/// Wrap the result of a system call that returns a descriptor, closing it.
+fn close(fd: libc::c_int) -> io::Result<()> {
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        drop(unsafe { OwnedFd::from_raw_fd(fd) });
+        Ok(())
+    }
+}
+
+/// Wrap the result of any other system call.
+fn check(r: libc::c_int) -> io::Result<()> {
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Create a socket, and bind it to an address on the loopback interface.
+fn bind() -> io::Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut sin = unsafe { mem::zeroed::<libc::sockaddr_in>() };
+    sin.sin_family = libc::AF_INET as libc::sa_family_t;
+    sin.sin_addr.s_addr = u32::from(Ipv4Addr::LOCALHOST).to_be();
+    #[cfg(target_os = "freebsd")]
+    {
+        sin.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+    }
+    check(unsafe {
+        libc::bind(
+            sock.as_raw_fd(),
+            (&sin as *const libc::sockaddr_in).cast(),
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    })
+}
+
+/// Use `right` on `fd`, harmlessly.
+fn probe(fd: RawFd, right: Right) -> io::Result<()> {
+    let mut buf = [0u8; 1];
+    match right {
+        Right::Read => check(unsafe { libc::read(fd, buf.as_mut_ptr().cast(), 0) } as libc::c_int),
+        Right::Write => check(unsafe { libc::write(fd, buf.as_ptr().cast(), 0) } as libc::c_int),
+        Right::SeekTell => {
+            if unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) } < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+        Right::Fstat => {
+            let mut sb = unsafe { mem::zeroed::<libc::stat>() };
+            check(unsafe { libc::fstat(fd, &mut sb) })
+        }
+        Right::Fcntl => check(unsafe { libc::fcntl(fd, libc::F_GETFL) }),
+        Right::Ioctl => {
+            let mut n: libc::c_int = 0;
+            check(unsafe { libc::ioctl(fd, libc::FIONREAD, &mut n) })
+        }
+        Right::Lookup => {
+            close(unsafe { libc::openat(fd, c".".as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) })
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Attempt the operations that capability mode forbids.
+fn forbidden() -> Vec<(String, io::Result<()>)> {
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+    let ppid = unsafe { libc::getppid() };
+    vec![
+        (
+            "open(\"/\")".to_owned(),
+            close(unsafe { libc::open(c"/".as_ptr(), flags) }),
+        ),
+        ("bind 127.0.0.1:0".to_owned(), bind()),
+        (
+            format!("kill({ppid}, 0)"),
+            check(unsafe { libc::kill(ppid, 0) }),
+        ),
+    ]
+}
+
+/// Use each right among [`PROBES`] that `lacks` selects on `fd`.
+/// Use each right that `fd` lacks, naming the operations after descriptor
+/// number `name`.
+fn probes(
+    fd: RawFd,
+    name: RawFd,
+    lacks: impl Fn(Right) -> bool,
+) -> Vec<(String, Right, io::Result<()>)> {
+    PROBES
+        .iter()
+        .filter(|(right, _)| lacks(*right))
+        .map(|(right, op)| (format!("{op} on fd {name}"), *right, probe(fd, *right)))
+        .collect()
+}
+
+/// Remember which of `results` didn't fail with `EPERM`.
+#[cfg(target_os = "linux")]
+fn record(results: impl IntoIterator<Item = (String, io::Result<()>)>) {
+    let mut unrefused = UNREFUSED.lock().unwrap_or_else(|e| e.into_inner());
+    for (operation, result) in results {
+        if matches!(result, Err(e) if e.raw_os_error() == Some(libc::EPERM)) {
+            unrefused.remove(&operation);
+        } else {
+            unrefused.insert(operation);
+        }
+    }
+}
+
+/// Attempt the operations that capability mode forbids, just before it's
+/// entered.
+#[cfg(target_os = "linux")]
+pub(crate) fn baseline() {
+    record(forbidden());
+}
+
+/// Use the rights that `fd` is about to lose, as `losing` selects them, just
+/// before they're taken away.  By then, `fd` will be numbered `at`.  Reads and
+/// writes are only attempted on files and directories, since on anything
+/// else, even empty ones can have effects.
+#[cfg(target_os = "linux")]
+pub(crate) fn baseline_fd(fd: RawFd, at: RawFd, losing: impl Fn(Right) -> bool) {
+    let mut sb = unsafe { mem::zeroed::<libc::stat>() };
+    let inert = unsafe { libc::fstat(fd, &mut sb) } == 0
+        && matches!(sb.st_mode & libc::S_IFMT, libc::S_IFREG | libc::S_IFDIR);
+    let results = probes(fd, at, |right| {
+        losing(right) && (inert || !matches!(right, Right::Read | Right::Write))
+    });
+    record(
+        results
+            .into_iter()
+            .map(|(operation, _, result)| (operation, result)),
+    );
+}
+
+/// Attempt a battery of operations that capability mode should forbid, and
+/// report any that succeeded.
+///
+/// The operations are:
+///
+/// * Opening `/`.
+/// * Binding a socket to a loopback address.
+/// * Sending signal 0 to the parent process.
+/// * Opening `..`, and `/`, relative to each directory of the installed
+///   [`Preopens`](crate::fs::Preopens).
+/// * On each limited descriptor, using each right that it lacks, among
+///   `CAP_READ`, `CAP_WRITE`, `CAP_SEEK_TELL`, `CAP_FSTAT`, `CAP_FCNTL`,
+///   `CAP_IOCTL`, and, for directories, `CAP_LOOKUP`.  Reads and writes are
+///   zero bytes long, so none of these has any effect even if it succeeds.
+///
+/// On Linux, `openat(2)` can follow `..` or an absolute path out of its
+/// directory, unless a Landlock ruleset forbids it.  So those checks will
+/// report breaches.  And since both kinds of denial are `EPERM` there, which
+/// an operation might fail with anyway, each operation is also attempted just
+/// before the filter that forbids it is installed.  `EPERM` only counts as a
+/// denial if that attempt didn't fail the same way; otherwise the check is
+/// inconclusive.  Reads and writes are only attempted then on files and
+/// directories, so on other descriptors, their denials are inconclusive too.
+///
+/// # Errors
+///
+/// * `EINVAL` - The process is not in capability mode.
+///
+/// # Example
+/// ```
+/// # use std::fs::File;
+/// # use capsicum::{CapRights, FileRights, Right};
+/// let file = File::open("/etc/passwd").unwrap();
+/// FileRights::new().allow(Right::Read).limit(&file).unwrap();
+/// capsicum::enter().unwrap();
+///
+/// let report = capsicum::selftest().unwrap();
+/// assert!(report.is_ok(), "{report}");
+/// ```
+pub fn selftest() -> io::Result<SelftestReport> {
+    if !process::sandboxed() {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut checks = Vec::new();
+    let mut attempt = |operation: String, fd: Option<RawFd>, right, result: io::Result<()>| {
+        let outcome = match result {
+            Ok(()) => SelftestOutcome::Allowed,
+            Err(e) if is_denial(&operation, &e) => SelftestOutcome::Denied,
+            Err(e) => SelftestOutcome::Inconclusive(e),
+        };
+        checks.push(SelftestCheck {
+            operation,
+            fd,
+            right,
+            outcome,
+        });
+    };
+
+    for (operation, result) in forbidden() {
+        attempt(operation, None, None, result);
+    }
+
+    if let Some(preopens) = fs::installed() {
+        for (prefix, dir) in preopens.dirs() {
+            let dir = dir.as_raw_fd();
+            let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+            for path in [c"..", c"/"] {
+                attempt(
+                    format!("openat({}, {path:?}) from {}", dir, prefix.display()),
+                    Some(dir),
+                    None,
+                    close(unsafe { libc::openat(dir, path.as_ptr(), flags) }),
+                );
+            }
+        }
+    }
+
+    for descriptor in audit::open_descriptors()? {
+        let Some(limits) = &descriptor.limits else {
+            continue;
+        };
+        if limits.rights.is_unlimited() {
+            continue;
+        }
+        let lacks = |right| {
+            !limits.rights.is_set(right)
+                && (right != Right::Lookup || descriptor.kind == DescriptorType::Directory)
+        };
+        for (operation, right, result) in probes(descriptor.fd, descriptor.fd, lacks) {
+            attempt(operation, Some(descriptor.fd), Some(right), result);
+        }
+    }
+
+    Ok(SelftestReport { checks })
+}
@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io,
+    os::fd::{BorrowedFd, RawFd},
+};
+
+use crate::{
+    common::CapRights,
+    sys::u_long,
+    Fcntl,
+    FcntlRights,
+    FdLimits,
+    FileRights,
+    IoctlRights,
+    Right,
+};
+
+/// Read-only terminal ioctls: getting the attributes, which is how
+/// `isatty(3)` and `tcgetattr(3)` work, and getting the window size.
+#[cfg(target_os = "freebsd")]
+const TTY_IOCTLS: &[u_long] = &[libc::TIOCGETA, libc::TIOCGWINSZ];
+#[cfg(target_os = "linux")]
+const TTY_IOCTLS: &[u_long] = &[
+    libc::TCGETS as u_long,
+    libc::TCGETS2 as u_long,
+    libc::TIOCGWINSZ as u_long,
+];
+
+/// How [`limit_stdio`] limits the standard streams.
+///
+/// By default, stdin may only be read, and stdout and stderr may only be
+/// written.  Each may also be polled and `fstat`ed, its flags may be read with
+/// `fcntl(F_GETFL)`, and it accepts the few ioctls that ask whether it's a
+/// terminal, and how big.  Those that could change a terminal, or inject
+/// input into it, like `TIOCSTI`, are denied.
+///
+/// # Example
+/// ```
+/// # use capsicum::StdioPolicy;
+/// // Seeking stdin is useful when it's redirected from a file.
+/// let policy = StdioPolicy::new().seekable();
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StdioPolicy {
+    pub(crate) streams: [Option<FdLimits>; 3],
+}
+
+impl StdioPolicy {
+    /// Create a new `StdioPolicy` with the default limits.
+    pub fn new() -> StdioPolicy {
+        let limits = |right: Right| {
+            let rights = *FileRights::new()
+                .allow(right)
+                .allow(Right::Event)
+                .allow(Right::Fstat)
+                .allow(Right::Fcntl)
+                .allow(Right::Ioctl);
+            FdLimits {
+                rights,
+                fcntls: Some(*FcntlRights::new().allow(Fcntl::GetFL)),
+                ioctls: StdioPolicy::tty_ioctls(),
+            }
+        };
+        StdioPolicy {
+            streams: [
+                Some(limits(Right::Read)),
+                Some(limits(Right::Write)),
+                Some(limits(Right::Write)),
+            ],
+        }
+    }
+
+    /// The ioctls that the default limits allow.
+    pub fn tty_ioctls() -> IoctlRights {
+        IoctlRights::Limited(TTY_IOCTLS.to_vec())
+    }
+
+    /// Set the limits for stdin, or leave it unlimited with `None`.
+    pub fn stdin(mut self, limits: Option<FdLimits>) -> Self {
+        self.streams[0] = limits;
+        self
+    }
+
+    /// Set the limits for stdout, or leave it unlimited with `None`.
+    pub fn stdout(mut self, limits: Option<FdLimits>) -> Self {
+        self.streams[1] = limits;
+        self
+    }
+
+    /// Set the limits for stderr, or leave it unlimited with `None`.
+    pub fn stderr(mut self, limits: Option<FdLimits>) -> Self {
+        self.streams[2] = limits;
+        self
+    }
+
+    /// Allow seeking each limited stream, for streams redirected from or to
+    /// files.
+    pub fn seekable(mut self) -> Self {
+        for limits in self.streams.iter_mut().flatten() {
+            limits.rights.allow(Right::Seek);
+        }
+        self
+    }
+
+    /// Allow no ioctls at all on any limited stream, for streams that are
+    /// known not to be terminals.  `isatty(3)` then fails with `ENOTCAPABLE`
+    /// rather than `ENOTTY`, but still reports that the stream is no
+    /// terminal.
+    pub fn no_tty(mut self) -> Self {
+        for limits in self.streams.iter_mut().flatten() {
+            limits.rights.deny(Right::Ioctl);
+            limits.ioctls = IoctlRights::Unlimited;
+        }
+        self
+    }
+}
+
+impl Default for StdioPolicy {
+    fn default() -> Self {
+        StdioPolicy::new()
+    }
+}
+
+/// Limit stdin, stdout, and stderr according to `policy`.
+///
+/// Streams that aren't open are skipped.  This should be called before
+/// entering capability mode; on Linux, that's when the limits take effect.
+///
+/// # Errors
+///
+/// * `ENOTCAPABLE` - Some stream was already limited to fewer rights than
+///   `policy` allows it.
+///
+/// # Example
+/// ```
+/// # use std::io::Write;
+/// # use capsicum::StdioPolicy;
+/// capsicum::limit_stdio(StdioPolicy::new()).unwrap();
+/// capsicum::enter().unwrap();
+///
+/// writeln!(std::io::stderr(), "Hello from capability mode").unwrap();
+/// ```
+pub fn limit_stdio(policy: StdioPolicy) -> io::Result<()> {
+    for (fd, limits) in (0..).zip(policy.streams.iter()) {
+        let Some(limits) = limits else {
+            continue;
+        };
+        if !is_open(fd) {
+            continue;
+        }
+        limits.limit(&unsafe { BorrowedFd::borrow_raw(fd) })?;
+    }
+    Ok(())
+}
+
+/// Is `fd` open?  `F_GETFD` is never limited.
+fn is_open(fd: RawFd) -> bool {
+    let r = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    r >= 0
+}
@@ -0,0 +1,137 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Telemetry about sandboxing, emitted through `tracing` when the `tracing`
+//! feature is enabled.  Without it, everything here does nothing.
+#![cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
+use std::ffi::CStr;
+#[cfg(feature = "tracing")]
+use std::{
+    fmt::Write,
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{io, os::fd::AsFd};
+
+#[cfg(feature = "tracing")]
+use crate::audit;
+
+/// Set in a forked child, where the subscriber may not be safe to use.
+#[cfg(feature = "tracing")]
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+/// Stop emitting events from this process.  For a child between `fork` and
+/// `exec`, where the subscriber may hold locks that no thread will release,
+/// or write to descriptors that are about to be closed or reused.
+pub(crate) fn silence() {
+    #[cfg(feature = "tracing")]
+    SILENT.store(true, Ordering::Relaxed);
+}
+
+#[cfg(feature = "tracing")]
+fn enabled() -> bool {
+    !SILENT.load(Ordering::Relaxed)
+}
+
+/// Report a `CapRights::limit` call, and pass its result through.  `kind`
+/// says which limits were applied, and `rights` describes them.
+pub(crate) fn limit<F, D>(
+    f: &F,
+    kind: &'static str,
+    rights: D,
+    result: io::Result<()>,
+) -> io::Result<()>
+where
+    F: AsFd,
+    D: FnOnce() -> String,
+{
+    #[cfg(feature = "tracing")]
+    if enabled() {
+        let fd = f.as_fd();
+        match &result {
+            Ok(()) => tracing::debug!(
+                fd = fd.as_raw_fd(),
+                fd_type = %audit::descriptor_type(fd),
+                kind,
+                rights = rights(),
+                "limited descriptor"
+            ),
+            Err(e) => tracing::warn!(
+                fd = fd.as_raw_fd(),
+                fd_type = %audit::descriptor_type(fd),
+                kind,
+                rights = rights(),
+                errno = e.raw_os_error(),
+                error = %e,
+                "failed to limit descriptor"
+            ),
+        }
+    }
+    result
+}
+
+/// Enter capability mode with `enter`, and report the descriptors that the
+/// process took along.
+pub(crate) fn enter<E: FnOnce() -> io::Result<()>>(enter: E) -> io::Result<()> {
+    #[cfg(feature = "tracing")]
+    if enabled() {
+        let fds = descriptors();
+        let result = enter();
+        match &result {
+            Ok(()) => tracing::info!(fds = %fds, "entered capability mode"),
+            Err(e) => tracing::error!(
+                errno = e.raw_os_error(),
+                error = %e,
+                "failed to enter capability mode"
+            ),
+        }
+        return result;
+    }
+    enter()
+}
+
+/// A span around a call to a Casper service.
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
+#[cfg(feature = "tracing")]
+pub type Span = tracing::span::EnteredSpan;
+/// A span around a call to a Casper service.
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
+#[cfg(not(feature = "tracing"))]
+pub struct Span;
+
+/// Enter a span for operation `op` on the Casper service named `service`.
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
+#[cfg(feature = "tracing")]
+pub fn casper_span(op: &'static str, service: &CStr) -> Span {
+    tracing::info_span!("casper", op, service = %service.to_string_lossy()).entered()
+}
+/// Enter a span for operation `op` on the Casper service named `service`.
+#[cfg(all(feature = "casper", target_os = "freebsd"))]
+#[cfg(not(feature = "tracing"))]
+pub fn casper_span(op: &'static str, service: &CStr) -> Span {
+    Span
+}
+
+/// Describe the process's open descriptors, their types and rights, like
+/// `0 (device: all), 3 (file: CAP_READ|CAP_SEEK)`.
+#[cfg(feature = "tracing")]
+fn descriptors() -> String {
+    let mut list = String::new();
+    for descriptor in audit::open_descriptors().unwrap_or_default() {
+        let rights = match &descriptor.limits {
+            Some(limits) if limits.rights.is_unlimited() => "all".to_owned(),
+            Some(limits) => limits.rights.names().join("|"),
+            None => "unknown".to_owned(),
+        };
+        let sep = if list.is_empty() { "" } else { ", " };
+        let _ = write!(
+            list,
+            "{sep}{} ({}: {rights})",
+            descriptor.fd, descriptor.kind
+        );
+    }
+    list
+}
@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Warm-up routines for libc state that can't be loaded in capability mode.
+//!
+//! Several libc facilities lazily open files from the global namespace the
+//! first time that they're used: timezone data, locale files, the resolver
+//! configuration, and the password and group databases.  In capability mode,
+//! those opens will fail, usually with confusing results.  The routines in
+//! this module force that state to load ahead of time.  None of them are
+//! called automatically; call the ones you need before entering capability
+//! mode, for example with [`Sandbox::pre_enter`](crate::Sandbox::pre_enter).
+//!
+//! Most of these routines modify process-global libc state, so they should
+//! be called while the process is still single-threaded.
+//!
+//! On Linux, glibc opens the password, group, and hosts files anew for every
+//! lookup, so those can't be warmed up.  There, [`warm_passwd`],
+//! [`warm_group`], and [`warm_resolver`] fail with
+//! [`Unsupported`](io::ErrorKind::Unsupported).
+//!
+//! # Example
+//! ```
+//! use capsicum::{warm, Sandbox};
+//!
+//! Sandbox::new()
+//!     .pre_enter(warm::warm_timezone)
+//!     .pre_enter(warm::warm_locale)
+//!     .enter()
+//!     .unwrap();
+//! ```
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    sync::OnceLock,
+};
+
+use libc::time_t;
+
+extern "C" {
+    fn tzset();
+}
+
+static DEV_NULL: OnceLock<File> = OnceLock::new();
+
+/// Load the local timezone.
+///
+/// Afterwards, functions like `localtime_r(3)` will work in capability mode.
+pub fn warm_timezone() -> io::Result<()> {
+    unsafe { tzset() };
+    // tzset loads lazily on some platforms.  Converting a time forces it.
+    let now: time_t = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Load the locale named by the environment, as `setlocale(LC_ALL, "")`
+/// would.
+///
+/// Afterwards, locale-dependent functions will keep using that locale in
+/// capability mode.  Changing the locale after entering capability mode will
+/// still fail.
+pub fn warm_locale() -> io::Result<()> {
+    if unsafe { libc::setlocale(libc::LC_ALL, c"".as_ptr()) }.is_null() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the locale named by the environment could not be loaded",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Load the resolver configuration from `/etc/resolv.conf` and
+/// `/etc/nsswitch.conf`, and keep the hosts database open.
+///
+/// Afterwards, `gethostbyname(3)` and friends can still consult `/etc/hosts`
+/// in capability mode.  Note that querying a DNS server from capability mode
+/// is impossible, because it requires connecting a socket to an address.  To
+/// do that, use the Casper `system.dns` service instead.
+pub fn warm_resolver() -> io::Result<()> {
+    imp::warm_resolver()
+}
+
+/// Open the password database, and keep it open.
+///
+/// Afterwards, `getpwuid(3)`, `getpwnam(3)`, and friends will work in
+/// capability mode.
+pub fn warm_passwd() -> io::Result<()> {
+    imp::warm_passwd()
+}
+
+/// Open the group database, and keep it open.
+///
+/// Afterwards, `getgrgid(3)`, `getgrnam(3)`, and friends will work in
+/// capability mode.
+pub fn warm_group() -> io::Result<()> {
+    imp::warm_group()
+}
+
+/// Open `/dev/null`, and keep it open for use by [`dev_null`].
+pub fn warm_devnull() -> io::Result<()> {
+    if DEV_NULL.get().is_none() {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        // If another thread won the race, this one's handle will be dropped.
+        let _ = DEV_NULL.set(f);
+    }
+    Ok(())
+}
+
+/// Get a handle to `/dev/null`, even in capability mode.
+///
+/// This is useful, for example, with [`Stdio::from`](std::process::Stdio),
+/// because [`Stdio::null`](std::process::Stdio::null) opens `/dev/null` by
+/// path.
+///
+/// # Errors
+///
+/// * `ENOENT` - [`warm_devnull`] was never called.
+///
+/// # Example
+/// ```
+/// # use std::io::Write;
+/// use capsicum::warm;
+///
+/// warm::warm_devnull().unwrap();
+/// capsicum::enter().unwrap();
+///
+/// let mut null = warm::dev_null().unwrap();
+/// null.write_all(b"Hello, World!").unwrap();
+/// ```
+pub fn dev_null() -> io::Result<File> {
+    DEV_NULL
+        .get()
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?
+        .try_clone()
+}
+
+#[cfg(target_os = "freebsd")]
+mod imp {
+    use std::io;
+
+    use libc::c_int;
+
+    extern "C" {
+        fn setpassent(stayopen: c_int) -> c_int;
+        fn setgroupent(stayopen: c_int) -> c_int;
+        fn sethostent(stayopen: c_int);
+        fn res_init() -> c_int;
+    }
+
+    pub(super) fn warm_resolver() -> io::Result<()> {
+        if unsafe { res_init() } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { sethostent(1) };
+        Ok(())
+    }
+
+    pub(super) fn warm_passwd() -> io::Result<()> {
+        if unsafe { setpassent(1) } == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(super) fn warm_group() -> io::Result<()> {
+        if unsafe { setgroupent(1) } == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+
+    fn reopened(path: &str) -> io::Error {
+        let msg = format!("glibc opens {path} anew for every lookup");
+        io::Error::new(io::ErrorKind::Unsupported, msg)
+    }
+
+    pub(super) fn warm_resolver() -> io::Result<()> {
+        Err(reopened("/etc/hosts"))
+    }
+
+    pub(super) fn warm_passwd() -> io::Result<()> {
+        Err(reopened("/etc/passwd"))
+    }
+
+    pub(super) fn warm_group() -> io::Result<()> {
+        Err(reopened("/etc/group"))
+    }
+}
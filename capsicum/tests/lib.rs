@@ -17,21 +17,29 @@ fn always_abort() {
     }));
 }
 
+/// The errno of an operation that a descriptor's rights forbid.
+#[cfg(target_os = "freebsd")]
+const ENOTCAPABLE: i32 = libc::ENOTCAPABLE;
+#[cfg(target_os = "linux")]
+const ENOTCAPABLE: i32 = libc::EPERM;
+
 mod base {
-    use std::{
-        fs,
-        io::{Read, Write},
-    };
+    #[cfg(target_os = "freebsd")]
+    use std::io::Write;
+    use std::{fs, io::Read};
 
     use capsicum::{
         enter,
         sandboxed,
+        try_clone_with_rights,
         CapRights,
         Fcntl,
         FcntlRights,
+        FdLimits,
         FileRights,
         IoctlRights,
         IoctlsBuilder,
+        Mode,
         Right,
     };
     use nix::{
@@ -47,6 +55,7 @@ mod base {
         assert_eq!(144115188075855873u64, Right::Read as u64);
     }
 
+    #[cfg(target_os = "freebsd")]
     #[test]
     fn test_rights() {
         let mut file = NamedTempFile::new().unwrap();
@@ -98,6 +107,7 @@ mod base {
                 always_abort();
                 enter().expect("cap_enter failed!");
                 assert!(sandboxed(), "application is not properly sandboxed");
+                assert_ne!(capsicum::mode(), Mode::None);
 
                 if fs::File::open(file.path()).is_ok() {
                     panic!("application is not properly sandboxed!");
@@ -133,6 +143,33 @@ mod base {
         assert!(matches!(limited, IoctlRights::Unlimited));
     }
 
+    #[test]
+    fn test_try_clone_with_rights() {
+        let file = tempfile().unwrap();
+        let mut limits = FdLimits::new(*FileRights::new().allow(Right::Read).allow(Right::Fcntl));
+        limits.fcntls = Some(*FcntlRights::new().allow(Fcntl::GetFL));
+        limits.ioctls = IoctlsBuilder::new().allow(1).finalize();
+
+        let dup = try_clone_with_rights(&file, &limits).unwrap();
+        assert_eq!(FdLimits::from_file(&dup).unwrap(), limits);
+        // The original is unaffected
+        assert_eq!(
+            FdLimits::from_file(&file).unwrap(),
+            FdLimits::from_file(&tempfile().unwrap()).unwrap()
+        );
+
+        // Rights can't be regained by cloning again
+        let mut wider = limits.clone();
+        wider.rights.allow(Right::Write);
+        let e = try_clone_with_rights(&dup, &wider).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+
+        // Unlimited fcntls and ioctls are inherited
+        let rights = FdLimits::new(limits.rights);
+        let dup2 = try_clone_with_rights(&dup, &rights).unwrap();
+        assert_eq!(FdLimits::from_file(&dup2).unwrap(), limits);
+    }
+
     #[test]
     fn test_fcntl() {
         let file = tempfile().unwrap();
@@ -145,6 +182,152 @@ mod base {
     }
 }
 
+mod audit {
+    use std::{
+        fs::File,
+        net::TcpListener,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    use capsicum::{
+        audit::{open_descriptors, Descriptor, DescriptorType},
+        CapRights,
+        FileRights,
+        Right,
+    };
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn find<F: AsRawFd>(descriptors: &[Descriptor], f: &F) -> Descriptor {
+        descriptors
+            .iter()
+            .find(|d| d.fd == f.as_raw_fd())
+            .cloned()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_open_descriptors() {
+        let file = NamedTempFile::new().unwrap();
+        FileRights::new().allow(Right::Read).limit(&file).unwrap();
+        let dir = File::open("/").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let pipe = fds.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+
+        let descriptors = open_descriptors().unwrap();
+        assert!(descriptors.windows(2).all(|w| w[0].fd < w[1].fd));
+
+        let d = find(&descriptors, &file);
+        assert_eq!(d.kind, DescriptorType::File);
+        assert_eq!(d.path.as_deref(), Some(file.path()));
+        assert_eq!(
+            d.limits.unwrap().rights,
+            *FileRights::new().allow(Right::Read)
+        );
+        assert!(!find(&descriptors, &file).is_unlimited());
+
+        let d = find(&descriptors, &dir);
+        assert_eq!(d.kind, DescriptorType::Directory);
+        assert_eq!(d.path.as_deref(), Some("/".as_ref()));
+        assert_eq!(d.flags.map(|f| f & libc::O_ACCMODE), Some(libc::O_RDONLY));
+
+        let d = find(&descriptors, &listener);
+        assert_eq!(d.kind, DescriptorType::Socket);
+        assert_eq!(d.address, Some(listener.local_addr().unwrap().to_string()));
+        assert!(d.is_unlimited());
+
+        let d = find(&descriptors, &pipe[0]);
+        assert_eq!(d.kind, DescriptorType::Pipe);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_open_descriptors_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("removed");
+        let removed = File::create(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // A file whose name merely looks like a removed file's
+        let path2 = dir.path().join("kept (deleted)");
+        let kept = File::create(&path2).unwrap();
+
+        let descriptors = open_descriptors().unwrap();
+        assert_eq!(find(&descriptors, &removed).path, Some(path));
+        assert_eq!(find(&descriptors, &kept).path, Some(path2));
+    }
+
+    #[test]
+    fn test_enter_strict() {
+        let file = NamedTempFile::new().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                // Other tests' descriptors may have been open during the fork.
+                let others = open_descriptors()
+                    .unwrap()
+                    .iter()
+                    .map(|d| d.fd)
+                    .filter(|fd| ![file.as_raw_fd(), listener.as_raw_fd()].contains(fd))
+                    .collect::<Vec<_>>();
+                let e = capsicum::enter_strict(&others).unwrap_err();
+                assert!(!capsicum::sandboxed());
+                let message = e.to_string();
+                let offender = format!("{} (file {})", file.as_raw_fd(), file.path().display());
+                assert!(message.contains(&offender), "{message}");
+                let offender = format!(
+                    "{} (socket {})",
+                    listener.as_raw_fd(),
+                    listener.local_addr().unwrap()
+                );
+                assert!(message.contains(&offender), "{message}");
+
+                let mut allowed = others.clone();
+                allowed.push(listener.as_raw_fd());
+                FileRights::new().allow(Right::Read).limit(&file).unwrap();
+                capsicum::enter_strict(&allowed).unwrap();
+                assert!(capsicum::sandboxed());
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    /// In capability mode, the descriptors can't be listed, so they're
+    /// probed.
+    #[test]
+    fn test_open_descriptors_sandboxed() {
+        let file = NamedTempFile::new().unwrap();
+        let rights = *FileRights::new().allow(Right::Read).allow(Right::Fstat);
+        rights.limit(&file).unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+                let d = find(&open_descriptors().unwrap(), &file);
+                assert_eq!(d.kind, DescriptorType::File);
+                assert_eq!(d.path, None);
+                assert_eq!(d.limits.unwrap().rights, rights);
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+}
+
 mod util {
     use std::fs;
 
@@ -200,7 +383,7 @@ mod util {
                 let e = dir.open_file(fname, 0, None).unwrap_err();
                 // The OS should return ENOTCAPABLE, but std::io::ErrorKind
                 // doesn't have a kind for that.
-                if e.raw_os_error() == Some(libc::ENOTCAPABLE) {
+                if e.raw_os_error() == Some(ENOTCAPABLE) {
                     unsafe { libc::_exit(0) }
                 } else {
                     unsafe { libc::_exit(1) }
@@ -213,3 +396,2522 @@ mod util {
         }
     }
 }
+
+mod fs {
+    use std::{
+        fs::File,
+        io::{self, Read, Write},
+        os::fd::AsRawFd,
+        path::Path,
+    };
+
+    use capsicum::{
+        fs::{Preopens, SandboxTempDir},
+        FileRights,
+        Right,
+    };
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let preopens = Preopens::new()
+            .dir("/usr", File::open("/").unwrap())
+            .dir("/usr/share/", File::open("/").unwrap());
+        let resolve = |path: &str| preopens.resolve(path).map(|(_, rest)| rest);
+        assert_eq!(resolve("/usr/share/misc").unwrap(), Path::new("misc"));
+        assert_eq!(resolve("/usr/shared").unwrap(), Path::new("shared"));
+        assert_eq!(resolve("/usr/share").unwrap(), Path::new("."));
+        assert_eq!(resolve("/usr/./lib/../share//x").unwrap(), Path::new("x"));
+        let e = resolve("/usr/../etc/passwd").unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+        let e = resolve("usr/lib").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_open_create() {
+        let dir = tempdir().unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let rights = *FileRights::new()
+                    .allow(Right::Lookup)
+                    .allow(Right::Create)
+                    .allow(Right::Read)
+                    .allow(Right::Write)
+                    .allow(Right::Seek)
+                    .allow(Right::Ftruncate)
+                    .allow(Right::Fstat);
+                Preopens::new()
+                    .open_dir(dir.path(), rights)
+                    .unwrap()
+                    .install();
+                capsicum::enter().unwrap();
+
+                let path = dir.path().join("hello");
+                capsicum::fs::create(&path)
+                    .unwrap()
+                    .write_all(b"hello")
+                    .unwrap();
+                let mut s = String::new();
+                capsicum::fs::open(&path)
+                    .unwrap()
+                    .read_to_string(&mut s)
+                    .unwrap();
+                assert_eq!(s, "hello");
+
+                let e = capsicum::fs::open("/etc/passwd").unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+        assert_eq!(std::fs::read(dir.path().join("hello")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_temp_dir() {
+        let base = tempdir().unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let tmp = SandboxTempDir::new_in(base.path()).unwrap();
+                capsicum::enter().unwrap();
+
+                let rights = *FileRights::new().allow(Right::Read).allow(Right::Write);
+                let (mut file, path) = tmp.create_file(rights).unwrap();
+                assert_eq!(path.parent(), Some(tmp.path()));
+                file.write_all(b"hello").unwrap();
+                let (_, other) = tmp.create_file(rights).unwrap();
+                assert_ne!(path, other);
+
+                let rights = *FileRights::new()
+                    .allow(Right::Lookup)
+                    .allow(Right::Create)
+                    .allow(Right::Write)
+                    .allow(Right::Seek)
+                    .allow(Right::Mkdirat);
+                let (subdir, path) = tmp.create_dir(rights).unwrap();
+                assert_eq!(path.parent(), Some(tmp.path()));
+                let subdir = subdir.as_raw_fd();
+                let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC;
+                let fd = unsafe { libc::openat(subdir, c"nested".as_ptr(), flags, 0o600) };
+                assert!(fd >= 0);
+                assert_eq!(
+                    unsafe { libc::mkdirat(subdir, c"deeper".as_ptr(), 0o700) },
+                    0
+                );
+
+                tmp.close().unwrap();
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+        assert_eq!(std::fs::read_dir(base.path()).unwrap().count(), 0);
+    }
+}
+
+mod limited {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use capsicum::{
+        limited::{Limited, ReadOnly, ReadWriteSeek, RightsSet},
+        FileRights,
+    };
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_narrow() {
+        let mut file = Limited::<_, ReadWriteSeek>::new(tempfile().unwrap()).unwrap();
+        file.write_all(b"Hello, World!").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut file = file.narrow::<ReadOnly>().unwrap();
+        assert_eq!(
+            FileRights::from_file(&file).unwrap(),
+            ReadOnly::file_rights()
+        );
+
+        // Without Right::Seek, reading continues from the current offset.
+        let mut s = String::new();
+        file.read_to_string(&mut s).unwrap();
+        assert_eq!("Hello, World!", s);
+
+        // The rights stay limited even after unwrapping.
+        let inner = file.into_inner();
+        assert_eq!(
+            FileRights::from_file(&inner).unwrap(),
+            ReadOnly::file_rights()
+        );
+        // Linux only enforces limits in capability mode
+        #[cfg(target_os = "freebsd")]
+        (&inner).write_all(b"Hello, World!").unwrap_err();
+    }
+}
+
+mod logfile {
+    use std::{
+        fs::{self, File},
+        io::Write,
+    };
+
+    use capsicum::logfile::AppendLog;
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_rotating() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("worker.log");
+        fs::write(dir.path().join("worker.log.1"), "older\n").unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let mut log = AppendLog::rotating(&path, 16).unwrap();
+                capsicum::enter().unwrap();
+                log.write_all(b"first record\n").unwrap();
+                log.write_all(b"second\n").unwrap();
+                log.write_all(b"third\n").unwrap();
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("worker.log.1"), "older\n");
+        assert_eq!(read("worker.log.2"), "first record\n");
+        assert_eq!(read("worker.log"), "second\nthird\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_overwrite() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("worker.log");
+        fs::write(dir.path().join("worker.log.1"), "older\n").unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let log = AppendLog::rotating(&path, 16).unwrap();
+                let fd = capsicum::audit::open_descriptors()
+                    .unwrap()
+                    .into_iter()
+                    .find(|d| d.path.as_deref() == Some(dir.path()))
+                    .unwrap()
+                    .fd;
+                capsicum::enter().unwrap();
+
+                // A rotated log can't be replaced by renaming another over it
+                let (from, to) = (c"worker.log".as_ptr(), c"worker.log.1".as_ptr());
+                assert_eq!(unsafe { libc::renameat(fd, from, fd, to) }, -1);
+                assert_eq!(unsafe { libc::renameat2(fd, from, fd, to, 0) }, -1);
+                // Nor truncated
+                let flags = libc::O_WRONLY | libc::O_TRUNC;
+                assert_eq!(unsafe { libc::openat(fd, to, flags) }, -1);
+                let how = [flags as u64, 0, 0];
+                let size = std::mem::size_of_val(&how);
+                let r = unsafe { libc::syscall(libc::SYS_openat2, fd, to, how.as_ptr(), size) };
+                assert_eq!(r, -1);
+
+                drop(log);
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+        let older = fs::read_to_string(dir.path().join("worker.log.1")).unwrap();
+        assert_eq!(older, "older\n");
+    }
+
+    #[test]
+    fn test_from_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("worker.log");
+        let e = AppendLog::from_file(File::create(&path).unwrap()).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+
+        let file = File::options().append(true).open(&path).unwrap();
+        let mut log = AppendLog::from_file(file).unwrap();
+        log.write_all(b"hello\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello\n");
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log() {
+        use log::Log;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("worker.log");
+        let log = AppendLog::open(&path).unwrap();
+        log.log(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("worker")
+                .args(format_args!("low on {}", "disk"))
+                .build(),
+        );
+        let line = fs::read_to_string(&path).unwrap();
+        assert!(line.ends_with("Z WARN  worker: low on disk\n"), "{line}");
+        assert_eq!(
+            line.len(),
+            "2026-10-18T13:52:01Z WARN  worker: low on disk\n".len()
+        );
+    }
+}
+
+#[cfg(feature = "manifest")]
+mod manifest {
+    use std::fs::File;
+
+    use capsicum::{
+        manifest::{self, DescriptorManifest, Drift, Manifest},
+        FdLimits,
+        FileRights,
+        Mode,
+        Right,
+        Sandbox,
+    };
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let passwd = File::open("/etc/passwd").unwrap();
+                let rights = *FileRights::new().allow(Right::Read);
+                let _fds = Sandbox::new()
+                    .fd("passwd", passwd, FdLimits::new(rights))
+                    .enter()
+                    .unwrap();
+
+                let captured = Manifest::capture().unwrap();
+                let passwd = captured
+                    .descriptors
+                    .iter()
+                    .find(|d| d.role == "passwd")
+                    .unwrap();
+                assert_eq!(passwd.limits, FdLimits::new(rights));
+                let json = captured.to_json().unwrap();
+                assert!(json.contains("\"CAP_READ\""), "{json}");
+                assert_eq!(Manifest::from_json(&json).unwrap(), captured);
+                assert_eq!(manifest::verify_manifest(&captured).unwrap(), []);
+
+                fn passwd_of(m: &mut Manifest) -> &mut DescriptorManifest {
+                    m.descriptors
+                        .iter_mut()
+                        .find(|d| d.role == "passwd")
+                        .unwrap()
+                }
+                let mut declared = captured.clone();
+                declared.mode = Mode::None;
+                passwd_of(&mut declared).role = "group".to_owned();
+                let drift = manifest::verify_manifest(&declared).unwrap();
+                assert_eq!(drift.len(), 3, "{drift:?}");
+                assert!(drift.contains(&Drift::Mode {
+                    declared: Mode::None,
+                    actual: captured.mode,
+                }));
+                assert!(drift.contains(&Drift::Missing {
+                    role: "group".to_owned(),
+                }));
+                assert!(drift
+                    .iter()
+                    .any(|d| matches!(d, Drift::Undeclared { role, .. } if role == "passwd")));
+
+                let mut declared = captured.clone();
+                passwd_of(&mut declared).limits.rights.allow(Right::Fstat);
+                let drift = manifest::verify_manifest(&declared).unwrap();
+                assert_eq!(drift.len(), 1, "{drift:?}");
+                assert!(matches!(&drift[0], Drift::Limits { role, .. } if role == "passwd"));
+                assert!(drift[0].to_string().contains("CAP_FSTAT"), "{}", drift[0]);
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_json_unknown_right() {
+        let json = r#"{"mode": "none", "descriptors": [
+            {"role": "config", "fd": 3, "limits": {"rights": ["CAP_TELEPORT"]}}
+        ]}"#;
+        let e = Manifest::from_json(json).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("CAP_TELEPORT"), "{e}");
+    }
+
+    #[test]
+    fn test_from_json_owned() {
+        // Names with escapes, or read from a stream, can't be borrowed
+        let json = r#"{"mode": "none", "descriptors": [
+            {"role": "config", "fd": 3, "limits": {"rights": ["CAP_\u0052EAD"]}}
+        ]}"#;
+        let expected = Manifest {
+            mode: Mode::None,
+            descriptors: vec![DescriptorManifest {
+                role: "config".to_owned(),
+                fd: 3,
+                limits: FdLimits::new(*FileRights::new().allow(Right::Read)),
+            }],
+            casper_services: Vec::new(),
+        };
+        assert_eq!(Manifest::from_json(json).unwrap(), expected);
+        let manifest: Manifest = serde_json::from_reader(json.as_bytes()).unwrap();
+        assert_eq!(manifest, expected);
+    }
+}
+
+#[cfg(feature = "policy")]
+mod policy {
+    use std::{fs, io, os::fd::AsRawFd};
+
+    use capsicum::{policy::Policy, Fcntl, FcntlRights, FdLimits, FileRights, IoctlRights, Right};
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("data.txt"), "hello").unwrap();
+        let toml = format!(
+            r#"
+            [[preopen]]
+            name = "data"
+            path = "{}"
+            flags = ["O_DIRECTORY"]
+            rights = ["CAP_LOOKUP", "CAP_READ", "CAP_FSTAT", "CAP_FCNTL"]
+            fcntls = ["CAP_FCNTL_GETFL"]
+            ioctls = ["FIONREAD", 42]
+            "#,
+            dir.path().display()
+        );
+        let policy = Policy::from_toml(&toml).unwrap();
+        assert!(policy.enters());
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let fds = policy.apply().unwrap();
+                assert!(capsicum::sandboxed());
+
+                let data = fds.get("data").unwrap();
+                let rights = *FileRights::new()
+                    .allow(Right::Lookup)
+                    .allow(Right::Read)
+                    .allow(Right::Fstat)
+                    .allow(Right::Fcntl);
+                let limits = fds.limits("data").unwrap();
+                assert_eq!(limits.rights, rights);
+                assert_eq!(limits.fcntls, Some(*FcntlRights::new().allow(Fcntl::GetFL)));
+                assert_eq!(
+                    limits.ioctls,
+                    IoctlRights::Limited(vec![libc::FIONREAD as _, 42])
+                );
+                let fd = unsafe {
+                    libc::openat(
+                        data.as_raw_fd(),
+                        c"data.txt".as_ptr(),
+                        libc::O_RDONLY | libc::O_CLOEXEC,
+                    )
+                };
+                assert!(fd >= 0, "{}", io::Error::last_os_error());
+                fs::File::open("/etc/passwd").unwrap_err();
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply_no_enter() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_owned();
+        let toml = format!(
+            r#"
+            enter = false
+
+            [[preopen]]
+            name = "log"
+            path = "{}"
+            flags = ["O_WRONLY", "O_APPEND"]
+            rights = ["CAP_WRITE"]
+
+            [stdio]
+            tty = false
+            unlimited = ["stderr"]
+            "#,
+            path.display()
+        );
+        let policy = Policy::from_toml(&toml).unwrap();
+        assert!(!policy.enters());
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let fds = policy.apply().unwrap();
+                assert!(!capsicum::sandboxed());
+                let log = fds.get("log").unwrap();
+                let rights = *FileRights::new().allow(Right::Write);
+                assert_eq!(FdLimits::from_file(&log).unwrap(), FdLimits::new(rights));
+                let stdin = FileRights::from_file(&io::stdin()).unwrap();
+                assert!(stdin.is_set(Right::Read));
+                assert!(!stdin.is_set(Right::Ioctl));
+                assert!(!stdin.is_set(Right::Write));
+                let stderr = FileRights::from_file(&io::stderr()).unwrap();
+                assert!(stderr.is_set(Right::Ioctl));
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    /// A statically linked program that reports what it inherited, or `None`
+    /// if it can't be built.
+    fn static_helper(dir: &std::path::Path) -> Option<std::path::PathBuf> {
+        let src = dir.join("helper.c");
+        let exe = dir.join("helper");
+        fs::write(
+            &src,
+            r#"
+            #include <fcntl.h>
+            #include <stdio.h>
+            #include <stdlib.h>
+            #include <unistd.h>
+
+            int main(void) {
+                char buf[16] = {0};
+                ssize_t n = read(3, buf, sizeof(buf) - 1);
+                printf("fds=%s data=%.*s open=%d\n", getenv("CAPSICUM_FDS"),
+                    (int)(n < 0 ? 0 : n), buf, open("/etc/passwd", O_RDONLY));
+                return 0;
+            }
+            "#,
+        )
+        .unwrap();
+        let status = std::process::Command::new("cc")
+            .arg("-static")
+            .arg("-o")
+            .arg(&exe)
+            .arg(&src)
+            .status()
+            .ok()?;
+        status.success().then_some(exe)
+    }
+
+    #[test]
+    fn test_capsicum_run() {
+        let dir = tempdir().unwrap();
+        let Some(helper) = static_helper(dir.path()) else {
+            eprintln!("Skipping test_capsicum_run: no static C toolchain");
+            return;
+        };
+        let data = dir.path().join("data.txt");
+        fs::write(&data, "hello").unwrap();
+        let policy = dir.path().join("policy.toml");
+        fs::write(
+            &policy,
+            format!(
+                r#"
+                [[preopen]]
+                name = "data"
+                path = "{}"
+                rights = ["CAP_READ"]
+                "#,
+                data.display()
+            ),
+        )
+        .unwrap();
+
+        let run = env!("CARGO_BIN_EXE_capsicum-run");
+        let output = std::process::Command::new(run)
+            .arg(&policy)
+            .arg(&helper)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(stdout, "fds=data=3 data=hello open=-1\n");
+
+        let output = std::process::Command::new(run)
+            .arg(&policy)
+            .arg("no-such-program")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(127), "{output:?}");
+
+        // Linux can't keep the limits across exec without capability mode.
+        let lax = dir.path().join("lax.toml");
+        fs::write(
+            &lax,
+            format!("enter = false\n{}", fs::read_to_string(&policy).unwrap()),
+        )
+        .unwrap();
+        let output = std::process::Command::new(run)
+            .arg(&lax)
+            .arg(&helper)
+            .output()
+            .unwrap();
+        #[cfg(target_os = "linux")]
+        assert_eq!(output.status.code(), Some(125), "{output:?}");
+        #[cfg(target_os = "freebsd")]
+        assert!(output.status.success(), "{output:?}");
+
+        // Casper channels can't be passed on.
+        let casper = dir.path().join("casper.toml");
+        fs::write(&casper, "[casper.\"system.grp\"]\n").unwrap();
+        let output = std::process::Command::new(run)
+            .arg(&casper)
+            .arg(&helper)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(125), "{output:?}");
+
+        // Files that can't be executed don't stop the search.
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        fs::create_dir(&first).unwrap();
+        fs::create_dir(&second).unwrap();
+        fs::write(first.join("helper"), "").unwrap();
+        fs::copy(&helper, second.join("helper")).unwrap();
+        let output = std::process::Command::new(run)
+            .arg(&policy)
+            .arg("helper")
+            .env("PATH", std::env::join_paths([first, second]).unwrap())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+    }
+
+    #[test]
+    fn test_from_toml_invalid() {
+        let preopen = |extra: &str| {
+            format!("[[preopen]]\nname = \"x\"\npath = \"/\"\nrights = [\"CAP_READ\"]\n{extra}")
+        };
+        for (toml, bad) in [
+            (
+                preopen("").replace("CAP_READ", "CAP_TELEPORT"),
+                "CAP_TELEPORT",
+            ),
+            (preopen("flags = [\"O_MAGIC\"]"), "O_MAGIC"),
+            (preopen("fcntls = [\"CAP_FCNTL_DUP\"]"), "CAP_FCNTL_DUP"),
+            (preopen("ioctls = [\"TIOCSTI_PLEASE\"]"), "TIOCSTI_PLEASE"),
+            (preopen("mode = 420"), "mode"),
+            ("[stdio]\nunlimited = [\"stdlog\"]".to_owned(), "stdlog"),
+            (
+                "[casper.\"system.dns\"]\nlimits = { ttl = 1.5 }".to_owned(),
+                "ttl",
+            ),
+            ("enter = \"yes\"".to_owned(), "enter"),
+            (
+                format!("{}{}", preopen(""), preopen("").replace("/", "/tmp")),
+                "preopen x: declared twice",
+            ),
+        ] {
+            let e = Policy::from_toml(&toml).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{toml}");
+            assert!(e.to_string().contains(bad), "{toml}: {e}");
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("policy.toml");
+        fs::write(
+            &path,
+            "enter = false\n[casper.\"system.grp\"]\nlimits = { cmds = [\"getgrgid\"] }\n",
+        )
+        .unwrap();
+        if cfg!(all(feature = "casper", target_os = "freebsd")) {
+            let policy = Policy::load(&path).unwrap();
+            assert!(!policy.enters());
+            assert_eq!(policy.casper_services().collect::<Vec<_>>(), ["system.grp"]);
+        } else {
+            // Casper services can't be opened
+            let e = Policy::load(&path).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().contains("system.grp"), "{e}");
+        }
+
+        fs::write(&path, "enter = false\n").unwrap();
+        assert!(!Policy::load(&path).unwrap().enters());
+
+        fs::write(&path, "enter = maybe\n").unwrap();
+        let e = Policy::load(&path).unwrap_err();
+        assert!(e.to_string().contains("policy.toml"), "{e}");
+    }
+}
+
+mod sandbox {
+    use std::{cell::Cell, fs, rc::Rc};
+
+    use capsicum::{FdLimits, FileRights, Right, Sandbox};
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::{tempfile, NamedTempFile};
+
+    use super::*;
+
+    #[test]
+    fn test_enter() {
+        let file = NamedTempFile::new().unwrap();
+        let rights = *FileRights::new().allow(Right::Read).allow(Right::Fstat);
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let ran = Rc::new(Cell::new(false));
+                let ran2 = ran.clone();
+                let fds = Sandbox::new()
+                    .fd("temp", tempfile().unwrap(), FdLimits::new(rights))
+                    .pre_enter(move || {
+                        // Hooks run before entering capability mode
+                        assert!(!capsicum::sandboxed());
+                        ran2.set(true);
+                        Ok(())
+                    })
+                    .enter()
+                    .unwrap();
+                assert!(ran.get());
+                assert!(capsicum::sandboxed());
+                fs::File::open(file.path()).unwrap_err();
+                let temp = fds.get("temp").unwrap();
+                assert_eq!(FileRights::from_file(&temp).unwrap(), rights);
+                assert_eq!(fds.limits("temp").unwrap().rights, rights);
+                assert!(fds.get("other").is_none());
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    /// If a hook fails, nothing else happens
+    #[test]
+    fn test_hook_fails() {
+        let file = tempfile().unwrap();
+        let e = Sandbox::new()
+            .fd("temp", file.try_clone().unwrap(), FdLimits::default())
+            .pre_enter(|| Err(std::io::Error::from_raw_os_error(libc::ESRCH)))
+            .enter()
+            .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ESRCH));
+        assert!(!capsicum::sandboxed());
+        assert_ne!(FileRights::from_file(&file).unwrap(), FileRights::new());
+    }
+
+    #[test]
+    fn test_duplicate_name() {
+        let e = Sandbox::new()
+            .fd("temp", tempfile().unwrap(), FdLimits::default())
+            .fd("temp", tempfile().unwrap(), FdLimits::default())
+            .enter()
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(!capsicum::sandboxed());
+    }
+}
+
+mod selftest {
+    use std::{fs::File, io, os::fd::AsRawFd};
+
+    use capsicum::{fs::Preopens, CapRights, FileRights, Right, SelftestOutcome};
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_selftest() {
+        let dir = tempdir().unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let file = File::open("/etc/passwd").unwrap();
+                FileRights::new().allow(Right::Read).limit(&file).unwrap();
+                let rights = *FileRights::new().allow(Right::Lookup).allow(Right::Read);
+                Preopens::new()
+                    .open_dir(dir.path(), rights)
+                    .unwrap()
+                    .install();
+                capsicum::enter().unwrap();
+
+                let report = capsicum::selftest().unwrap();
+                let outcome = |op: &str| {
+                    let check = report.checks().iter().find(|c| c.operation == op);
+                    &check
+                        .unwrap_or_else(|| panic!("no {op}: {report:?}"))
+                        .outcome
+                };
+                assert!(matches!(outcome("open(\"/\")"), SelftestOutcome::Denied));
+                assert!(matches!(
+                    outcome("bind 127.0.0.1:0"),
+                    SelftestOutcome::Denied
+                ));
+                let fd = file.as_raw_fd();
+                for op in ["write", "lseek", "fstat", "fcntl", "ioctl"] {
+                    let op = format!("{op} on fd {fd}");
+                    assert!(matches!(outcome(&op), SelftestOutcome::Denied), "{op}");
+                }
+                assert!(report
+                    .checks()
+                    .iter()
+                    .all(|c| c.fd != Some(fd) || c.right.is_some_and(|r| r != Right::Read)));
+
+                // Linux can't confine openat to its directory without Landlock.
+                let preopened = report
+                    .checks()
+                    .iter()
+                    .filter(|c| c.operation.starts_with("openat("))
+                    .collect::<Vec<_>>();
+                assert_eq!(preopened.len(), 2);
+                #[cfg(target_os = "freebsd")]
+                assert!(report.is_ok(), "{report}");
+                #[cfg(target_os = "linux")]
+                assert!(report
+                    .breaches()
+                    .all(|c| c.operation.starts_with("openat(")));
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    /// An unprivileged process may not signal its root parent, sandboxed or
+    /// not.
+    #[test]
+    fn test_selftest_refused_anyway() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                assert_eq!(unsafe { libc::setuid(65534) }, 0);
+                capsicum::enter().unwrap();
+
+                let report = capsicum::selftest().unwrap();
+                let ppid = unsafe { libc::getppid() };
+                let op = format!("kill({ppid}, 0)");
+                let check = report.checks().iter().find(|c| c.operation == op).unwrap();
+                #[cfg(target_os = "freebsd")]
+                assert!(matches!(check.outcome, SelftestOutcome::Denied));
+                #[cfg(target_os = "linux")]
+                assert!(
+                    matches!(&check.outcome, SelftestOutcome::Inconclusive(e)
+                        if e.raw_os_error() == Some(libc::EPERM)),
+                    "{report:?}"
+                );
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_selftest_not_sandboxed() {
+        let e = capsicum::selftest().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+mod stdio {
+    use std::io;
+
+    use capsicum::StdioPolicy;
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+
+    use super::*;
+
+    fn errno<F: FnOnce() -> libc::c_int>(f: F) -> Option<i32> {
+        if f() < 0 {
+            io::Error::last_os_error().raw_os_error()
+        } else {
+            None
+        }
+    }
+
+    fn in_capability_mode<F: FnOnce()>(policy: StdioPolicy, f: F) {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::limit_stdio(policy).unwrap();
+                capsicum::enter().unwrap();
+                f();
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_limit_stdio() {
+        in_capability_mode(StdioPolicy::new(), || {
+            let mut buf = [0u8; 1];
+            let p = buf.as_mut_ptr().cast();
+            assert_eq!(
+                errno(|| unsafe { libc::write(1, p, 0) } as libc::c_int),
+                None
+            );
+            let r = errno(|| unsafe { libc::read(1, p, 0) } as libc::c_int);
+            assert_eq!(r, Some(ENOTCAPABLE));
+            let r = errno(|| unsafe { libc::write(0, p, 0) } as libc::c_int);
+            assert_eq!(r, Some(ENOTCAPABLE));
+
+            let r = errno(|| unsafe { libc::ioctl(0, libc::TIOCSTI, p) });
+            assert_eq!(r, Some(ENOTCAPABLE));
+            let mut ws = unsafe { std::mem::zeroed::<libc::winsize>() };
+            let r = errno(|| unsafe { libc::ioctl(0, libc::TIOCGWINSZ, &mut ws) });
+            assert!(matches!(r, None | Some(libc::ENOTTY)), "{r:?}");
+        });
+    }
+
+    #[test]
+    fn test_limit_stdio_no_tty() {
+        let policy = StdioPolicy::new().stdin(None).no_tty();
+        in_capability_mode(policy, || {
+            let mut ws = unsafe { std::mem::zeroed::<libc::winsize>() };
+            let r = errno(|| unsafe { libc::ioctl(2, libc::TIOCGWINSZ, &mut ws) });
+            assert_eq!(r, Some(ENOTCAPABLE));
+            let r = errno(|| unsafe { libc::ioctl(0, libc::TIOCGWINSZ, &mut ws) });
+            assert!(matches!(r, None | Some(libc::ENOTTY)), "{r:?}");
+        });
+    }
+}
+
+mod error {
+    use std::{
+        fs::File,
+        io::{self, Write},
+        os::{
+            fd::{AsFd, AsRawFd},
+            unix::fs::FileExt,
+        },
+    };
+
+    use capsicum::{CapError, CapErrorKind, CapRights, FileRights, Right};
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::tempfile;
+
+    use super::*;
+
+    #[test]
+    fn test_not_capable() {
+        let mut file = tempfile().unwrap();
+        FileRights::new().allow(Right::Read).limit(&file).unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+                let fd = file.as_raw_fd();
+
+                let e = file.write(b"x").unwrap_err();
+                let e = CapError::new(e, "write", Some(file.as_fd()));
+                assert_eq!(e.kind(), CapErrorKind::NotCapable);
+                assert_eq!(e.fd(), Some(fd));
+                assert_eq!(e.operation(), "write");
+                assert_eq!(e.required(), Some(*FileRights::new().allow(Right::Write)));
+                assert_eq!(e.held(), Some(*FileRights::new().allow(Right::Read)));
+                assert_eq!(
+                    e.to_string(),
+                    format!("write on fd {fd} requires CAP_WRITE; fd has CAP_READ")
+                );
+
+                // Composite rights are spelled out.
+                let e = file.read_at(&mut [0; 1], 0).unwrap_err();
+                let e = CapError::new(e, "pread", Some(file.as_fd()));
+                assert_eq!(
+                    e.to_string(),
+                    format!("pread on fd {fd} requires CAP_READ|CAP_SEEK; fd has CAP_READ")
+                );
+
+                let e = io::Error::from(e);
+                assert_eq!(e.raw_os_error(), None);
+                assert!(e.get_ref().unwrap().is::<CapError>());
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_capability_mode() {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+                let e = File::open("/etc/passwd").unwrap_err();
+                let e = CapError::new(e, "open", None);
+                assert_eq!(e.kind(), CapErrorKind::CapabilityMode);
+                assert_eq!(e.required(), None);
+                assert_eq!(
+                    e.to_string(),
+                    "open is not allowed in capability mode, which forbids access to global \
+                     namespaces"
+                );
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_required() {
+        let file = tempfile().unwrap();
+        let required = |op: &str| {
+            let e = io::Error::from_raw_os_error(libc::EPERM);
+            CapError::new(e, op, Some(file.as_fd())).required()
+        };
+        assert_eq!(
+            required("futimens"),
+            Some(*FileRights::new().allow(Right::Futimes))
+        );
+        assert_eq!(
+            required("utimensat"),
+            Some(*FileRights::new().allow(Right::Futimesat))
+        );
+        // Rights that depend on the flags aren't counted.
+        assert_eq!(
+            required("openat"),
+            Some(*FileRights::new().allow(Right::Lookup))
+        );
+        // Nor are those on other descriptors.
+        assert_eq!(
+            required("renameat"),
+            Some(*FileRights::new().allow(Right::RenameatSource))
+        );
+        assert_eq!(
+            required("poll"),
+            Some(*FileRights::new().allow(Right::Event))
+        );
+        assert_eq!(required("frobnicate"), None);
+    }
+
+    #[test]
+    fn test_other() {
+        let file = tempfile().unwrap();
+        let e = io::Error::from_raw_os_error(libc::EPERM);
+        let e = CapError::new(e, "fchown", Some(file.as_fd()));
+        assert_eq!(e.kind(), CapErrorKind::Other);
+        assert!(e
+            .to_string()
+            .starts_with(&format!("fchown on fd {}: ", file.as_raw_fd())));
+        assert_eq!(e.into_inner().raw_os_error(), Some(libc::EPERM));
+    }
+}
+
+mod fdpass {
+    use std::{
+        io::IoSlice,
+        os::{
+            fd::{AsFd, AsRawFd},
+            unix::net::{UnixDatagram, UnixStream},
+        },
+    };
+
+    use capsicum::{recv_fds, send_fds, FdLimits, FileRights, IoctlsBuilder, Right};
+    use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+    use tempfile::tempfile;
+
+    fn limits() -> (FdLimits, FdLimits) {
+        let ro = FdLimits::new(*FileRights::new().allow(Right::Read).allow(Right::Fstat));
+        let mut wo = FdLimits::new(*FileRights::new().allow(Right::Write).allow(Right::Ioctl));
+        wo.ioctls = IoctlsBuilder::new().allow(libc::FIONREAD).finalize();
+        (ro, wo)
+    }
+
+    #[test]
+    fn test_stream() {
+        let (ro, wo) = limits();
+        let file = tempfile().unwrap();
+        let (broker, worker) = UnixStream::pair().unwrap();
+        let before = FdLimits::from_file(&file).unwrap();
+        send_fds(&broker, &[(file.as_fd(), &ro), (broker.as_fd(), &wo)]).unwrap();
+        // A second message mustn't be mistaken for part of the first.
+        send_fds(&broker, &[(file.as_fd(), &wo)]).unwrap();
+        // The sender's descriptors are unaffected.
+        assert_eq!(FdLimits::from_file(&file).unwrap(), before);
+
+        let received = recv_fds(&worker).unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].1, ro);
+        assert_eq!(received[1].1, wo);
+        assert_eq!(FdLimits::from_file(&received[0].0).unwrap(), ro);
+        drop(received);
+        let received = recv_fds(&worker).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, wo);
+
+        drop(broker);
+        let e = recv_fds(&worker).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_datagram() {
+        let (ro, _) = limits();
+        let file = tempfile().unwrap();
+        let (broker, worker) = UnixDatagram::pair().unwrap();
+        send_fds(&broker, &[]).unwrap();
+        send_fds(&broker, &[(file.as_fd(), &ro)]).unwrap();
+
+        assert!(recv_fds(&worker).unwrap().is_empty());
+        let received = recv_fds(&worker).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, ro);
+    }
+
+    /// Send a message, with descriptors, that wasn't made by `send_fds`.
+    fn forge<F: AsFd>(socket: &UnixStream, fd: &F, labels: &[u8]) {
+        let mut message = ((labels.len() + 4) as u32).to_ne_bytes().to_vec();
+        message.extend(labels);
+        let fds = [fd.as_fd().as_raw_fd()];
+        sendmsg::<()>(
+            socket.as_raw_fd(),
+            &[IoSlice::new(&message)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_malformed() {
+        let file = tempfile().unwrap();
+        let (broker, worker) = UnixStream::pair().unwrap();
+        // No labels for one descriptor
+        forge(&broker, &file, &0u32.to_ne_bytes());
+        let e = recv_fds(&worker).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EBADMSG));
+
+        // A truncated label
+        let mut labels = 1u32.to_ne_bytes().to_vec();
+        labels.extend([0; 9]);
+        forge(&broker, &file, &labels);
+        let e = recv_fds(&worker).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EBADMSG));
+
+        // A label with unknown fcntl rights
+        let mut labels = 1u32.to_ne_bytes().to_vec();
+        labels.extend(((1u64 << 57) | 1).to_ne_bytes());
+        labels.extend((1u64 << 58).to_ne_bytes());
+        labels.push(1);
+        labels.extend(u32::MAX.to_ne_bytes());
+        labels.push(0);
+        forge(&broker, &file, &labels);
+        let e = recv_fds(&worker).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EBADMSG));
+    }
+
+    /// A descriptor that holds more rights than its label claims is rejected.
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn test_excess() {
+        use capsicum::CapRights;
+
+        let file = tempfile().unwrap();
+        FileRights::new()
+            .allow(Right::Read)
+            .allow(Right::Write)
+            .limit(&file)
+            .unwrap();
+        let (broker, worker) = UnixStream::pair().unwrap();
+        // A label that claims CAP_READ only, with unlimited fcntls and ioctls
+        let mut labels = 1u32.to_ne_bytes().to_vec();
+        labels.extend(libc::CAP_READ.to_ne_bytes());
+        labels.extend((1u64 << 58).to_ne_bytes());
+        labels.extend([0, 0]);
+        forge(&broker, &file, &labels);
+        let e = recv_fds(&worker).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    }
+}
+
+#[cfg(feature = "privsep")]
+mod privsep {
+    use std::{
+        io::{self, Read, Write},
+        os::unix::net::UnixStream,
+    };
+
+    use capsicum::{
+        privsep::{self, Message},
+        FdLimits,
+        FileRights,
+        Right,
+        Sandbox,
+    };
+    use serde::{Deserialize, Serialize};
+    use tempfile::NamedTempFile;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    enum Request {
+        Add(i32, i32),
+        Greet,
+        Forbidden,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    enum Response {
+        Sum(i32),
+        Greeted,
+    }
+
+    #[test]
+    fn test_serve() {
+        let parent = unsafe {
+            privsep::fork(Sandbox::new(), |_, mut parent| {
+                let reply = parent.call(Request::Add(2, 3))?;
+                assert_eq!(reply.body, Response::Sum(5));
+                assert!(reply.fds.is_empty());
+
+                // Descriptors go from the child to the parent, too.  But not
+                // on Linux, where the child's limits forbid sending any.
+                let (mut reader, writer) = UnixStream::pair()?;
+                let wo = FdLimits::new(*FileRights::new().allow(Right::Write));
+                let request = Message::new(Request::Greet).fd(writer, wo);
+                if cfg!(target_os = "linux") {
+                    let e = parent.call(request).unwrap_err();
+                    assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+                } else {
+                    let reply = parent.call(request)?;
+                    assert_eq!(reply.body, Response::Greeted);
+                    let mut greeting = String::new();
+                    reader.read_to_string(&mut greeting)?;
+                    assert_eq!(greeting, "Hello, World!");
+                }
+
+                let e = parent.call(Request::Forbidden).unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EACCES));
+                Ok(())
+            })
+        }
+        .unwrap();
+
+        let status = parent
+            .policy(|request| match request {
+                Request::Forbidden => Err(io::Error::from_raw_os_error(libc::EACCES)),
+                _ => Ok(()),
+            })
+            .serve(|request| match request.body {
+                Request::Add(a, b) => Ok(Response::Sum(a + b).into()),
+                Request::Greet => {
+                    let (fd, limits) = request.fds.into_iter().next().unwrap();
+                    assert!(limits.rights.is_set(Right::Write));
+                    assert!(!limits.rights.is_set(Right::Read));
+                    std::fs::File::from(fd).write_all(b"Hello, World!")?;
+                    Ok(Response::Greeted.into())
+                }
+                Request::Forbidden => unreachable!(),
+            })
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_many_fds() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"Hello, World!").unwrap();
+        let parent = unsafe {
+            privsep::fork(Sandbox::new(), |_, mut parent| {
+                // The child receives each descriptor in capability mode, yet
+                // there's no end to them
+                for i in 0..100 {
+                    let mut reply = parent.call(Request::Add(i, 1))?;
+                    assert_eq!(reply.body, Response::Sum(i + 1));
+                    let (fd, _) = reply.fds.pop().unwrap();
+                    let mut greeting = String::new();
+                    std::fs::File::from(fd).read_to_string(&mut greeting)?;
+                    assert_eq!(greeting, "Hello, World!");
+                }
+                Ok(())
+            })
+        }
+        .unwrap();
+
+        let ro = FdLimits::new(*FileRights::new().allow(Right::Read));
+        let status = parent
+            .serve(|request| match request.body {
+                Request::Add(a, b) => {
+                    let fd = std::fs::File::open(file.path())?;
+                    Ok(Message::new(Response::Sum(a + b)).fd(fd, ro.clone()))
+                }
+                _ => unreachable!(),
+            })
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_child_error() {
+        let parent = unsafe {
+            privsep::fork::<Request, Response, _>(Sandbox::new(), |_, _| {
+                Err(io::ErrorKind::Other.into())
+            })
+        }
+        .unwrap();
+        let status = parent.serve(|_| unreachable!()).unwrap();
+        assert_eq!(status.code(), Some(1));
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod trace {
+    use std::{
+        fmt,
+        os::fd::AsRawFd,
+        sync::{Arc, Mutex},
+    };
+
+    use capsicum::{CapRights, FileRights, Right};
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+    use tempfile::tempfile;
+    use tracing::{
+        field::{Field, Visit},
+        span,
+        Event,
+        Metadata,
+        Subscriber,
+    };
+
+    use super::*;
+
+    /// A subscriber that records each event's fields, like `a=1 b=2`.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0 += &format!("{}={:?} ", field.name(), value);
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0 += &format!("{}={} ", field.name(), value);
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0.trim_end().to_owned());
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_limit() {
+        let recorder = Recorder::default();
+        let file = tempfile().unwrap();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            FileRights::new()
+                .allow(Right::Read)
+                .allow(Right::Seek)
+                .limit(&file)
+                .unwrap();
+            FileRights::new()
+                .allow(Right::Write)
+                .limit(&file)
+                .unwrap_err();
+        });
+        let fd = file.as_raw_fd();
+        let events = recorder.events();
+        assert_eq!(
+            events[0],
+            format!(
+                "message=limited descriptor fd={fd} fd_type=file kind=rights \
+                 rights=CAP_READ|CAP_SEEK"
+            )
+        );
+        assert!(events[1].starts_with(&format!(
+            "message=failed to limit descriptor fd={fd} fd_type=file kind=rights \
+             rights=CAP_WRITE errno={ENOTCAPABLE} "
+        )));
+    }
+
+    #[test]
+    fn test_enter() {
+        let file = tempfile().unwrap();
+        FileRights::new().allow(Right::Read).limit(&file).unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                let recorder = Recorder::default();
+                tracing::subscriber::with_default(recorder.clone(), || {
+                    capsicum::enter().unwrap();
+                });
+                let events = recorder.events();
+                assert_eq!(events.len(), 1);
+                assert!(events[0].starts_with("message=entered capability mode fds="));
+                let described = format!("{} (file: CAP_READ)", file.as_raw_fd());
+                assert!(events[0].contains(&described), "{}", events[0]);
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+}
+
+mod process {
+    use std::{
+        io::Read,
+        os::{
+            fd::AsRawFd,
+            unix::{net::UnixStream, process::ExitStatusExt},
+        },
+    };
+
+    use capsicum::{
+        process::{Executable, Fork, ProcessDescriptor, SandboxedCommand},
+        CapRights,
+        FdLimits,
+        FileRights,
+        Right,
+    };
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+
+    use super::*;
+
+    fn spawn<F: FnOnce()>(f: F) -> ProcessDescriptor {
+        match unsafe { ProcessDescriptor::fork() }.unwrap() {
+            Fork::Child => {
+                f();
+                unsafe { libc::_exit(0) };
+            }
+            Fork::Parent(pd) => pd,
+        }
+    }
+
+    #[test]
+    fn test_wait() {
+        let pd = spawn(|| unsafe { libc::_exit(42) });
+        assert!(pd.pid().unwrap() > 0);
+        let status = pd.wait().unwrap();
+        assert_eq!(status.code(), Some(42));
+    }
+
+    #[test]
+    fn test_kill() {
+        let pd = spawn(|| loop {
+            unsafe { libc::pause() };
+        });
+        pd.kill(libc::SIGTERM).unwrap();
+        let status = pd.wait().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+    }
+
+    /// libc's fork handlers run in the child, so its allocator is usable
+    /// even if the parent is multithreaded.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_atfork() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        static FORKED: AtomicBool = AtomicBool::new(false);
+        extern "C" fn child() {
+            FORKED.store(true, Ordering::Relaxed);
+        }
+        unsafe { libc::pthread_atfork(None, None, Some(child)) };
+        let pd = spawn(|| {
+            if !FORKED.load(Ordering::Relaxed) {
+                unsafe { libc::_exit(1) };
+            }
+        });
+        assert_eq!(pd.wait().unwrap().code(), Some(0));
+    }
+
+    #[test]
+    fn test_capability_mode() {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                // Children can still be forked and managed by descriptor
+                let pd = spawn(|| loop {
+                    unsafe { libc::pause() };
+                });
+                FileRights::new()
+                    .allow(Right::Pdkill)
+                    .allow(Right::Pdwait)
+                    .limit(&pd)
+                    .unwrap();
+                let e = pd.pid().unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+                pd.kill(libc::SIGKILL).unwrap();
+                assert_eq!(pd.wait().unwrap().signal(), Some(libc::SIGKILL));
+
+                let pd = spawn(|| unsafe { libc::_exit(7) });
+                FileRights::new().allow(Right::Pdwait).limit(&pd).unwrap();
+                let e = pd.kill(libc::SIGKILL).unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+                assert_eq!(pd.wait().unwrap().code(), Some(7));
+
+                let pd = spawn(|| unsafe { libc::_exit(0) });
+                FileRights::new().allow(Right::Pdkill).limit(&pd).unwrap();
+                let e = pd.wait().unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    /// A statically linked program, that can start in capability mode, and
+    /// arguments for it that don't need any access to the file system.
+    #[cfg(target_os = "linux")]
+    const STATIC: (&str, &str) = ("/sbin/ldconfig", "--version");
+    #[cfg(target_os = "freebsd")]
+    const STATIC: (&str, &str) = ("/rescue/echo", "ldconfig");
+
+    /// Arguments for the same program, that do need access to the file system.
+    #[cfg(target_os = "linux")]
+    const STATIC_FS: &str = "-p";
+    #[cfg(target_os = "freebsd")]
+    const STATIC_FS: &str = "/";
+
+    fn output(cmd: SandboxedCommand) -> (String, std::process::ExitStatus) {
+        let (mut reader, writer) = UnixStream::pair().unwrap();
+        let stdout = FdLimits::new(*FileRights::new().allow(Right::Write));
+        let child = cmd.fd(1, writer, stdout).spawn().unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        (output, child.wait().unwrap())
+    }
+
+    #[test]
+    fn test_command() {
+        let (output, status) = output(SandboxedCommand::new(STATIC.0).arg(STATIC.1));
+        assert!(status.success());
+        assert!(output.contains("ldconfig"), "{output}");
+    }
+
+    #[test]
+    fn test_command_capability_mode() {
+        #[cfg(target_os = "freebsd")]
+        let program = "/rescue/ls";
+        #[cfg(target_os = "linux")]
+        let program = STATIC.0;
+        let cmd = SandboxedCommand::new(program).arg(STATIC_FS);
+        let (_, status) = output(cmd);
+        assert!(!status.success());
+
+        let cmd = SandboxedCommand::new(program)
+            .arg(STATIC_FS)
+            .capability_mode(false);
+        let (_, status) = output(cmd);
+        assert!(status.success());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_command_fds() {
+        // Even a descriptor that isn't close-on-exec isn't inherited
+        let file = tempfile::tempfile().unwrap();
+        let fd = file.as_raw_fd();
+        unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+        let script = "for fd in 0 2 3 4 5 6 7 8 9; do [ -e /dev/fd/$fd ] && echo $fd; done; true";
+        let cmd = SandboxedCommand::new("sh")
+            .args(["-c", script])
+            .capability_mode(false);
+        let (output, status) = output(cmd);
+        assert!(status.success());
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_command_inherited_limits() {
+        // The child's descriptor can't have more rights than the parent's
+        let (_reader, writer) = UnixStream::pair().unwrap();
+        FileRights::new().allow(Right::Read).limit(&writer).unwrap();
+        let rw = FdLimits::new(*FileRights::new().allow(Right::Read).allow(Right::Write));
+        let e = SandboxedCommand::new(STATIC.0)
+            .arg(STATIC.1)
+            .fd(1, writer, rw)
+            .spawn()
+            .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(ENOTCAPABLE));
+    }
+
+    #[test]
+    fn test_command_interrupted() {
+        extern "C" fn handle(_: libc::c_int) {}
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                // Signals interrupt the parent while it waits for the child
+                // to execute the program.
+                unsafe {
+                    let mut action = std::mem::zeroed::<libc::sigaction>();
+                    action.sa_sigaction = handle as extern "C" fn(libc::c_int) as usize;
+                    libc::sigaction(libc::SIGALRM, &action, std::ptr::null_mut());
+                    let interval = libc::timeval {
+                        tv_sec: 0,
+                        tv_usec: 100,
+                    };
+                    let timer = libc::itimerval {
+                        it_interval: interval,
+                        it_value: interval,
+                    };
+                    libc::setitimer(libc::ITIMER_REAL, &timer, std::ptr::null_mut());
+                }
+                for _ in 0..100 {
+                    let stdout = tempfile::tempfile().unwrap();
+                    let child = SandboxedCommand::new(STATIC.0)
+                        .arg(STATIC.1)
+                        .fd(1, stdout, FdLimits::default())
+                        .spawn()
+                        .unwrap();
+                    assert!(child.wait().unwrap().success());
+                }
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let e = SandboxedCommand::new("/nonexistent").spawn().unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOENT));
+
+        let e = SandboxedCommand::new(STATIC.0)
+            .fd(1, tempfile::tempfile().unwrap(), FdLimits::default())
+            .fd(1, tempfile::tempfile().unwrap(), FdLimits::default())
+            .spawn()
+            .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+
+        // An error in the child, when it executes the program
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let e = SandboxedCommand::new(file.path()).spawn().unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EACCES));
+    }
+
+    #[test]
+    fn test_executable() {
+        let exe = Executable::open(STATIC.0).unwrap();
+        assert_eq!(exe.interpreter(), None);
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                let cmd = SandboxedCommand::from_executable(exe).arg(STATIC.1);
+                let (output, status) = output(cmd);
+                assert!(status.success());
+                assert!(output.contains("ldconfig"), "{output}");
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_executable_dynamic() {
+        let exe = Executable::open("/bin/true").unwrap();
+        assert!(exe.interpreter().is_some());
+        let e = SandboxedCommand::from_executable(exe).spawn().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+
+        let exe = Executable::open("/bin/true").unwrap();
+        let cmd = SandboxedCommand::from_executable(exe).capability_mode(false);
+        let (_, status) = output(cmd);
+        assert!(status.success());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_executable_script() {
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("script");
+        std::fs::write(&path, "#!/bin/sh -e\necho \"$1\"\n").unwrap();
+        let exe = Executable::open(&path).unwrap();
+        assert_eq!(exe.interpreter(), Some(std::path::Path::new("/bin/sh")));
+        let e = SandboxedCommand::from_executable(exe).spawn().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+
+        let exe = Executable::open(&path).unwrap();
+        let cmd = SandboxedCommand::from_executable(exe)
+            .arg("Hello, World!")
+            .capability_mode(false);
+        let (output, status) = output(cmd);
+        assert!(status.success());
+        assert_eq!(output, "Hello, World!\n");
+    }
+
+    #[test]
+    fn test_executable_malformed() {
+        // A 64-bit ELF header whose program headers would end past the
+        // largest file offset
+        let mut header = [0u8; 64];
+        header[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        header[0x20..0x28].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        header[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        header[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes());
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("malformed");
+        std::fs::write(&path, header).unwrap();
+        let e = Executable::open(&path).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOEXEC));
+    }
+}
+
+mod warm {
+    use std::{ffi::CStr, io::Write, mem, process::Stdio};
+
+    use capsicum::warm;
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, ForkResult},
+    };
+
+    use super::*;
+
+    #[cfg(target_os = "freebsd")]
+    extern "C" {
+        fn gethostbyname(name: *const libc::c_char) -> *mut libc::c_void;
+    }
+
+    /// Run `f` in a forked child, after warming up with `warm` and entering
+    /// capability mode.
+    fn in_sandbox<W, F>(warm: W, f: F)
+    where
+        W: FnOnce() -> std::io::Result<()>,
+        F: FnOnce(),
+    {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                warm().unwrap();
+                capsicum::enter().unwrap();
+                f();
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    /// The local timezone's abbreviation at 2001-09-09 01:46:40 UTC.
+    fn zone() -> String {
+        let now = 1_000_000_000;
+        let mut tm = unsafe { mem::zeroed::<libc::tm>() };
+        assert!(!unsafe { libc::localtime_r(&now, &mut tm) }.is_null());
+        let zone = unsafe { CStr::from_ptr(tm.tm_zone) };
+        zone.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_timezone() {
+        // The zone is only found in the timezone database
+        let tz = || std::env::set_var("TZ", "America/New_York");
+        in_sandbox(
+            || {
+                tz();
+                Ok(())
+            },
+            || assert_ne!(zone(), "EDT"),
+        );
+        in_sandbox(
+            || {
+                tz();
+                warm::warm_timezone()
+            },
+            || assert_eq!(zone(), "EDT"),
+        );
+    }
+
+    #[test]
+    fn test_locale() {
+        // Unlike the C locale, C.UTF-8 is loaded from files
+        let lc_all = || std::env::set_var("LC_ALL", "C.UTF-8");
+        in_sandbox(
+            || {
+                lc_all();
+                Ok(())
+            },
+            || assert!(unsafe { libc::setlocale(libc::LC_ALL, c"".as_ptr()) }.is_null()),
+        );
+        in_sandbox(
+            || {
+                lc_all();
+                warm::warm_locale()
+            },
+            || {
+                let codeset = unsafe { CStr::from_ptr(libc::nl_langinfo(libc::CODESET)) };
+                assert_eq!(codeset.to_bytes(), b"UTF-8");
+            },
+        );
+    }
+
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn test_resolver() {
+        in_sandbox(warm::warm_resolver, || {
+            assert!(!unsafe { gethostbyname(c"localhost".as_ptr()) }.is_null());
+        });
+    }
+
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn test_passwd() {
+        in_sandbox(warm::warm_passwd, || {
+            assert!(!unsafe { libc::getpwuid(0) }.is_null());
+        });
+    }
+
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn test_group() {
+        in_sandbox(warm::warm_group, || {
+            assert!(!unsafe { libc::getgrgid(0) }.is_null());
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_unsupported() {
+        for warm in [warm::warm_resolver, warm::warm_passwd, warm::warm_group] {
+            let e = warm().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn test_devnull() {
+        warm::dev_null().unwrap_err();
+        in_sandbox(warm::warm_devnull, || {
+            let mut null = warm::dev_null().unwrap();
+            null.write_all(b"Hello, World!").unwrap();
+            let _ = Stdio::from(warm::dev_null().unwrap());
+        });
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        ffi::CString,
+        fs,
+        io::{IoSlice, Read, Seek, SeekFrom, Write},
+        mem,
+        net::{TcpListener, UdpSocket},
+        os::{
+            fd::{AsFd, AsRawFd, IntoRawFd},
+            unix::{
+                ffi::OsStrExt,
+                net::{UnixListener, UnixStream},
+            },
+        },
+    };
+
+    use capsicum::{
+        landlock,
+        CapRights,
+        Fcntl,
+        FcntlRights,
+        FdLimits,
+        FileRights,
+        IoctlsBuilder,
+        Mode,
+        Right,
+    };
+    use nix::{
+        errno::Errno,
+        fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+        sys::{
+            signal::kill,
+            socket::{
+                sendmsg,
+                socketpair,
+                AddressFamily,
+                ControlMessage,
+                MsgFlags,
+                SockFlag,
+                SockType,
+                UnixAddr,
+            },
+            wait::{waitpid, WaitStatus},
+        },
+        unistd::{fork, getpid, getppid, gettid, ForkResult, Pid},
+    };
+    use tempfile::{tempdir, tempfile};
+
+    use super::*;
+
+    #[test]
+    fn test_enter() {
+        let tdir = tempdir().unwrap();
+        fs::write(tdir.path().join("foo"), b"Hello, World!").unwrap();
+        let aa = cap_std::ambient_authority();
+        let dir = cap_std::fs::Dir::open_ambient_dir(tdir.path(), aa).unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(!capsicum::get_mode().unwrap());
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+                assert!(capsicum::sandboxed());
+                assert!(capsicum::get_mode().unwrap());
+                assert_eq!(capsicum::mode(), Mode::SeccompFilter);
+
+                // The global file system namespace is off limits
+                let e = fs::File::open(tdir.path().join("foo")).unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+                fs::metadata(tdir.path()).unwrap_err();
+                fs::create_dir(tdir.path().join("bar")).unwrap_err();
+
+                // But the directory opened beforehand is still usable
+                assert_eq!(dir.read_to_string("foo").unwrap(), "Hello, World!");
+                dir.create_dir("bar").unwrap();
+
+                // Sockets may not be bound or connected, nor send to arbitrary
+                // addresses
+                TcpListener::bind("127.0.0.1:0").unwrap_err();
+                UnixListener::bind(tdir.path().join("sock")).unwrap_err();
+                let addr = peer.local_addr().unwrap();
+                udp.send_to(b"Hello, World!", addr).unwrap_err();
+                udp.connect(addr).unwrap_err();
+
+                // Only the process group may be signalled, with pid 0, since
+                // the filter can't tell which pid is the caller's own
+                assert_eq!(kill(getppid(), None), Err(Errno::EPERM));
+                assert_eq!(kill(getpid(), None), Err(Errno::EPERM));
+                kill(Pid::from_raw(0), None).unwrap();
+                let (pid, tid) = (getpid().as_raw(), gettid().as_raw());
+                let r = unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, 0) };
+                assert_eq!(r, -1);
+
+                // Nor may the mount API reopen a directory, nor legacy AIO
+                // take descriptors out of the filter's sight
+                let r = unsafe {
+                    libc::syscall(libc::SYS_open_tree, dir.as_raw_fd(), c".".as_ptr(), 0)
+                };
+                assert_eq!(r, -1);
+                assert_eq!(Errno::last(), Errno::EPERM);
+                let mut ctx: libc::c_ulong = 0;
+                let r = unsafe { libc::syscall(libc::SYS_io_setup, 1, &mut ctx) };
+                assert_eq!(r, -1);
+                assert_eq!(Errno::last(), Errno::EPERM);
+                // The same goes for children forked afterwards
+                match unsafe { fork() }.unwrap() {
+                    ForkResult::Child => {
+                        let parent = kill(getppid(), None);
+                        let group = kill(Pid::from_raw(0), None);
+                        let ok = parent == Err(Errno::EPERM) && group.is_ok();
+                        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+                    }
+                    ForkResult::Parent { child } => {
+                        let cstat = waitpid(child, None).unwrap();
+                        assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+                    }
+                }
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+                assert!(!capsicum::sandboxed());
+                assert!(tdir.path().join("bar").is_dir());
+            }
+        }
+    }
+
+    #[test]
+    fn test_rights() {
+        let mut file = tempfile().unwrap();
+        let mut late = tempfile().unwrap();
+        let rights = *FileRights::new()
+            .allow(Right::Read)
+            .allow(Right::Seek)
+            .allow(Right::Fstat);
+        rights.limit(&file).unwrap();
+        assert_eq!(FileRights::from_file(&file).unwrap(), rights);
+        let (_, pipe) = nix::unistd::pipe().unwrap();
+        FileRights::new().allow(Right::Write).limit(&pipe).unwrap();
+
+        // The limits aren't enforced until entering capability mode
+        file.write_all(b"Hello, World!").unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                let mut s = String::new();
+                file.seek(SeekFrom::Start(0)).unwrap();
+                file.read_to_string(&mut s).unwrap();
+                assert_eq!(s, "Hello, World!");
+                file.metadata().unwrap();
+                let e = file.write_all(b"Hello, World!").unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+
+                // After entering, limits are enforced immediately
+                late.write_all(b"Hello, World!").unwrap();
+                FileRights::new().allow(Right::Read).limit(&late).unwrap();
+                late.write_all(b"Hello, World!").unwrap_err();
+                late.seek(SeekFrom::Start(0)).unwrap_err();
+
+                // Pipes can't be spliced into without both rights, since the
+                // filter can't tell which end a descriptor is
+                let iov = libc::iovec {
+                    iov_base: c"Hello".as_ptr() as *mut _,
+                    iov_len: 5,
+                };
+                let r = unsafe { libc::vmsplice(pipe.as_raw_fd(), &iov, 1, 0) };
+                assert_eq!(r, -1);
+                assert_eq!(Errno::last(), Errno::EPERM);
+
+                // And they can't be expanded
+                let e = FileRights::new()
+                    .allow(Right::Read)
+                    .allow(Right::Write)
+                    .limit(&late)
+                    .unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let tdir = tempdir().unwrap();
+        let aa = cap_std::ambient_authority();
+        let dir = cap_std::fs::Dir::open_ambient_dir(tdir.path(), aa).unwrap();
+        let file = tempfile().unwrap();
+        FileRights::new().allow(Right::Read).limit(&file).unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                // A limited descriptor can't be closed, lest a new descriptor
+                // reuse its number and its limits
+                let fd = file.into_raw_fd();
+                assert_eq!(unsafe { libc::close(fd) }, -1);
+                assert_eq!(Errno::last(), Errno::EPERM);
+                let r = unsafe { libc::syscall(libc::SYS_close_range, 0, u32::MAX, 0) };
+                assert_eq!(r, -1);
+                let mut new = dir.create("foo").unwrap();
+                assert_ne!(new.as_raw_fd(), fd);
+                new.write_all(b"Hello, World!").unwrap();
+                // Nor replaced by another
+                assert_eq!(unsafe { libc::dup3(new.as_raw_fd(), fd, 0) }, -1);
+
+                // The same goes for descriptors limited after entering
+                FileRights::new().allow(Right::Write).limit(&new).unwrap();
+                let fd = new.into_raw_fd();
+                assert_eq!(unsafe { libc::close(fd) }, -1);
+                let new = dir.create("bar").unwrap();
+                assert_ne!(new.as_raw_fd(), fd);
+                drop(new);
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dup() {
+        let file = tempfile().unwrap();
+        FileRights::new().allow(Right::Read).limit(&file).unwrap();
+        let tdir = tempdir().unwrap();
+        fs::write(tdir.path().join("foo"), b"Hello, World!").unwrap();
+        let dir = fs::File::open(tdir.path()).unwrap();
+        FileRights::new()
+            .allow(Right::Lookup)
+            .allow(Right::Read)
+            .limit(&dir)
+            .unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                // A duplicate would escape the limits, so none may be made
+                let fd = file.as_raw_fd();
+                file.try_clone().unwrap_err();
+                assert_eq!(unsafe { libc::dup(fd) }, -1);
+                assert_eq!(unsafe { libc::dup3(fd, 100, 0) }, -1);
+                assert_eq!(fcntl(fd, FcntlArg::F_DUPFD(0)), Err(Errno::EPERM));
+                assert_eq!(fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0)), Err(Errno::EPERM));
+
+                // openat2's flags can't be checked, so it seems not to exist
+                let how = [libc::O_RDWR as u64, 0, 0];
+                let r = unsafe {
+                    libc::syscall(
+                        libc::SYS_openat2,
+                        dir.as_raw_fd(),
+                        c"foo".as_ptr(),
+                        how.as_ptr(),
+                        mem::size_of_val(&how),
+                    )
+                };
+                assert_eq!(r, -1);
+                assert_eq!(Errno::last(), Errno::ENOSYS);
+                // But openat's can
+                let flags = libc::O_RDWR;
+                let r = unsafe { libc::openat(dir.as_raw_fd(), c"foo".as_ptr(), flags) };
+                assert_eq!(r, -1);
+                assert_eq!(Errno::last(), Errno::EPERM);
+                let flags = libc::O_RDONLY;
+                let r = unsafe { libc::openat(dir.as_raw_fd(), c"foo".as_ptr(), flags) };
+                assert!(r >= 0);
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_send() {
+        let file = tempfile().unwrap();
+        FileRights::new().allow(Right::Read).limit(&file).unwrap();
+        let (tx, rx) = UnixStream::pair().unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                // The receiver's copy would escape the limits, and the filter
+                // can't see which descriptors a message carries, so none may
+                // be sent
+                let ro = FdLimits::new(*FileRights::new().allow(Right::Read));
+                let e = capsicum::send_fds(&tx, &[(file.as_fd(), &ro)]).unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+                let r = sendmsg::<UnixAddr>(
+                    tx.as_raw_fd(),
+                    &[IoSlice::new(b"Hello, World!")],
+                    &[ControlMessage::ScmRights(&[file.as_raw_fd()])],
+                    MsgFlags::empty(),
+                    None,
+                );
+                assert_eq!(r, Err(Errno::EPERM));
+
+                // But messages without descriptors may
+                capsicum::send_fds(&tx, &[]).unwrap();
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+                assert!(capsicum::recv_fds(&rx).unwrap().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_recv() {
+        let file = tempfile().unwrap();
+        let ro = FdLimits::new(*FileRights::new().allow(Right::Read));
+        let (tx, rx) = UnixStream::pair().unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                drop(tx);
+                capsicum::enter().unwrap();
+
+                // Received descriptors don't each cost a filter, so any
+                // number of them may be received
+                for _ in 0..100 {
+                    let (fd, limits) = capsicum::recv_fds(&rx).unwrap().pop().unwrap();
+                    assert_eq!(limits, ro);
+                    let e = fs::File::from(fd).write_all(b"Hello, World!").unwrap_err();
+                    assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+                }
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                for _ in 0..100 {
+                    capsicum::send_fds(&tx, &[(file.as_fd(), &ro)]).unwrap();
+                }
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_late_limits() {
+        let files = (0..33).map(|_| tempfile().unwrap()).collect::<Vec<_>>();
+        let ro = *FileRights::new().allow(Right::Read);
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                // Each limit applied after entering installs a filter, up to
+                // a fixed number of them
+                for file in &files[..32] {
+                    ro.limit(file).unwrap();
+                    // Limits that don't change anything don't count
+                    ro.limit(file).unwrap();
+                }
+                let e = ro.limit(&files[32]).unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::ENOMEM));
+                (&files[31]).write_all(b"Hello, World!").unwrap_err();
+                (&files[32]).write_all(b"Hello, World!").unwrap();
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_descriptors() {
+        let file = tempfile().unwrap();
+        let ro = FdLimits::new(*FileRights::new().allow(Right::Read));
+        let wo = FdLimits::new(*FileRights::new().allow(Right::Write));
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                // New descriptors with the same limits share a filter, so
+                // there's no end to them, as long as they're closed
+                for limits in [&ro, &wo].repeat(50) {
+                    let clone = capsicum::try_clone_with_rights(&file, limits).unwrap();
+                    assert_eq!(FdLimits::from_file(&clone).unwrap(), *limits);
+                    let mut clone = fs::File::from(clone);
+                    let e = if limits == &ro {
+                        clone.write_all(b"Hello, World!").unwrap_err()
+                    } else {
+                        clone.read_to_end(&mut Vec::new()).unwrap_err()
+                    };
+                    assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+                }
+
+                // But only so many may be open at once
+                let clones = (0..32)
+                    .map(|_| capsicum::try_clone_with_rights(&file, &ro).unwrap())
+                    .collect::<Vec<_>>();
+                let e = capsicum::try_clone_with_rights(&file, &ro).unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EMFILE));
+                // And they still can't be duplicated
+                assert_eq!(unsafe { libc::dup(clones[0].as_raw_fd()) }, -1);
+                drop(clones);
+                capsicum::try_clone_with_rights(&file, &ro).unwrap();
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fcntls_and_ioctls() {
+        let file = tempfile().unwrap();
+        FcntlRights::new().allow(Fcntl::GetFL).limit(&file).unwrap();
+        let (sock, _peer) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::empty(),
+        )
+        .unwrap();
+        IoctlsBuilder::new()
+            .allow(libc::FIONREAD as libc::c_ulong)
+            .finalize()
+            .limit(&sock)
+            .unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                capsicum::enter().unwrap();
+
+                let fd = file.as_raw_fd();
+                fcntl(fd, FcntlArg::F_GETFL).unwrap();
+                let r = fcntl(fd, FcntlArg::F_SETFL(OFlag::O_APPEND));
+                assert_eq!(r, Err(Errno::EPERM));
+                // Commands that aren't subject to fcntl rights still work
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).unwrap();
+
+                let mut n: libc::c_int = 0;
+                let fd = sock.as_raw_fd();
+                assert_eq!(unsafe { libc::ioctl(fd, libc::FIONREAD, &mut n) }, 0);
+                assert_eq!(unsafe { libc::ioctl(fd, libc::TIOCOUTQ, &mut n) }, -1);
+                assert_eq!(Errno::last(), Errno::EPERM);
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_landlock() {
+        if landlock::abi().is_err() {
+            // Landlock is unavailable on this kernel
+            return;
+        }
+        let tdir = tempdir().unwrap();
+        fs::create_dir(tdir.path().join("ro")).unwrap();
+        fs::create_dir(tdir.path().join("rw")).unwrap();
+        fs::write(tdir.path().join("ro/foo"), b"Hello, World!").unwrap();
+        fs::write(tdir.path().join("secret"), b"Hello, World!").unwrap();
+        let aa = cap_std::ambient_authority();
+        let ro = cap_std::fs::Dir::open_ambient_dir(tdir.path().join("ro"), aa).unwrap();
+        let rw = cap_std::fs::Dir::open_ambient_dir(tdir.path().join("rw"), aa).unwrap();
+        let read = *FileRights::new().allow(Right::Lookup).allow(Right::Read);
+        let write = *read.clone().allow(Right::Write).allow(Right::Create);
+        let ruleset = landlock::Ruleset::new()
+            .unwrap()
+            .allow(&ro, &read)
+            .unwrap()
+            .allow(&rw, &write)
+            .unwrap();
+        assert!(ruleset.unexpressible().is_empty());
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                ruleset.restrict_on_enter().unwrap();
+                capsicum::enter().unwrap();
+                assert_eq!(capsicum::mode(), Mode::Multiple);
+
+                assert_eq!(ro.read_to_string("foo").unwrap(), "Hello, World!");
+                let e = ro.write("bar", b"Hello, World!").unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EACCES));
+                rw.write("bar", b"Hello, World!").unwrap();
+                rw.create_dir("baz").unwrap_err();
+
+                // Absolute paths may not escape the ruleset's directories
+                let path = CString::new(tdir.path().join("secret").as_os_str().as_bytes()).unwrap();
+                let fd = unsafe { libc::openat(rw.as_raw_fd(), path.as_ptr(), libc::O_RDONLY) };
+                assert_eq!(fd, -1);
+                assert_eq!(Errno::last(), Errno::EACCES);
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+                assert!(tdir.path().join("rw/bar").exists());
+            }
+        }
+    }
+
+    #[test]
+    fn test_landlock_threads() {
+        let Ok(ruleset) = landlock::Ruleset::new() else {
+            // Landlock is unavailable on this kernel
+            return;
+        };
+        let tdir = tempdir().unwrap();
+        let aa = cap_std::ambient_authority();
+        let dir = cap_std::fs::Dir::open_ambient_dir(tdir.path(), aa).unwrap();
+        let read = *FileRights::new().allow(Right::Lookup).allow(Right::Read);
+        let ruleset = ruleset.allow(&dir, &read).unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                always_abort();
+                ruleset.restrict_on_enter().unwrap();
+
+                // Landlock would only restrict this thread
+                let (tx, rx) = std::sync::mpsc::channel::<()>();
+                let thread = std::thread::spawn(move || rx.recv());
+                let e = capsicum::enter().unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EBUSY));
+                assert!(!capsicum::sandboxed());
+
+                // The thread is only counted out once the kernel has reaped it.
+                drop(tx);
+                thread.join().unwrap().unwrap_err();
+                let mut r = capsicum::enter();
+                for _ in 0..100 {
+                    if !matches!(&r, Err(e) if e.raw_os_error() == Some(libc::EBUSY)) {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    r = capsicum::enter();
+                }
+                r.unwrap();
+                assert_eq!(capsicum::mode(), Mode::Multiple);
+
+                // Too late to count threads
+                let ruleset = landlock::Ruleset::new().unwrap();
+                let e = ruleset.restrict_on_enter().unwrap_err();
+                assert_eq!(e.raw_os_error(), Some(libc::EPERM));
+
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => {
+                let cstat = waitpid(child, None).unwrap();
+                assert!(matches!(cstat, WaitStatus::Exited(_, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_landlock_unexpressible() {
+        let Ok(ruleset) = landlock::Ruleset::new() else {
+            // Landlock is unavailable on this kernel
+            return;
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::File::open(dir.path()).unwrap();
+        let rights = *FileRights::new()
+            .allow(Right::Lookup)
+            .allow(Right::Read)
+            .allow(Right::Seek)
+            .allow(Right::Fstat)
+            .allow(Right::MacSet)
+            .allow(Right::Fchflags);
+        let ruleset = ruleset.allow(&dir, &rights).unwrap();
+        let expected = *FileRights::new()
+            .allow(Right::MacSet)
+            .allow(Right::Fchflags);
+        assert_eq!(ruleset.unexpressible(), &[(dir.as_raw_fd(), expected)]);
+    }
+}